@@ -0,0 +1,290 @@
+use crate::types::Mapping;
+use crate::util::compute_delta;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local, SecondsFormat};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use tracing::debug;
+
+/// A command that was received via the admin socket.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    /// Execute the named mapping immediately regardless of its schedule.
+    Run(String),
+    /// Report the state of all mappings or of the named one.
+    Status(Option<String>),
+    /// Stop executing the named mapping until it is resumed.
+    Pause(String),
+    /// Execute the named mapping on schedule again.
+    Resume(String),
+    /// Re-read the configuration file and apply the mappings, the
+    /// Prometheus and Icinga sections and the backends.
+    Reload,
+}
+
+impl Command {
+    /// Parse a single line of the admin protocol, i.e. the name of the
+    /// command followed by its argument.
+    fn parse(line: &str) -> Result<Self, anyhow::Error> {
+        let line = line.trim();
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match name {
+            "run" if argument.is_empty() => bail!("usage: run <mapping>"),
+            "run" => Ok(Command::Run(argument.to_string())),
+            "status" if argument.is_empty() => Ok(Command::Status(None)),
+            "status" => Ok(Command::Status(Some(argument.to_string()))),
+            "pause" if argument.is_empty() => bail!("usage: pause <mapping>"),
+            "pause" => Ok(Command::Pause(argument.to_string())),
+            "resume" if argument.is_empty() => bail!("usage: resume <mapping>"),
+            "resume" => Ok(Command::Resume(argument.to_string())),
+            "reload" if !argument.is_empty() => bail!("usage: reload"),
+            "reload" => Ok(Command::Reload),
+            "" => bail!("empty command"),
+            _ => bail!("unknown command '{}'", name),
+        }
+    }
+}
+
+/// A command together with the channel that the response is sent back to
+/// the client on.
+pub(crate) struct Request {
+    pub command: Command,
+    reply: Sender<Value>,
+}
+
+impl Request {
+    pub(crate) fn reply(self, response: Value) {
+        // The client may have hung up in the meantime.
+        let _ = self.reply.send(response);
+    }
+}
+
+/// A Unix socket that accepts commands from operators, one per line, and
+/// answers each with a JSON object on a single line. The commands are
+/// passed on to the main loop which executes them between the checks.
+pub(crate) struct AdminSocket {
+    receiver: Receiver<Request>,
+}
+
+impl AdminSocket {
+    /// Bind to the socket at the given path. A socket that was left behind
+    /// by a previous instance is replaced, one that is still in use is not.
+    pub(crate) fn bind(path: &Path) -> Result<Self, anyhow::Error> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("admin socket {:?} is in use by another process", path);
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove stale admin socket {:?}", path))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to bind admin socket {:?}", path))?;
+        // Only the user that vec2checkd runs as may send commands.
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to set permissions of admin socket {:?}", path))?;
+
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle(stream, sender) {
+                        debug!("Failed to handle admin connection: {:#}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(AdminSocket { receiver })
+    }
+
+    /// Wait up to `timeout` for a request. This replaces the sleep of the
+    /// main loop, so that commands are executed without delay.
+    pub(crate) fn wait(&self, timeout: Duration) -> Option<Request> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Return the requests that are waiting to be executed.
+    pub(crate) fn pending(&self) -> Vec<Request> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// Build the response to a `run` command from the outcome of the check.
+/// A check that did not submit a result reports the reason instead of the
+/// state, which would be the one of an earlier execution.
+pub(crate) fn run_response(mapping: &Mapping, duration: Duration, failure: Option<&str>) -> Value {
+    let skipped = failure.is_none() && mapping.state.skipped.is_some();
+    let mut response = json!({
+        "mapping": mapping.name,
+        "success": failure.is_none(),
+        "skipped": skipped,
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+    });
+    match (failure, mapping.state.skipped) {
+        (Some(err), _) => response["error"] = json!(err),
+        (None, Some(reason)) => response["reason"] = json!(reason),
+        (None, None) => {
+            if let Some(exit_value) = mapping.state.last_exit_value {
+                response["state"] = json!(crate::icinga::real_exit_value_to_status(
+                    mapping.service.is_some(),
+                    &exit_value
+                ));
+            }
+        }
+    }
+    response
+}
+
+/// Build the response to a `status` command, i.e. the outcome of the most
+/// recent execution and the time of the next one of each mapping.
+pub(crate) fn status_response<'a>(
+    mappings: impl Iterator<Item = &'a Mapping>,
+    paused: &HashSet<String>,
+) -> Value {
+    let now = Local::now();
+    let mappings: Vec<Value> = mappings
+        .map(|mapping| {
+            let next_execution = now
+                + chrono::Duration::from_std(compute_delta(mapping))
+                    .unwrap_or_else(|_| chrono::Duration::zero());
+            json!({
+                "name": mapping.name,
+                "host": mapping.host,
+                "service": mapping.service,
+                "interval": mapping.interval.as_secs(),
+                "paused": paused.contains(&mapping.name),
+                "last_execution": mapping.state.last_execution.map(as_rfc3339),
+                "last_exit_value": mapping.state.last_exit_value,
+                "last_state": mapping.state.last_exit_value.map(|exit_value| {
+                    crate::icinga::real_exit_value_to_status(mapping.service.is_some(), &exit_value)
+                }),
+                "last_error": mapping.state.last_error,
+                "next_execution": as_rfc3339(next_execution),
+            })
+        })
+        .collect();
+    json!({ "mappings": mappings })
+}
+
+fn as_rfc3339(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Pass each command of a connection on to the main loop and write back
+/// the response once the command was executed.
+fn handle(stream: UnixStream, sender: Sender<Request>) -> Result<(), anyhow::Error> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        let response = match Command::parse(&line) {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                sender.send(Request { command, reply })?;
+                response.recv()?
+            }
+            Err(err) => json!({ "error": format!("{:#}", err) }),
+        };
+
+        writeln!(writer, "{}", response)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heartbeat::Heartbeat;
+    use crate::types::HeartbeatConfig;
+
+    fn host_mapping() -> Mapping {
+        Heartbeat::new(HeartbeatConfig {
+            host: "foo".to_string(),
+            service: None,
+            interval: Duration::from_secs(60),
+        })
+        .mapping()
+        .clone()
+    }
+
+    #[test]
+    fn test_run_response() {
+        let mut mapping = host_mapping();
+        mapping.state.last_exit_value = Some(1);
+        let response = run_response(&mapping, Duration::from_millis(5), None);
+        assert_eq!(response["success"], true);
+        assert_eq!(response["state"], "DOWN");
+
+        let response = run_response(&mapping, Duration::from_millis(5), Some("timeout"));
+        assert_eq!(response["success"], false);
+        assert_eq!(response["error"], "timeout");
+        assert!(response.get("state").is_none());
+    }
+
+    #[test]
+    fn test_status_response() {
+        let mut mapping = host_mapping();
+        let response = status_response(std::iter::once(&mapping), &HashSet::new());
+        assert_eq!(response["mappings"][0]["last_state"], Value::Null);
+
+        mapping.state.last_exit_value = Some(1);
+        let paused = HashSet::from([mapping.name.clone()]);
+        let response = status_response(std::iter::once(&mapping), &paused);
+        assert_eq!(response["mappings"][0]["last_exit_value"], 1);
+        assert_eq!(response["mappings"][0]["last_state"], "DOWN");
+        assert_eq!(response["mappings"][0]["paused"], true);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Command::parse("run Node status\n").unwrap(),
+            Command::Run("Node status".to_string())
+        );
+        assert_eq!(
+            Command::parse("run  foo ").unwrap(),
+            Command::Run("foo".to_string())
+        );
+        assert_eq!(
+            Command::parse("run").unwrap_err().to_string(),
+            "usage: run <mapping>"
+        );
+        assert_eq!(
+            Command::parse("stop foo").unwrap_err().to_string(),
+            "unknown command 'stop'"
+        );
+        assert_eq!(Command::parse("status").unwrap(), Command::Status(None));
+        assert_eq!(
+            Command::parse("status foo").unwrap(),
+            Command::Status(Some("foo".to_string()))
+        );
+        assert_eq!(
+            Command::parse("pause foo").unwrap(),
+            Command::Pause("foo".to_string())
+        );
+        assert_eq!(
+            Command::parse("resume foo").unwrap(),
+            Command::Resume("foo".to_string())
+        );
+        assert_eq!(
+            Command::parse("resume").unwrap_err().to_string(),
+            "usage: resume <mapping>"
+        );
+        assert_eq!(Command::parse("reload").unwrap(), Command::Reload);
+        assert!(Command::parse("reload now").is_err());
+        assert!(Command::parse("  ").is_err());
+    }
+}
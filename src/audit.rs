@@ -0,0 +1,127 @@
+use crate::types::AuditLogConfig;
+use anyhow::Context;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// An append-only file with one JSON object per line that is rotated when
+/// it would exceed its maximum size. Rotated files are suffixed with a
+/// number, the oldest file is removed once there are `max_files` of them.
+#[derive(Debug)]
+struct RotatingFile {
+    config: AuditLogConfig,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(config: AuditLogConfig) -> Result<Self, anyhow::Error> {
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { config, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), anyhow::Error> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.config.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)
+            .with_context(|| format!("failed to write to audit log {:?}", self.config.path))?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift the rotated files by one, i.e. "audit.log.1" becomes
+    /// "audit.log.2", and start over with an empty file.
+    fn rotate(&mut self) -> Result<(), anyhow::Error> {
+        let path = &self.config.path;
+        for index in (1..self.config.max_files).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(path, index + 1))
+                    .with_context(|| format!("failed to rotate audit log {:?}", from))?;
+            }
+        }
+        if self.config.max_files > 0 {
+            std::fs::rename(path, rotated_path(path, 1))
+                .with_context(|| format!("failed to rotate audit log {:?}", path))?;
+        } else {
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove audit log {:?}", path))?;
+        }
+        self.file = open_append(path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, anyhow::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open audit log {:?}", path))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Records every payload that is sent to the Icinga API together with the
+/// outcome of the request, so that the state history of an Icinga object
+/// can be traced back to the check results. The log can be cloned and
+/// shared across tokio tasks.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditLog {
+    file: Arc<Mutex<RotatingFile>>,
+}
+
+impl AuditLog {
+    pub(crate) fn open(config: AuditLogConfig) -> Result<Self, anyhow::Error> {
+        Ok(AuditLog {
+            file: Arc::new(Mutex::new(RotatingFile::open(config)?)),
+        })
+    }
+
+    /// Append a record to the audit log.
+    pub(crate) fn record<T: Serialize>(&self, record: &T) -> Result<(), anyhow::Error> {
+        let line = serde_json::to_string(record)?;
+        self.file.lock().unwrap().write_line(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("vec2checkd-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+
+        let log = AuditLog::open(AuditLogConfig {
+            path: path.clone(),
+            max_size: 20,
+            max_files: 2,
+        })
+        .unwrap();
+
+        for i in 0..4 {
+            log.record(&serde_json::json!({ "record": i })).unwrap();
+        }
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "{\"record\":3}\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "{\"record\":2}\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "{\"record\":1}\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+use crate::icinga::{self, IcingaClient};
+use crate::types::{BackendConfig, CheckResult, Mapping, SensuConfig};
+use anyhow::Context;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::debug;
+use url::Url;
+
+/// A destination for check results. Every mapping submits its check
+/// result to each of the configured backends.
+#[derive(Clone)]
+pub(crate) enum Backend {
+    Icinga(IcingaClient),
+    CommandFile(PathBuf),
+    CheckResultSpool(PathBuf),
+    Sensu(SensuClient),
+    Stdout,
+}
+
+/// A client to the events API of a Sensu Go agent.
+#[derive(Clone)]
+pub(crate) struct SensuClient {
+    client: reqwest::Client,
+    url: Url,
+    handlers: Vec<String>,
+}
+
+impl SensuClient {
+    pub(crate) fn new(config: &SensuConfig) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder().build()?;
+        Ok(SensuClient {
+            client,
+            url: config.url.clone(),
+            handlers: config.handlers.clone(),
+        })
+    }
+
+    /// Send the check result as an event to the Sensu agent; ref:
+    /// https://docs.sensu.io/sensu-go/latest/observability-pipeline/observe-schedule/agent/#events-post-specification
+    async fn send(&self, mapping: &Mapping, result: &CheckResult) -> Result<(), anyhow::Error> {
+        let event = build_sensu_event(mapping, result, &self.handlers);
+
+        debug!(
+            "'{}': Send event to Sensu agent at {}: {}",
+            mapping.name, self.url, event
+        );
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .json(&event)
+            .timeout(crate::util::compute_delta(mapping))
+            .send()
+            .await?;
+
+        debug!(
+            "'{}': Process Sensu agent response: {:?}",
+            mapping.name, response
+        );
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Backend {
+    /// Return a short name of the backend that is used in logs.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Backend::Icinga(_) => "Icinga API",
+            Backend::CommandFile(_) => "external command file",
+            Backend::CheckResultSpool(_) => "checkresult spool directory",
+            Backend::Sensu(_) => "Sensu agent",
+            Backend::Stdout => "stdout",
+        }
+    }
+
+    /// Submit a check result on behalf of a mapping.
+    pub(crate) async fn send(
+        &self,
+        mapping: &Mapping,
+        result: &CheckResult,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Backend::Icinga(client) => {
+                // Build the JSON payload to be sent to the Icinga API.
+                // Note that the exit value is the "real" one as the API
+                // returns HTTP 400 Bad Request when host states (= exit values)
+                // other than 0 or 1 are sent.
+                let payload = icinga::build_payload(
+                    mapping,
+                    result.exit_value,
+                    result.plugin_output.clone(),
+                    result.performance_data.clone(),
+                    result.execution_start,
+                    result.execution_end,
+                )?;
+                client.send(mapping, payload).await
+            }
+            Backend::CommandFile(path) => {
+                let command = format_external_command(mapping, result);
+                debug!(
+                    "'{}': Write external command to {:?}: {}",
+                    mapping.name,
+                    path,
+                    command.trim_end()
+                );
+                // The command file is usually a named pipe, so it must not
+                // be created or truncated.
+                OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut f| f.write_all(command.as_bytes()))
+                    .with_context(|| format!("failed to write to command file {:?}", path))
+            }
+            Backend::CheckResultSpool(dir) => write_checkresult_file(dir, mapping, result),
+            Backend::Sensu(client) => client.send(mapping, result).await,
+            Backend::Stdout => {
+                let line = serde_json::to_string(&StdoutRecord::from(mapping, result))?;
+                let mut stdout = std::io::stdout().lock();
+                writeln!(stdout, "{}", line)
+                    .and_then(|_| stdout.flush())
+                    .with_context(|| "failed to write check result to stdout")
+            }
+        }
+    }
+}
+
+/// A check result as it is printed to stdout, one JSON object per line.
+#[derive(Serialize)]
+struct StdoutRecord<'a> {
+    mapping: &'a str,
+    host: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: &'a Option<String>,
+    #[serde(flatten)]
+    result: &'a CheckResult,
+}
+
+impl<'a> StdoutRecord<'a> {
+    fn from(mapping: &'a Mapping, result: &'a CheckResult) -> Self {
+        StdoutRecord {
+            mapping: &mapping.name,
+            host: &mapping.host,
+            service: &mapping.service,
+            result,
+        }
+    }
+}
+
+/// Build the set of backends from the configuration. The Icinga API client
+/// must be passed when an Icinga backend is configured.
+pub(crate) fn build(
+    configs: &[BackendConfig],
+    icinga_client: Option<&IcingaClient>,
+) -> Result<Vec<Backend>, anyhow::Error> {
+    configs
+        .iter()
+        .map(|config| match config {
+            BackendConfig::Icinga => Ok(Backend::Icinga(
+                icinga_client
+                    .expect("Icinga API client must be initialized when the Icinga backend is used")
+                    .clone(),
+            )),
+            BackendConfig::CommandFile(path) => Ok(Backend::CommandFile(path.clone())),
+            BackendConfig::CheckResultSpool(dir) => Ok(Backend::CheckResultSpool(dir.clone())),
+            BackendConfig::Sensu(sensu) => SensuClient::new(sensu)
+                .map(Backend::Sensu)
+                .with_context(|| "failed to initialize Sensu agent API client"),
+            BackendConfig::Stdout => Ok(Backend::Stdout),
+        })
+        .collect()
+}
+
+/// Build a Sensu event from a check result. The Icinga host is used as the
+/// proxy entity and the Icinga service (or the mapping name when the mapping
+/// updates a host object) as the name of the check. Exit values and output
+/// are passed as-is, performance data are parsed by Sensu as metrics.
+fn build_sensu_event(
+    mapping: &Mapping,
+    result: &CheckResult,
+    handlers: &[String],
+) -> serde_json::Value {
+    let mut output = result.plugin_output.clone();
+    if let Some(perf_data) = &result.performance_data {
+        if !perf_data.is_empty() {
+            output.push_str(" | ");
+            output.push_str(&perf_data.join(" "));
+        }
+    }
+
+    let mut check = serde_json::json!({
+        "metadata": {
+            "name": mapping.service.as_ref().unwrap_or(&mapping.name),
+        },
+        "proxy_entity_name": mapping.host,
+        "status": result.exit_value,
+        "output": output,
+        "interval": mapping.interval.as_secs(),
+        "ttl": mapping.interval.as_secs() + 10,
+        "executed": result.execution_start as u64,
+        "handlers": handlers,
+    });
+
+    if result.performance_data.is_some() {
+        check["output_metric_format"] = serde_json::json!("nagios_perfdata");
+    }
+
+    serde_json::json!({ "check": check })
+}
+
+/// Join plugin output and performance data the way a plugin would print
+/// them and escape newlines, as both the external command file and the
+/// checkresult files expect the output on a single line.
+fn format_output(result: &CheckResult) -> String {
+    let mut output = result.plugin_output.clone();
+    if let Some(perf_data) = &result.performance_data {
+        if !perf_data.is_empty() {
+            output.push('|');
+            output.push_str(&perf_data.join(" "));
+        }
+    }
+    output.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Format a check result as an external command; ref:
+/// https://assets.nagios.com/downloads/nagioscore/docs/externalcmds/
+fn format_external_command(mapping: &Mapping, result: &CheckResult) -> String {
+    match &mapping.service {
+        Some(service) => format!(
+            "[{}] PROCESS_SERVICE_CHECK_RESULT;{};{};{};{}\n",
+            result.execution_end as u64,
+            mapping.host,
+            service,
+            result.exit_value,
+            format_output(result)
+        ),
+        None => format!(
+            "[{}] PROCESS_HOST_CHECK_RESULT;{};{};{}\n",
+            result.execution_end as u64,
+            mapping.host,
+            result.exit_value,
+            format_output(result)
+        ),
+    }
+}
+
+/// Format a check result as the content of a file in the checkresult
+/// spool directory that is regularly reaped by Nagios.
+fn format_checkresult_file(mapping: &Mapping, result: &CheckResult) -> String {
+    let mut content = format!(
+        "### Passive Check Result File ###\nfile_time={}\n\n",
+        result.execution_end as u64
+    );
+    content.push_str(&format!("host_name={}\n", mapping.host));
+    if let Some(service) = &mapping.service {
+        content.push_str(&format!("service_description={}\n", service));
+    }
+    content.push_str(&format!(
+        "check_type=1\ncheck_options=0\nscheduled_check=0\nreschedule_check=0\nlatency=0.0\nstart_time={:.3}\nfinish_time={:.3}\nearly_timeout=0\nexited_ok=1\nreturn_code={}\noutput={}\n",
+        result.execution_start,
+        result.execution_end,
+        result.exit_value,
+        format_output(result)
+    ));
+    content
+}
+
+/// Write a checkresult file and the accompanying ".ok" file that signals
+/// Nagios that the file is complete.
+fn write_checkresult_file(
+    dir: &Path,
+    mapping: &Mapping,
+    result: &CheckResult,
+) -> Result<(), anyhow::Error> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let file_name = format!(
+        "c{}{}{}",
+        result.execution_end as u64,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = dir.join(&file_name);
+
+    debug!(
+        "'{}': Write check result to spool file {:?}",
+        mapping.name, path
+    );
+
+    std::fs::write(&path, format_checkresult_file(mapping, result))
+        .with_context(|| format!("failed to write checkresult file {:?}", path))?;
+    File::create(dir.join(format!("{}.ok", file_name)))
+        .with_context(|| format!("failed to create checkresult file {:?}.ok", path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        DefaultOutput, MappingState, NoData, NonFinitePolicy, PerformanceData, ResultCount,
+        TemplateEngine, ThresholdPair, ThresholdTemplates,
+    };
+    use std::time::{Duration, Instant};
+
+    fn seed_mapping() -> Mapping {
+        Mapping {
+            name: "foobar".to_string(),
+            query: "up".to_string(),
+            thresholds: ThresholdPair::default(),
+            host: "foo".to_string(),
+            service: Some("bar".to_string()),
+            interval: Duration::from_secs(60),
+            last_apply: Instant::now(),
+            plugin_output: None,
+            performance_data: PerformanceData::default(),
+            sync_vars: false,
+            request_timeout: None,
+            basic_auth: None,
+            state: MappingState::default(),
+            threshold_rules: vec![],
+            baseline_query: None,
+            no_data: NoData::default(),
+            non_finite: NonFinitePolicy::default(),
+            result_count: ResultCount::default(),
+            aggregate: None,
+            transform: None,
+            breach_cycles: 1,
+            flapping: None,
+            state_map: [0, 1, 2, 3],
+            maintenance: vec![],
+            label_states: None,
+            state_from_label: None,
+            include: vec![],
+            exclude: vec![],
+            deduplicate: None,
+            derive: None,
+            default_output: DefaultOutput::default(),
+            expect: None,
+            conditions: None,
+            threshold_templates: ThresholdTemplates::default(),
+            grace_period: None,
+            escalate_after: None,
+            count_breaching: None,
+            group_by: vec![],
+            ignore_unchanged: false,
+            clamp_min: None,
+            clamp_max: None,
+            precision: None,
+            query_error_state: None,
+            partials: Default::default(),
+            plugin_output_strict: true,
+            template_engine: TemplateEngine::default(),
+            max_output_length: None,
+        }
+    }
+
+    fn seed_result() -> CheckResult {
+        CheckResult {
+            exit_value: 2,
+            plugin_output: "[CRITICAL] first line\nsecond line".to_string(),
+            performance_data: Some(vec![
+                "'foobar/eaa8c4'=5;;;;".to_string(),
+                "'foobar/6c72e2'=15;;;;".to_string(),
+            ]),
+            execution_start: 1640995200.25,
+            execution_end: 1640995201.5,
+        }
+    }
+
+    #[test]
+    fn test_format_external_command_for_service_objects() {
+        assert_eq!(
+            format_external_command(&seed_mapping(), &seed_result()),
+            "[1640995201] PROCESS_SERVICE_CHECK_RESULT;foo;bar;2;[CRITICAL] first line\\nsecond line|'foobar/eaa8c4'=5;;;; 'foobar/6c72e2'=15;;;;\n"
+        );
+    }
+
+    #[test]
+    fn test_format_external_command_for_host_objects() {
+        let mut mapping = seed_mapping();
+        mapping.service = None;
+        let mut result = seed_result();
+        result.exit_value = 1;
+        result.performance_data = None;
+        assert_eq!(
+            format_external_command(&mapping, &result),
+            "[1640995201] PROCESS_HOST_CHECK_RESULT;foo;1;[CRITICAL] first line\\nsecond line\n"
+        );
+    }
+
+    #[test]
+    fn test_format_checkresult_file() {
+        let content = format_checkresult_file(&seed_mapping(), &seed_result());
+        assert!(content.contains("host_name=foo\nservice_description=bar\n"));
+        assert!(content.contains("start_time=1640995200.250\nfinish_time=1640995201.500\n"));
+        assert!(content.contains("return_code=2\n"));
+        assert!(content.ends_with(
+            "output=[CRITICAL] first line\\nsecond line|'foobar/eaa8c4'=5;;;; 'foobar/6c72e2'=15;;;;\n"
+        ));
+    }
+
+    #[test]
+    fn test_build_sensu_event() {
+        let handlers = vec!["slack".to_string()];
+        assert_eq!(
+            build_sensu_event(&seed_mapping(), &seed_result(), &handlers),
+            serde_json::json!({
+                "check": {
+                    "metadata": {
+                        "name": "bar"
+                    },
+                    "proxy_entity_name": "foo",
+                    "status": 2,
+                    "output": "[CRITICAL] first line\nsecond line | 'foobar/eaa8c4'=5;;;; 'foobar/6c72e2'=15;;;;",
+                    "output_metric_format": "nagios_perfdata",
+                    "interval": 60,
+                    "ttl": 70,
+                    "executed": 1640995200,
+                    "handlers": ["slack"]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_format_stdout_record() {
+        let mapping = seed_mapping();
+        let result = seed_result();
+        assert_eq!(
+            serde_json::to_value(StdoutRecord::from(&mapping, &result)).unwrap(),
+            serde_json::json!({
+                "mapping": "foobar",
+                "host": "foo",
+                "service": "bar",
+                "exit_value": 2,
+                "plugin_output": "[CRITICAL] first line\nsecond line",
+                "performance_data": ["'foobar/eaa8c4'=5;;;;", "'foobar/6c72e2'=15;;;;"],
+                "execution_start": 1640995200.25,
+                "execution_end": 1640995201.5
+            })
+        );
+    }
+}
@@ -1,11 +1,17 @@
 use crate::error::*;
+use crate::logging::LogLevel;
 use crate::types::*;
-use anyhow::{anyhow, bail};
-use log::debug;
+use crate::util::get_start_time;
+use anyhow::{anyhow, bail, Context};
+use chrono::{NaiveTime, Weekday};
 use nagios_range::NagiosRange;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tracing::debug;
 use url::Url;
 use yaml_rust::yaml::{Hash, Yaml};
 
@@ -15,15 +21,92 @@ use yaml_rust::yaml::{Hash, Yaml};
 /// ```yaml
 /// '<name>':
 ///   query: '<promql_query>'
+///   baseline_query: '<promql_query>'   # optional
+///   include:                           # optional
+///     - '<label_matcher>'
+///   exclude:                           # optional
+///     - '<label_matcher>'
 ///   host: '<host_object>'
 ///   service: '<host_object>'           # optional
 ///   interval: <check_interval>
 ///   thresholds:                        # optional
 ///     warning: '<nagios_range>'        # optional
 ///     critical: '<nagios_range>'       # optional
+///     unknown: '<nagios_range>'        # optional
+///     ok: '<range>'                    # optional
+///     ok_violation_state: '<state>'    # optional
+///   expect: <num>|[<num>, ...]         # optional
+///   expect_state: '<state>'            # optional
+///   conditions:                        # optional
+///     operator: 'and'|'or'             # optional
+///     queries:
+///       - '<condition>'
+///   threshold_rules:                   # optional
+///     - matchers:
+///         - '<label_matcher>'
+///       warning: '<nagios_range>'      # optional
+///       critical: '<nagios_range>'     # optional
 ///   plugin_output: '<custom_template>' # optional
+///   plugin_output_strict: <bool>       # optional
+///   template_engine: '<engine>'        # optional
+///   max_output_length: <bytes>         # optional
+///   default_output:                    # optional
+///     top: <count>                     # optional
+///     long: <bool>                     # optional
+///     labels:                          # optional
+///       - '<label_name>'
+///   no_data:                           # optional
+///     state: '<state>'                 # optional
+///     plugin_output: '<template>'      # optional
+///   non_finite: 'unknown'|'drop'|<num> # optional
+///   min_results: <count>               # optional
+///   max_results: <count>               # optional
+///   result_count_state: '<state>'      # optional
+///   query_error_state: '<state>'       # optional
+///   deduplicate: 'max'|'min'|'last'    # optional
+///   ignore_unchanged: <bool>           # optional
+///   derive: 'delta'|'rate'             # optional
+///   aggregate: '<operator>'|'p<N>'     # optional
+///   count_breaching: '<nagios_range>'  # optional
+///   group_by:                          # optional
+///     - '<label_name>'
+///   for: <cycles>                      # optional
+///   escalate_after: <cycles>           # optional
+///   flapping:                          # optional
+///     window: <executions>             # optional
+///     max_changes: <count>             # optional
+///   maintenance:                       # optional
+///     - '<maintenance_window>'
+///   state_map:                         # optional
+///     '<state>': '<state>'
+///   label_states:                      # optional
+///     label: '<label_name>'
+///     states:
+///       '<label_value>': '<state>'
+///     default: '<state>'               # optional
+///   state_from_label:                  # optional
+///     label: '<label_name>'
+///     states:                          # optional
+///       '<label_value>': '<state>'
+///   transform:                         # optional
+///     from: '<unit>'                   # optional
+///     to: '<unit>'                     # optional
+///     scale: <factor>                  # optional
+///     offset: <summand>                # optional
+///   clamp_min: <number>                # optional
+///   clamp_max: <number>                # optional
+///   precision: <decimal_places>        # optional
+///   sync_vars: <bool>                  # optional
+///   request_timeout: <seconds>         # optional
+///   authentication:                    # optional
+///     username: '<api_user>'
+///     password: '<password>'
 /// ```
-fn parse_mapping(mapping: (&Yaml, &Yaml)) -> Result<Mapping, anyhow::Error> {
+fn parse_mapping(
+    mapping: (&Yaml, &Yaml),
+    global_maintenance: &[MaintenanceWindow],
+    global_performance_data: &PerformanceData,
+) -> Result<Mapping, anyhow::Error> {
     let name = mapping
         .0
         .as_str()
@@ -74,6 +157,674 @@ fn parse_mapping(mapping: (&Yaml, &Yaml)) -> Result<Mapping, anyhow::Error> {
         None => None,
     };
 
+    let baseline_query = match items.get(&Yaml::from_str("baseline_query")) {
+        Some(q) => Some(
+            q.as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("mappings.{}.baseline_query", name),
+                    kind: "string",
+                })?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let no_data = match items.get(&Yaml::from_str("no_data")) {
+        Some(n) => {
+            let n_hash = n.as_hash().ok_or(ParseFieldError {
+                field: format!("mappings.{}.no_data", name),
+                kind: "hash",
+            })?;
+
+            let exit_value = match n_hash.get(&Yaml::from_str("state")) {
+                Some(s) => {
+                    let field = format!("mappings.{}.no_data.state", name);
+                    let raw = s.as_str().ok_or(ParseFieldError {
+                        field: field.clone(),
+                        kind: "string",
+                    })?;
+                    parse_state(raw).with_context(|| format!("failed to parse {}", field))?
+                }
+                None => NoData::default().exit_value,
+            };
+
+            let plugin_output = match n_hash.get(&Yaml::from_str("plugin_output")) {
+                Some(p) => Some(
+                    p.as_str()
+                        .ok_or(ParseFieldError {
+                            field: format!("mappings.{}.no_data.plugin_output", name),
+                            kind: "string",
+                        })?
+                        .to_string(),
+                ),
+                None => None,
+            };
+
+            NoData {
+                exit_value,
+                plugin_output,
+            }
+        }
+        None => NoData::default(),
+    };
+
+    let non_finite = match items.get(&Yaml::from_str("non_finite")) {
+        Some(Yaml::String(s)) if s == "unknown" => NonFinitePolicy::Unknown,
+        Some(Yaml::String(s)) if s == "drop" => NonFinitePolicy::Drop,
+        Some(Yaml::Real(r)) => {
+            NonFinitePolicy::Substitute(r.parse::<f64>().map_err(|_| ParseFieldError {
+                field: format!("mappings.{}.non_finite", name),
+                kind: "number",
+            })?)
+        }
+        Some(Yaml::Integer(i)) => NonFinitePolicy::Substitute(*i as f64),
+        Some(_) => {
+            return Err(anyhow!(
+                "mappings.{}.non_finite must be one of 'unknown', 'drop' or a number",
+                name
+            ))
+        }
+        None => NonFinitePolicy::default(),
+    };
+
+    let result_count = {
+        let parse_count = |key: &str| -> Result<Option<usize>, anyhow::Error> {
+            match items.get(&Yaml::from_str(key)) {
+                Some(c) => {
+                    let num = c.as_i64().ok_or(ParseFieldError {
+                        field: format!("mappings.{}.{}", name, key),
+                        kind: "number",
+                    })?;
+                    let count = usize::try_from(num).map_err(|_| ParseFieldError {
+                        field: format!("mappings.{}.{}", name, key),
+                        kind: "number",
+                    })?;
+                    Ok(Some(count))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let min = parse_count("min_results")?;
+        let max = parse_count("max_results")?;
+
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                bail!(
+                    "mappings.{}.min_results must not be greater than mappings.{}.max_results",
+                    name,
+                    name
+                );
+            }
+        }
+
+        let exit_value = match items.get(&Yaml::from_str("result_count_state")) {
+            Some(s) => {
+                let field = format!("mappings.{}.result_count_state", name);
+                let raw = s.as_str().ok_or(ParseFieldError {
+                    field: field.clone(),
+                    kind: "string",
+                })?;
+                parse_state(raw).with_context(|| format!("failed to parse {}", field))?
+            }
+            None => ResultCount::default().exit_value,
+        };
+
+        ResultCount {
+            min,
+            max,
+            exit_value,
+        }
+    };
+
+    let query_error_state = match items.get(&Yaml::from_str("query_error_state")) {
+        Some(s) => {
+            let field = format!("mappings.{}.query_error_state", name);
+            let raw = s.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            Some(parse_state(raw).with_context(|| format!("failed to parse {}", field))?)
+        }
+        None => None,
+    };
+
+    let aggregate = match items.get(&Yaml::from_str("aggregate")) {
+        Some(a) => {
+            let field = format!("mappings.{}.aggregate", name);
+            let raw = a.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            let aggregation = match raw {
+                "sum" => Aggregation::Sum,
+                "avg" => Aggregation::Avg,
+                "max" => Aggregation::Max,
+                "min" => Aggregation::Min,
+                "count" => Aggregation::Count,
+                "median" => Aggregation::Percentile(50.0),
+                _ => match raw.strip_prefix('p').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n) if n <= 100 => Aggregation::Percentile(f64::from(n)),
+                    _ => bail!(
+                        "{} must be one of 'sum', 'avg', 'max', 'min', 'count', 'median' or a percentile like 'p95', got '{}'",
+                        field,
+                        raw
+                    ),
+                },
+            };
+            Some(aggregation)
+        }
+        None => None,
+    };
+
+    let deduplicate = match items.get(&Yaml::from_str("deduplicate")) {
+        Some(d) => {
+            let field = format!("mappings.{}.deduplicate", name);
+            let raw = d.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            let deduplication = match raw {
+                "max" => Deduplication::Max,
+                "min" => Deduplication::Min,
+                "last" => Deduplication::Last,
+                _ => bail!(
+                    "{} must be one of 'max', 'min' or 'last', got '{}'",
+                    field,
+                    raw
+                ),
+            };
+            Some(deduplication)
+        }
+        None => None,
+    };
+
+    let ignore_unchanged = match items.get(&Yaml::from_str("ignore_unchanged")) {
+        Some(val) => val.as_bool().ok_or(ParseFieldError {
+            field: format!("mappings.{}.ignore_unchanged", name),
+            kind: "boolean",
+        })?,
+        None => false,
+    };
+
+    let derive = match items.get(&Yaml::from_str("derive")) {
+        Some(d) => {
+            let field = format!("mappings.{}.derive", name);
+            let raw = d.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            let derivation = match raw {
+                "delta" => Derivation::Delta,
+                "rate" => Derivation::Rate,
+                _ => bail!("{} must be one of 'delta' or 'rate', got '{}'", field, raw),
+            };
+            Some(derivation)
+        }
+        None => None,
+    };
+
+    let count_breaching = match items.get(&Yaml::from_str("count_breaching")) {
+        Some(c) => {
+            let field = format!("mappings.{}.count_breaching", name);
+            let raw = c.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            if aggregate.is_some() {
+                bail!(
+                    "{} cannot be combined with mappings.{}.aggregate",
+                    field,
+                    name
+                );
+            }
+            Some(NagiosRange::from(raw).with_context(|| format!("failed to parse {}", field))?)
+        }
+        None => None,
+    };
+
+    let group_by = match items.get(&Yaml::from_str("group_by")) {
+        Some(g) => {
+            let field = format!("mappings.{}.group_by", name);
+            if aggregate.is_none() && count_breaching.is_none() {
+                bail!(
+                    "{} requires mappings.{}.aggregate or mappings.{}.count_breaching",
+                    field,
+                    name,
+                    name
+                );
+            }
+            g.as_vec()
+                .ok_or(ParseFieldError {
+                    field: field.clone(),
+                    kind: "array",
+                })?
+                .iter()
+                .map(|label| {
+                    label
+                        .as_str()
+                        .map(|label| label.to_string())
+                        .ok_or(ParseFieldError {
+                            field: field.clone(),
+                            kind: "string",
+                        })
+                })
+                .collect::<Result<Vec<String>, ParseFieldError>>()?
+        }
+        None => vec![],
+    };
+
+    let parse_clamp = |key: &str| -> Result<Option<f64>, anyhow::Error> {
+        match items.get(&Yaml::from_str(key)) {
+            Some(c) => {
+                let num =
+                    c.as_f64()
+                        .or_else(|| c.as_i64().map(|i| i as f64))
+                        .ok_or(ParseFieldError {
+                            field: format!("mappings.{}.{}", name, key),
+                            kind: "number",
+                        })?;
+                Ok(Some(num))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let clamp_min = parse_clamp("clamp_min")?;
+    let clamp_max = parse_clamp("clamp_max")?;
+
+    if let (Some(min), Some(max)) = (clamp_min, clamp_max) {
+        if min > max {
+            bail!(
+                "mappings.{}.clamp_min must not be greater than mappings.{}.clamp_max, got {} and {}",
+                name,
+                name,
+                min,
+                max
+            );
+        }
+    }
+
+    let precision = match items.get(&Yaml::from_str("precision")) {
+        Some(p) => {
+            let field = format!("mappings.{}.precision", name);
+            let num = p.as_i64().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "number",
+            })?;
+            if !(0..=15).contains(&num) {
+                bail!("{} must be between 0 and 15, got {}", field, num);
+            }
+            Some(num as u32)
+        }
+        None => None,
+    };
+
+    let transform = match items.get(&Yaml::from_str("transform")) {
+        Some(t) => {
+            let t_hash = t.as_hash().ok_or(ParseFieldError {
+                field: format!("mappings.{}.transform", name),
+                kind: "hash",
+            })?;
+            Some(parse_transform(
+                t_hash,
+                &format!("mappings.{}.transform", name),
+            )?)
+        }
+        None => None,
+    };
+
+    let breach_cycles = match items.get(&Yaml::from_str("for")) {
+        Some(f) => {
+            let num = f.as_i64().ok_or(ParseFieldError {
+                field: format!("mappings.{}.for", name),
+                kind: "number",
+            })?;
+            let cycles = u32::try_from(num).map_err(|_| ParseFieldError {
+                field: format!("mappings.{}.for", name),
+                kind: "number",
+            })?;
+            if cycles == 0 {
+                bail!("mappings.{}.for must be at least 1", name);
+            }
+            cycles
+        }
+        None => 1,
+    };
+
+    let flapping = match items.get(&Yaml::from_str("flapping")) {
+        Some(f) => {
+            let f_hash = f.as_hash().ok_or(ParseFieldError {
+                field: format!("mappings.{}.flapping", name),
+                kind: "hash",
+            })?;
+
+            let parse_count = |key: &str, default: usize| -> Result<usize, anyhow::Error> {
+                match f_hash.get(&Yaml::from_str(key)) {
+                    Some(c) => {
+                        let field = format!("mappings.{}.flapping.{}", name, key);
+                        let num = c.as_i64().ok_or(ParseFieldError {
+                            field: field.clone(),
+                            kind: "number",
+                        })?;
+                        Ok(usize::try_from(num).map_err(|_| ParseFieldError {
+                            field,
+                            kind: "number",
+                        })?)
+                    }
+                    None => Ok(default),
+                }
+            };
+
+            let window = parse_count("window", 10)?;
+            let max_changes = parse_count("max_changes", 3)?;
+
+            if window < 2 {
+                bail!("mappings.{}.flapping.window must be at least 2", name);
+            }
+
+            Some(FlapDetection {
+                window,
+                max_changes,
+            })
+        }
+        None => None,
+    };
+
+    let mut state_map = [0, 1, 2, 3];
+    if let Some(s) = items.get(&Yaml::from_str("state_map")) {
+        let s_hash = s.as_hash().ok_or(ParseFieldError {
+            field: format!("mappings.{}.state_map", name),
+            kind: "hash",
+        })?;
+
+        for (from, to) in s_hash.iter() {
+            let field = format!("mappings.{}.state_map", name);
+            let from = from.as_str().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "string",
+            })?;
+            let to = to.as_str().ok_or(ParseFieldError {
+                field: format!("{}.{}", field, from),
+                kind: "string",
+            })?;
+            let from_value =
+                parse_state(from).with_context(|| format!("failed to parse {}", field))?;
+            state_map[from_value as usize] =
+                parse_state(to).with_context(|| format!("failed to parse {}.{}", field, from))?;
+        }
+    }
+
+    let mut maintenance = match items.get(&Yaml::from_str("maintenance")) {
+        Some(m) => parse_maintenance_windows(m, &format!("mappings.{}.maintenance", name))?,
+        None => vec![],
+    };
+    maintenance.extend_from_slice(global_maintenance);
+
+    let label_states = match items.get(&Yaml::from_str("label_states")) {
+        Some(l) => {
+            let field = format!("mappings.{}.label_states", name);
+            let l_hash = l.as_hash().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "hash",
+            })?;
+
+            let label = l_hash
+                .get(&Yaml::from_str("label"))
+                .ok_or(MissingFieldError {
+                    field: format!("{}.label", field),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.label", field),
+                    kind: "string",
+                })?
+                .to_string();
+
+            let raw_states = l_hash
+                .get(&Yaml::from_str("states"))
+                .ok_or(MissingFieldError {
+                    field: format!("{}.states", field),
+                })?
+                .as_hash()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.states", field),
+                    kind: "hash",
+                })?;
+
+            let mut states = HashMap::new();
+            for (value, state) in raw_states.iter() {
+                let value = value.as_str().ok_or(ParseFieldError {
+                    field: format!("{}.states", field),
+                    kind: "string",
+                })?;
+                let state = state.as_str().ok_or(ParseFieldError {
+                    field: format!("{}.states.{}", field, value),
+                    kind: "string",
+                })?;
+                let state = parse_state(state)
+                    .with_context(|| format!("failed to parse {}.states.{}", field, value))?;
+                states.insert(value.to_string(), state);
+            }
+
+            let default = match l_hash.get(&Yaml::from_str("default")) {
+                Some(d) => {
+                    let d = d.as_str().ok_or(ParseFieldError {
+                        field: format!("{}.default", field),
+                        kind: "string",
+                    })?;
+                    parse_state(d).with_context(|| format!("failed to parse {}.default", field))?
+                }
+                None => 3,
+            };
+
+            Some(LabelStates {
+                label,
+                states,
+                default,
+            })
+        }
+        None => None,
+    };
+
+    let state_from_label = match items.get(&Yaml::from_str("state_from_label")) {
+        Some(s) => {
+            let field = format!("mappings.{}.state_from_label", name);
+            let s_hash = s.as_hash().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "hash",
+            })?;
+
+            let label = s_hash
+                .get(&Yaml::from_str("label"))
+                .ok_or(MissingFieldError {
+                    field: format!("{}.label", field),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.label", field),
+                    kind: "string",
+                })?
+                .to_string();
+
+            // Without a table the label values are expected to be state
+            // names themselves.
+            let states = match s_hash.get(&Yaml::from_str("states")) {
+                Some(t) => {
+                    let t_hash = t.as_hash().ok_or(ParseFieldError {
+                        field: format!("{}.states", field),
+                        kind: "hash",
+                    })?;
+                    let mut states = HashMap::new();
+                    for (value, state) in t_hash.iter() {
+                        let value = value.as_str().ok_or(ParseFieldError {
+                            field: format!("{}.states", field),
+                            kind: "string",
+                        })?;
+                        let state = state.as_str().ok_or(ParseFieldError {
+                            field: format!("{}.states.{}", field, value),
+                            kind: "string",
+                        })?;
+                        let state = parse_state(state).with_context(|| {
+                            format!("failed to parse {}.states.{}", field, value)
+                        })?;
+                        states.insert(value.to_string(), state);
+                    }
+                    states
+                }
+                None => HashMap::from([
+                    ("ok".to_string(), 0),
+                    ("warning".to_string(), 1),
+                    ("critical".to_string(), 2),
+                    ("unknown".to_string(), 3),
+                ]),
+            };
+
+            Some(StateFromLabel { label, states })
+        }
+        None => None,
+    };
+
+    let include = match items.get(&Yaml::from_str("include")) {
+        Some(i) => parse_label_matchers(i, &format!("mappings.{}.include", name))?,
+        None => vec![],
+    };
+
+    let exclude = match items.get(&Yaml::from_str("exclude")) {
+        Some(e) => parse_label_matchers(e, &format!("mappings.{}.exclude", name))?,
+        None => vec![],
+    };
+
+    let expect = match items.get(&Yaml::from_str("expect")) {
+        Some(e) => {
+            let field = format!("mappings.{}.expect", name);
+            let parse_value = |v: &Yaml| -> Result<f64, anyhow::Error> {
+                match v {
+                    Yaml::Integer(i) => Ok(*i as f64),
+                    Yaml::Real(r) => r.parse::<f64>().map_err(|_| {
+                        ParseFieldError {
+                            field: field.clone(),
+                            kind: "number",
+                        }
+                        .into()
+                    }),
+                    _ => Err(ParseFieldError {
+                        field: field.clone(),
+                        kind: "number",
+                    }
+                    .into()),
+                }
+            };
+            let values = match e {
+                Yaml::Array(a) => a
+                    .iter()
+                    .map(parse_value)
+                    .collect::<Result<Vec<f64>, anyhow::Error>>()?,
+                v => vec![parse_value(v)?],
+            };
+            if values.is_empty() {
+                bail!("{} must contain at least one value", field);
+            }
+
+            let exit_value = match items.get(&Yaml::from_str("expect_state")) {
+                Some(s) => {
+                    let s = s.as_str().ok_or(ParseFieldError {
+                        field: format!("mappings.{}.expect_state", name),
+                        kind: "string",
+                    })?;
+                    parse_state(s).with_context(|| {
+                        format!("failed to parse mappings.{}.expect_state", name)
+                    })?
+                }
+                None => 2,
+            };
+
+            Some(Expectation { values, exit_value })
+        }
+        None => None,
+    };
+
+    let conditions = match items.get(&Yaml::from_str("conditions")) {
+        Some(c) => Some(parse_conditions(
+            c,
+            &format!("mappings.{}.conditions", name),
+        )?),
+        None => None,
+    };
+
+    let escalate_after = match items.get(&Yaml::from_str("escalate_after")) {
+        Some(e) => {
+            let field = format!("mappings.{}.escalate_after", name);
+            let num = e.as_i64().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "number",
+            })?;
+            if num < 1 {
+                bail!("{} must be at least 1", field);
+            }
+            Some(u32::try_from(num).map_err(|_| ParseFieldError {
+                field,
+                kind: "number",
+            })?)
+        }
+        None => None,
+    };
+
+    let default_output = match items.get(&Yaml::from_str("default_output")) {
+        Some(d) => {
+            let d_hash = d.as_hash().ok_or(ParseFieldError {
+                field: format!("mappings.{}.default_output", name),
+                kind: "hash",
+            })?;
+
+            let top = match d_hash.get(&Yaml::from_str("top")) {
+                Some(t) => {
+                    let num = t.as_i64().ok_or(ParseFieldError {
+                        field: format!("mappings.{}.default_output.top", name),
+                        kind: "number",
+                    })?;
+                    usize::try_from(num).map_err(|_| ParseFieldError {
+                        field: format!("mappings.{}.default_output.top", name),
+                        kind: "number",
+                    })?
+                }
+                None => 0,
+            };
+
+            let long = match d_hash.get(&Yaml::from_str("long")) {
+                Some(l) => l.as_bool().ok_or(ParseFieldError {
+                    field: format!("mappings.{}.default_output.long", name),
+                    kind: "boolean",
+                })?,
+                None => false,
+            };
+
+            let labels = match d_hash.get(&Yaml::from_str("labels")) {
+                Some(l) => l
+                    .as_vec()
+                    .ok_or(ParseFieldError {
+                        field: format!("mappings.{}.default_output.labels", name),
+                        kind: "array",
+                    })?
+                    .iter()
+                    .map(|label| {
+                        label
+                            .as_str()
+                            .map(|label| label.to_string())
+                            .ok_or(ParseFieldError {
+                                field: format!("mappings.{}.default_output.labels", name),
+                                kind: "string",
+                            })
+                    })
+                    .collect::<Result<Vec<String>, ParseFieldError>>()?,
+                None => vec![],
+            };
+
+            DefaultOutput { top, long, labels }
+        }
+        None => DefaultOutput::default(),
+    };
+
     let plugin_output = match items.get(&Yaml::from_str("plugin_output")) {
         Some(p) => Some(
             p.as_str()
@@ -86,143 +837,945 @@ fn parse_mapping(mapping: (&Yaml, &Yaml)) -> Result<Mapping, anyhow::Error> {
         None => None,
     };
 
-    let thresholds = {
-        match items.get(&Yaml::from_str("thresholds")) {
-            Some(t) => {
-                let t_hash = t.as_hash().ok_or(ParseFieldError {
-                    field: format!("mappings.{}.thresholds", name),
-                    kind: "hash",
-                })?;
+    let plugin_output_strict = match items.get(&Yaml::from_str("plugin_output_strict")) {
+        Some(val) => val.as_bool().ok_or(ParseFieldError {
+            field: format!("mappings.{}.plugin_output_strict", name),
+            kind: "boolean",
+        })?,
+        None => true,
+    };
+
+    let template_engine = match items.get(&Yaml::from_str("template_engine")) {
+        Some(e) => match e.as_str() {
+            Some("handlebars") => TemplateEngine::Handlebars,
+            Some("jinja") => TemplateEngine::Jinja,
+            _ => bail!(
+                "mappings.{}.template_engine must be one of 'handlebars' or 'jinja'",
+                name
+            ),
+        },
+        None => TemplateEngine::Handlebars,
+    };
+
+    let max_output_length = match items.get(&Yaml::from_str("max_output_length")) {
+        Some(l) => {
+            let field = format!("mappings.{}.max_output_length", name);
+            let num = l.as_i64().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "number",
+            })?;
+            // Leave room for at least a few characters of the summary line
+            // next to the note on omitted lines.
+            if num < 32 {
+                bail!("{} must be at least 32, got {}", field, num);
+            }
+            Some(num as usize)
+        }
+        None => None,
+    };
+
+    let (thresholds, threshold_templates) = {
+        match items.get(&Yaml::from_str("thresholds")) {
+            Some(t) => {
+                let field = format!("mappings.{}.thresholds", name);
+                let mut t_hash = t
+                    .as_hash()
+                    .ok_or(ParseFieldError {
+                        field: field.clone(),
+                        kind: "hash",
+                    })?
+                    .clone();
+
+                // Ranges that contain handlebars expressions are rendered per
+                // time series, thus they are only checked for valid syntax here.
+                let mut take_template = |key: &str| -> Result<Option<String>, anyhow::Error> {
+                    let yaml_key = Yaml::from_str(key);
+                    match t_hash.get(&yaml_key).and_then(|v| v.as_str()) {
+                        Some(raw) if raw.contains("{{") => {
+                            let raw = raw.to_string();
+                            handlebars::Template::compile(&raw).with_context(|| {
+                                format!("failed to parse {}.{} as handlebars template", field, key)
+                            })?;
+                            t_hash.remove(&yaml_key);
+                            Ok(Some(raw))
+                        }
+                        _ => Ok(None),
+                    }
+                };
+
+                let threshold_templates = ThresholdTemplates {
+                    warning: take_template("warning")?,
+                    critical: take_template("critical")?,
+                };
+
+                (parse_threshold_pair(&t_hash, &field)?, threshold_templates)
+            }
+            None => (ThresholdPair::default(), ThresholdTemplates::default()),
+        }
+    };
+
+    let threshold_rules = match items.get(&Yaml::from_str("threshold_rules")) {
+        Some(r) => {
+            let rules = r.as_vec().ok_or(ParseFieldError {
+                field: format!("mappings.{}.threshold_rules", name),
+                kind: "array",
+            })?;
+
+            rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| {
+                    parse_threshold_rule(rule, &format!("mappings.{}.threshold_rules.{}", name, i))
+                })
+                .collect::<Result<Vec<ThresholdRule>, anyhow::Error>>()?
+        }
+        None => vec![],
+    };
+
+    let interval: Duration = match items.get(&Yaml::from_str("interval")) {
+        Some(i) => {
+            let num = i.as_i64().ok_or(ParseFieldError {
+                field: format!("mappings.{}.interval", name),
+                kind: "number",
+            })?;
+
+            let conv = u16::try_from(num).map_err(|_| ParseFieldError {
+                field: format!("mappings.{}.interval", name),
+                kind: "number",
+            })? as u64;
+
+            let valid_range = 10..=3600;
+
+            if !valid_range.contains(&conv) {
+                return Err(anyhow!(
+                    "mappings.{}.interval must be in the range {:?}, got {}",
+                    name,
+                    valid_range,
+                    conv
+                ));
+            }
+
+            Duration::from_secs(conv)
+        }
+        None => Duration::from_secs(60),
+    };
+
+    let performance_data = match items.get(&Yaml::from_str("performance_data")) {
+        Some(t) => {
+            let field = format!("mappings.{}.performance_data", name);
+            let t_hash = t.as_hash().ok_or(ParseFieldError {
+                field: field.clone(),
+                kind: "hash",
+            })?;
+            parse_performance_data(t_hash, &field, global_performance_data)?
+        }
+        None => global_performance_data.clone(),
+    };
+
+    let request_timeout = match items.get(&Yaml::from_str("request_timeout")) {
+        Some(t) => Some(parse_timeout(
+            t,
+            &format!("mappings.{}.request_timeout", name),
+        )?),
+        None => None,
+    };
+
+    let basic_auth = match items.get(&Yaml::from_str("authentication")) {
+        Some(a) => {
+            let a_hash = a.as_hash().ok_or(ParseFieldError {
+                field: format!("mappings.{}.authentication", name),
+                kind: "hash",
+            })?;
+
+            let username = {
+                let conf_attr = format!("mappings.{}.authentication.username", name);
+                a_hash
+                    .get(&Yaml::from_str("username"))
+                    .ok_or(MissingFieldError {
+                        field: conf_attr.clone(),
+                    })?
+                    .as_str()
+                    .ok_or(ParseFieldError {
+                        field: conf_attr,
+                        kind: "string",
+                    })?
+                    .to_string()
+            };
+
+            let password = {
+                let conf_attr = format!("mappings.{}.authentication.password", name);
+                a_hash
+                    .get(&Yaml::from_str("password"))
+                    .ok_or(MissingFieldError {
+                        field: conf_attr.clone(),
+                    })?
+                    .as_str()
+                    .ok_or(ParseFieldError {
+                        field: conf_attr,
+                        kind: "string",
+                    })?
+                    .to_string()
+            };
+
+            Some(IcingaBasicAuth { username, password })
+        }
+        None => None,
+    };
+
+    let sync_vars = match items.get(&Yaml::from_str("sync_vars")) {
+        Some(val) => val.as_bool().ok_or(ParseFieldError {
+            field: format!("mappings.{}.sync_vars", name),
+            kind: "boolean",
+        })?,
+        None => false,
+    };
+
+    Ok(Mapping {
+        name,
+        query,
+        host,
+        service,
+        interval,
+        plugin_output,
+        plugin_output_strict,
+        template_engine,
+        max_output_length,
+        thresholds,
+        performance_data,
+        sync_vars,
+        request_timeout,
+        basic_auth,
+        threshold_rules,
+        baseline_query,
+        no_data,
+        non_finite,
+        result_count,
+        query_error_state,
+        partials: Arc::default(),
+        aggregate,
+        transform,
+        breach_cycles,
+        flapping,
+        state_map,
+        maintenance,
+        label_states,
+        state_from_label,
+        include,
+        exclude,
+        deduplicate,
+        derive,
+        default_output,
+        expect,
+        conditions,
+        threshold_templates,
+        grace_period: None,
+        escalate_after,
+        count_breaching,
+        group_by,
+        ignore_unchanged,
+        clamp_min,
+        clamp_max,
+        precision,
+        last_apply: Instant::now(),
+        state: MappingState::default(),
+    })
+}
+
+/// Parses the performance data settings of a mapping or the global
+/// defaults from YAML configuration. Settings that are not present are
+/// taken from `defaults`.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// performance_data:
+///   enabled: <bool>                    # optional
+///   label: '<template>'                # optional
+///   label_prefix: '<prefix>'           # optional
+///   uom: '<uom>'|'<template>'          # optional
+///   auto_uom: <bool>                   # optional
+///   warning: '<nagios_range>'|false    # optional
+///   critical: '<nagios_range>'|false   # optional
+///   numeric_thresholds: <bool>         # optional
+///   precision: <decimal_places>        # optional
+///   fixed: <bool>                      # optional
+///   strict_labels: <bool>              # optional
+///   sort: 'query'|'label'|'value'      # optional
+///   max_items: <count>                 # optional
+///   select: 'value'|'label'            # optional
+///   non_finite: 'drop'|<number>        # optional
+///   summary: <bool>                    # optional
+///   format: '<template>'               # optional
+///   absent: <number>                   # optional
+/// ```
+fn parse_performance_data(
+    t_hash: &Hash,
+    field: &str,
+    defaults: &PerformanceData,
+) -> Result<PerformanceData, anyhow::Error> {
+    let parse_bool = |key: &str, default: bool| -> Result<bool, anyhow::Error> {
+        match t_hash.get(&Yaml::from_str(key)) {
+            Some(val) => val.as_bool().ok_or_else(|| {
+                ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "boolean",
+                }
+                .into()
+            }),
+            None => Ok(default),
+        }
+    };
+
+    let parse_string = |key: &str| -> Result<Option<String>, anyhow::Error> {
+        match t_hash.get(&Yaml::from_str(key)) {
+            Some(val) => {
+                let raw = val.as_str().ok_or(ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "string",
+                })?;
+                Ok(Some(raw.to_string()))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let enabled = parse_bool("enabled", defaults.enabled)?;
+
+    let label = parse_string("label")?.or_else(|| defaults.label.clone());
+
+    let label_prefix = parse_string("label_prefix")?.or_else(|| defaults.label_prefix.clone());
+
+    let uom = match parse_string("uom")? {
+        Some(uom) => {
+            if uom.contains("{{") {
+                handlebars::Template::compile(&uom).with_context(|| {
+                    format!("failed to parse {}.uom as handlebars template", field)
+                })?;
+            }
+            Some(uom)
+        }
+        None => defaults.uom.clone(),
+    };
+
+    let auto_uom = parse_bool("auto_uom", defaults.auto_uom)?;
+
+    let warning = parse_perfdata_threshold(t_hash, "warning", field)?
+        .unwrap_or_else(|| defaults.warning.clone());
+    let critical = parse_perfdata_threshold(t_hash, "critical", field)?
+        .unwrap_or_else(|| defaults.critical.clone());
+
+    let numeric_thresholds = parse_bool("numeric_thresholds", defaults.numeric_thresholds)?;
+
+    let precision = match t_hash.get(&Yaml::from_str("precision")) {
+        Some(val) => {
+            let num = val.as_i64().ok_or(ParseFieldError {
+                field: format!("{}.precision", field),
+                kind: "number",
+            })?;
+            if !(0..=15).contains(&num) {
+                bail!("{}.precision must be between 0 and 15, got {}", field, num);
+            }
+            Some(num as usize)
+        }
+        None => defaults.precision,
+    };
+
+    let fixed = parse_bool("fixed", defaults.fixed)?;
+
+    let strict_labels = parse_bool("strict_labels", defaults.strict_labels)?;
+
+    let sort = match parse_string("sort")?.as_deref() {
+        Some("query") => PerfdataOrder::Query,
+        Some("label") => PerfdataOrder::Label,
+        Some("value") => PerfdataOrder::Value,
+        Some(raw) => bail!(
+            "{}.sort must be one of 'query', 'label' or 'value', got '{}'",
+            field,
+            raw
+        ),
+        None => defaults.sort,
+    };
+
+    let max_items = match t_hash.get(&Yaml::from_str("max_items")) {
+        Some(val) => {
+            let num = val.as_i64().ok_or(ParseFieldError {
+                field: format!("{}.max_items", field),
+                kind: "number",
+            })?;
+            if num < 1 {
+                bail!("{}.max_items must be greater than 0, got {}", field, num);
+            }
+            Some(num as usize)
+        }
+        None => defaults.max_items,
+    };
+
+    let select = match parse_string("select")?.as_deref() {
+        Some("label") => PerfdataOrder::Label,
+        Some("value") => PerfdataOrder::Value,
+        Some(raw) => bail!(
+            "{}.select must be one of 'label' or 'value', got '{}'",
+            field,
+            raw
+        ),
+        None => defaults.select,
+    };
+
+    let non_finite = match t_hash.get(&Yaml::from_str("non_finite")) {
+        Some(Yaml::String(s)) if s == "drop" => PerfdataNonFinite::Drop,
+        Some(Yaml::Real(r)) => {
+            PerfdataNonFinite::Substitute(r.parse::<f64>().map_err(|_| ParseFieldError {
+                field: format!("{}.non_finite", field),
+                kind: "number",
+            })?)
+        }
+        Some(Yaml::Integer(i)) => PerfdataNonFinite::Substitute(*i as f64),
+        Some(_) => bail!("{}.non_finite must be either 'drop' or a number", field),
+        None => defaults.non_finite,
+    };
+
+    let summary = parse_bool("summary", defaults.summary)?;
+
+    let format = match parse_string("format")? {
+        Some(format) => {
+            handlebars::Template::compile(&format).with_context(|| {
+                format!("failed to parse {}.format as handlebars template", field)
+            })?;
+            Some(format)
+        }
+        None => defaults.format.clone(),
+    };
+
+    let absent = match t_hash.get(&Yaml::from_str("absent")) {
+        Some(Yaml::Real(r)) => Some(r.parse::<f64>().map_err(|_| ParseFieldError {
+            field: format!("{}.absent", field),
+            kind: "number",
+        })?),
+        Some(Yaml::Integer(i)) => Some(*i as f64),
+        Some(_) => {
+            return Err(ParseFieldError {
+                field: format!("{}.absent", field),
+                kind: "number",
+            }
+            .into())
+        }
+        None => defaults.absent,
+    };
+
+    Ok(PerformanceData {
+        enabled,
+        label,
+        label_prefix,
+        uom,
+        auto_uom,
+        warning,
+        critical,
+        numeric_thresholds,
+        precision,
+        fixed,
+        strict_labels,
+        sort,
+        max_items,
+        select,
+        non_finite,
+        summary,
+        format,
+        absent,
+    })
+}
+
+/// Parse a threshold that overrides the alerting threshold in the
+/// performance data. `false` leaves the threshold out.
+fn parse_perfdata_threshold(
+    t_hash: &Hash,
+    key: &str,
+    field: &str,
+) -> Result<Option<PerfdataThreshold>, anyhow::Error> {
+    match t_hash.get(&Yaml::from_str(key)) {
+        Some(Yaml::Boolean(false)) => Ok(Some(PerfdataThreshold::Omit)),
+        Some(Yaml::String(raw)) => {
+            let range = NagiosRange::from(raw)
+                .with_context(|| format!("failed to parse {}.{}", field, key))?;
+            Ok(Some(PerfdataThreshold::Range(range)))
+        }
+        Some(_) => bail!("{}.{} must be either a Nagios range or false", field, key),
+        None => Ok(None),
+    }
+}
+
+/// Parses a state name to the corresponding service exit value. Host
+/// states are accepted as well and map to OK and CRITICAL respectively.
+fn parse_state(raw: &str) -> Result<u8, anyhow::Error> {
+    match raw.to_lowercase().as_str() {
+        "ok" | "up" => Ok(0),
+        "warning" => Ok(1),
+        "critical" | "down" => Ok(2),
+        "unknown" => Ok(3),
+        _ => Err(anyhow!(
+            "'{}' is not a valid state, must be one of 'ok', 'warning', 'critical', 'unknown', 'up' or 'down'",
+            raw
+        )),
+    }
+}
+
+/// Parses a value transformation from YAML configuration. A unit conversion
+/// (`from` and `to`) is applied first, then the value is multiplied by `scale`
+/// and `offset` is added. All of this is folded into a single linear function.
+fn parse_transform(t_hash: &Hash, field: &str) -> Result<Transform, anyhow::Error> {
+    let parse_number = |key: &str, default: f64| -> Result<f64, anyhow::Error> {
+        match t_hash.get(&Yaml::from_str(key)) {
+            Some(Yaml::Real(r)) => r.parse::<f64>().map_err(|_| {
+                ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "number",
+                }
+                .into()
+            }),
+            Some(Yaml::Integer(i)) => Ok(*i as f64),
+            Some(_) => Err(ParseFieldError {
+                field: format!("{}.{}", field, key),
+                kind: "number",
+            }
+            .into()),
+            None => Ok(default),
+        }
+    };
+
+    let parse_unit = |key: &str| -> Result<Option<(&'static str, f64)>, anyhow::Error> {
+        match t_hash.get(&Yaml::from_str(key)) {
+            Some(u) => {
+                let raw = u.as_str().ok_or(ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "string",
+                })?;
+                let unit = unit_factor(raw)
+                    .ok_or_else(|| anyhow!("{}.{}: unknown unit '{}'", field, key, raw))?;
+                Ok(Some(unit))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let conversion = match (parse_unit("from")?, parse_unit("to")?) {
+        (Some((from_dim, from)), Some((to_dim, to))) => {
+            if from_dim != to_dim {
+                bail!(
+                    "{}: cannot convert a {} to a {} unit",
+                    field,
+                    from_dim,
+                    to_dim
+                );
+            }
+            from / to
+        }
+        (None, None) => 1.0,
+        _ => bail!(
+            "{}: both 'from' and 'to' are required to convert units",
+            field
+        ),
+    };
+
+    let scale = parse_number("scale", 1.0)?;
+    let offset = parse_number("offset", 0.0)?;
+
+    Ok(Transform {
+        factor: conversion * scale,
+        offset,
+    })
+}
+
+/// Return the dimension and the factor relative to the base unit of the
+/// dimension for the units that values may be converted between.
+fn unit_factor(unit: &str) -> Option<(&'static str, f64)> {
+    let unit = match unit {
+        "B" | "bytes" => ("data", 1.0),
+        "kB" | "KB" => ("data", 1e3),
+        "MB" => ("data", 1e6),
+        "GB" => ("data", 1e9),
+        "TB" => ("data", 1e12),
+        "KiB" => ("data", 1024.0),
+        "MiB" => ("data", 1024.0_f64.powi(2)),
+        "GiB" => ("data", 1024.0_f64.powi(3)),
+        "TiB" => ("data", 1024.0_f64.powi(4)),
+        "ns" => ("time", 1e-9),
+        "us" => ("time", 1e-6),
+        "ms" => ("time", 1e-3),
+        "s" | "seconds" => ("time", 1.0),
+        "m" | "min" => ("time", 60.0),
+        "h" => ("time", 3600.0),
+        "d" => ("time", 86400.0),
+        "ratio" => ("ratio", 1.0),
+        "%" | "percent" => ("ratio", 0.01),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// Parses the conditions that are combined with the main query of a mapping.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// operator: 'and'|'or'             # optional
+/// queries:
+///   - name: '<name>'
+///     query: '<promql_query>'
+///     warning: '<nagios_range>'    # optional
+///     critical: '<nagios_range>'   # optional
+/// ```
+fn parse_conditions(conditions: &Yaml, field: &str) -> Result<Conditions, anyhow::Error> {
+    let c_hash = conditions.as_hash().ok_or(ParseFieldError {
+        field: field.to_string(),
+        kind: "hash",
+    })?;
+
+    let operator = match c_hash.get(&Yaml::from_str("operator")) {
+        Some(o) => match o.as_str() {
+            Some("and") => BoolOp::And,
+            Some("or") => BoolOp::Or,
+            _ => bail!("{}.operator must be one of 'and' or 'or'", field),
+        },
+        None => BoolOp::And,
+    };
+
+    let queries = c_hash
+        .get(&Yaml::from_str("queries"))
+        .ok_or(MissingFieldError {
+            field: format!("{}.queries", field),
+        })?
+        .as_vec()
+        .ok_or(ParseFieldError {
+            field: format!("{}.queries", field),
+            kind: "array",
+        })?;
+
+    if queries.is_empty() {
+        bail!("{}.queries must contain at least one query", field);
+    }
+
+    let mut items = vec![];
+
+    for (index, query) in queries.iter().enumerate() {
+        let field = format!("{}.queries.{}", field, index);
+        let q_hash = query.as_hash().ok_or(ParseFieldError {
+            field: field.clone(),
+            kind: "hash",
+        })?;
+
+        let get_str = |key: &str| -> Result<String, anyhow::Error> {
+            Ok(q_hash
+                .get(&Yaml::from_str(key))
+                .ok_or(MissingFieldError {
+                    field: format!("{}.{}", field, key),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "string",
+                })?
+                .to_string())
+        };
+
+        let name = get_str("name")?;
+        let query = get_str("query")?;
+        let thresholds = parse_threshold_pair(q_hash, &field)?;
+
+        if thresholds.warning.is_none() && thresholds.critical.is_none() {
+            bail!("{} must define a warning and/or critical threshold", field);
+        }
+
+        items.push(Condition {
+            name,
+            query,
+            thresholds,
+        });
+    }
+
+    Ok(Conditions { operator, items })
+}
+
+/// Parses a list of maintenance windows from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// - days: ['<weekday>', ...]   # optional
+///   start: '<HH:MM>'
+///   end: '<HH:MM>'
+///   action: 'skip'|'ok'        # optional
+/// ```
+fn parse_maintenance_windows(
+    windows: &Yaml,
+    field: &str,
+) -> Result<Vec<MaintenanceWindow>, anyhow::Error> {
+    let windows = windows.as_vec().ok_or(ParseFieldError {
+        field: field.to_string(),
+        kind: "array",
+    })?;
+
+    let mut result = vec![];
+
+    for (index, window) in windows.iter().enumerate() {
+        let field = format!("{}.{}", field, index);
+        let w_hash = window.as_hash().ok_or(ParseFieldError {
+            field: field.clone(),
+            kind: "hash",
+        })?;
+
+        let parse_time = |key: &str| -> Result<NaiveTime, anyhow::Error> {
+            let raw = w_hash
+                .get(&Yaml::from_str(key))
+                .ok_or(MissingFieldError {
+                    field: format!("{}.{}", field, key),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.{}", field, key),
+                    kind: "string",
+                })?;
+            NaiveTime::parse_from_str(raw, "%H:%M")
+                .with_context(|| format!("{}.{} must be a time of day, e.g. '01:30'", field, key))
+        };
+
+        let start = parse_time("start")?;
+        let end = parse_time("end")?;
+
+        let days = match w_hash.get(&Yaml::from_str("days")) {
+            Some(d) => d
+                .as_vec()
+                .ok_or(ParseFieldError {
+                    field: format!("{}.days", field),
+                    kind: "array",
+                })?
+                .iter()
+                .map(|day| {
+                    day.as_str()
+                        .and_then(|day| day.parse::<Weekday>().ok())
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "{}.days must only contain weekdays, e.g. 'mon' or 'monday'",
+                                field
+                            )
+                        })
+                })
+                .collect::<Result<Vec<Weekday>, anyhow::Error>>()?,
+            None => vec![],
+        };
+
+        let action = match w_hash.get(&Yaml::from_str("action")) {
+            Some(a) => match a.as_str() {
+                Some("skip") => MaintenanceAction::Skip,
+                Some("ok") => MaintenanceAction::Ok,
+                _ => bail!("{}.action must be one of 'skip' or 'ok'", field),
+            },
+            None => MaintenanceAction::Skip,
+        };
+
+        result.push(MaintenanceWindow {
+            days,
+            start,
+            end,
+            action,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Parses a pair of warning and critical thresholds (Nagios ranges), an
+/// additional unknown threshold and an OK range, all of which are optional.
+fn parse_threshold_pair(t_hash: &Hash, field: &str) -> Result<ThresholdPair, anyhow::Error> {
+    let warning = match t_hash.get(&Yaml::from_str("warning")) {
+        Some(w) => {
+            let w_raw = w.as_str().ok_or(ParseFieldError {
+                field: format!("{}.warning", field),
+                kind: "string",
+            })?;
+            Some(NagiosRange::from(w_raw)?)
+        }
+        None => None,
+    };
+
+    let critical = match t_hash.get(&Yaml::from_str("critical")) {
+        Some(c) => {
+            let c_raw = c.as_str().ok_or(ParseFieldError {
+                field: format!("{}.critical", field),
+                kind: "string",
+            })?;
+            Some(NagiosRange::from(c_raw)?)
+        }
+        None => None,
+    };
+
+    let unknown = match t_hash.get(&Yaml::from_str("unknown")) {
+        Some(u) => {
+            let u_raw = u.as_str().ok_or(ParseFieldError {
+                field: format!("{}.unknown", field),
+                kind: "string",
+            })?;
+            Some(NagiosRange::from(u_raw)?)
+        }
+        None => None,
+    };
+
+    let ok = match t_hash.get(&Yaml::from_str("ok")) {
+        Some(o) => {
+            let o_raw = o.as_str().ok_or(ParseFieldError {
+                field: format!("{}.ok", field),
+                kind: "string",
+            })?;
+            let range = NagiosRange::from(o_raw)?;
+            if range.checks_inside() {
+                bail!(
+                    "{}.ok must not be an inverted range ('@'), the range already defines the values that are OK",
+                    field
+                );
+            }
+
+            let exit_value = match t_hash.get(&Yaml::from_str("ok_violation_state")) {
+                Some(s) => {
+                    let s_field = format!("{}.ok_violation_state", field);
+                    let raw = s.as_str().ok_or(ParseFieldError {
+                        field: s_field.clone(),
+                        kind: "string",
+                    })?;
+                    parse_state(raw).with_context(|| format!("failed to parse {}", s_field))?
+                }
+                None => 2,
+            };
+
+            Some(OkRange { range, exit_value })
+        }
+        None => None,
+    };
+
+    Ok(ThresholdPair {
+        warning,
+        critical,
+        unknown,
+        ok,
+    })
+}
+
+/// Parses a single threshold rule from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// - matchers:
+///     - '<label_matcher>'
+///   warning: '<nagios_range>'        # optional
+///   critical: '<nagios_range>'       # optional
+/// ```
+fn parse_threshold_rule(rule: &Yaml, field: &str) -> Result<ThresholdRule, anyhow::Error> {
+    let r_hash = rule.as_hash().ok_or(ParseFieldError {
+        field: field.to_string(),
+        kind: "hash",
+    })?;
 
-                if t_hash.is_empty() {
-                    ThresholdPair::default()
-                } else {
-                    let warning = match t_hash.get(&Yaml::from_str("warning")) {
-                        Some(w) => {
-                            let w_raw = w.as_str().ok_or(ParseFieldError {
-                                field: format!("mappings.{}.thresholds.warning", name),
-                                kind: "string",
-                            })?;
-                            Some(NagiosRange::from(w_raw)?)
-                        }
-                        None => None,
-                    };
+    let matchers = parse_label_matchers(
+        r_hash
+            .get(&Yaml::from_str("matchers"))
+            .ok_or(MissingFieldError {
+                field: format!("{}.matchers", field),
+            })?,
+        &format!("{}.matchers", field),
+    )?;
 
-                    let critical = match t_hash.get(&Yaml::from_str("critical")) {
-                        Some(c) => {
-                            let c_raw = c.as_str().ok_or(ParseFieldError {
-                                field: format!("mappings.{}.thresholds.critical", name),
-                                kind: "string",
-                            })?;
-                            Some(NagiosRange::from(c_raw)?)
-                        }
-                        None => None,
-                    };
+    if matchers.is_empty() {
+        bail!("{}.matchers must contain at least one label matcher", field);
+    }
 
-                    ThresholdPair { warning, critical }
-                }
-            }
-            None => ThresholdPair::default(),
-        }
-    };
+    let thresholds = parse_threshold_pair(r_hash, field)?;
 
-    let interval: Duration = match items.get(&Yaml::from_str("interval")) {
-        Some(i) => {
-            let num = i.as_i64().ok_or(ParseFieldError {
-                field: format!("mappings.{}.interval", name),
-                kind: "number",
-            })?;
+    Ok(ThresholdRule {
+        matchers,
+        thresholds,
+    })
+}
 
-            let conv = u16::try_from(num).map_err(|_| ParseFieldError {
-                field: format!("mappings.{}.interval", name),
-                kind: "number",
-            })? as u64;
+/// Parses a list of label matchers in PromQL syntax.
+fn parse_label_matchers(matchers: &Yaml, field: &str) -> Result<Vec<LabelMatcher>, anyhow::Error> {
+    matchers
+        .as_vec()
+        .ok_or(ParseFieldError {
+            field: field.to_string(),
+            kind: "array",
+        })?
+        .iter()
+        .map(|m| {
+            let raw = m.as_str().ok_or(ParseFieldError {
+                field: field.to_string(),
+                kind: "string",
+            })?;
+            parse_label_matcher(raw).with_context(|| format!("failed to parse {}", field))
+        })
+        .collect()
+}
 
-            let valid_range = 10..=3600;
+/// Parses a label matcher in PromQL syntax, e.g. `mountpoint="/var"`
+/// or `device=~"sd.*"`. Regular expressions are fully anchored just as
+/// in PromQL.
+pub(crate) fn parse_label_matcher(raw: &str) -> Result<LabelMatcher, anyhow::Error> {
+    let pos = raw
+        .find(['=', '!'])
+        .ok_or_else(|| anyhow!("'{}' is not a valid label matcher", raw))?;
 
-            if !valid_range.contains(&conv) {
-                return Err(anyhow!(
-                    "mappings.{}.interval must be in the range {:?}, got {}",
-                    name,
-                    valid_range,
-                    conv
-                ));
-            }
+    let label = raw[..pos].trim().to_string();
 
-            Duration::from_secs(conv)
-        }
-        None => Duration::from_secs(60),
-    };
+    if label.is_empty()
+        || label.starts_with(|c: char| c.is_ascii_digit())
+        || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        bail!("'{}' is not a valid label name", label);
+    }
 
-    let performance_data = {
-        match items.get(&Yaml::from_str("performance_data")) {
-            Some(t) => {
-                let t_hash = t.as_hash().ok_or(ParseFieldError {
-                    field: format!("mappings.{}.performance_data", name),
-                    kind: "hash",
-                })?;
+    let rest = &raw[pos..];
+    let (op, value) = ["=~", "!~", "!=", "="]
+        .iter()
+        .find_map(|op| rest.strip_prefix(op).map(|value| (*op, value.trim())))
+        .ok_or_else(|| anyhow!("'{}' is not a valid label matcher", raw))?;
 
-                if t_hash.is_empty() {
-                    PerformanceData::default()
-                } else {
-                    let enabled = match t_hash.get(&Yaml::from_str("enabled")) {
-                        Some(val) => val.as_bool().ok_or(ParseFieldError {
-                            field: format!("mappings.{}.performance_data.enabled", name),
-                            kind: "boolean",
-                        })?,
-                        None => true,
-                    };
-
-                    let label = match t_hash.get(&Yaml::from_str("label")) {
-                        Some(val) => {
-                            let label = val
-                                .as_str()
-                                .ok_or(ParseFieldError {
-                                    field: format!("mappings.{}.performance_data.label", name),
-                                    kind: "string",
-                                })?
-                                .to_string();
-                            Some(label)
-                        }
-                        None => None,
-                    };
-
-                    let uom = match t_hash.get(&Yaml::from_str("uom")) {
-                        Some(val) => {
-                            let label = val
-                                .as_str()
-                                .ok_or(ParseFieldError {
-                                    field: format!("mappings.{}.performance_data.uom", name),
-                                    kind: "string",
-                                })?
-                                .to_string();
-                            Some(label)
-                        }
-                        None => None,
-                    };
+    // Quotes around the value are optional.
+    let value = ['"', '\'']
+        .iter()
+        .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+        .unwrap_or(value)
+        .to_string();
 
-                    PerformanceData {
-                        enabled,
-                        label,
-                        uom,
-                    }
-                }
+    let op = match op {
+        "=" => LabelMatchOp::Equal(value),
+        "!=" => LabelMatchOp::NotEqual(value),
+        _ => {
+            let regex = regex::Regex::new(&format!("^(?:{})$", value))
+                .with_context(|| format!("'{}' is not a valid regular expression", value))?;
+            if op == "=~" {
+                LabelMatchOp::Regex(regex)
+            } else {
+                LabelMatchOp::NotRegex(regex)
             }
-            None => PerformanceData::default(),
         }
     };
 
-    Ok(Mapping {
-        name,
-        query,
-        host,
-        service,
-        interval,
-        plugin_output,
-        thresholds,
-        performance_data,
-        last_apply: Instant::now(),
-    })
+    Ok(LabelMatcher { label, op })
+}
+
+/// Parses a timeout in seconds from YAML configuration. The timeout must be
+/// in the range 1..=3600.
+fn parse_timeout(value: &Yaml, field: &str) -> Result<Duration, anyhow::Error> {
+    let num = value.as_i64().ok_or(ParseFieldError {
+        field: field.to_string(),
+        kind: "number",
+    })?;
+
+    let valid_range = 1..=3600;
+
+    if !valid_range.contains(&num) {
+        return Err(anyhow!(
+            "{} must be in the range {:?}, got {}",
+            field,
+            valid_range,
+            num
+        ));
+    }
+
+    Ok(Duration::from_secs(num as u64))
 }
 
 /// Parses a multiple mappings from YAML configuration.
@@ -238,6 +1791,42 @@ fn parse_mapping(mapping: (&Yaml, &Yaml)) -> Result<Mapping, anyhow::Error> {
 pub(crate) fn parse_mappings(config: Hash) -> Result<Vec<Mapping>, anyhow::Error> {
     let mut mappings: Vec<Mapping> = vec![];
 
+    // Global maintenance windows apply to every mapping.
+    let global_maintenance = match config.get(&Yaml::from_str("maintenance")) {
+        Some(m) => parse_maintenance_windows(m, "maintenance")?,
+        None => vec![],
+    };
+
+    let grace_period = parse_grace_period(&config)?;
+
+    // Partials are shared by the plugin output templates of all mappings.
+    // Named templates may be referenced as plugin output and include each
+    // other, thus they are available as partials as well.
+    let named_templates = load_templates_dir(&config)?;
+    let mut partials = parse_partials(&config)?;
+    for (name, template) in named_templates.iter() {
+        if partials.insert(name.clone(), template.clone()).is_some() {
+            bail!(
+                "template '{}' from templates_dir conflicts with templates.partials.{}",
+                name,
+                name
+            );
+        }
+    }
+    let partials = Arc::new(partials);
+
+    // Global performance data settings are the defaults of every mapping.
+    let performance_data = match config.get(&Yaml::from_str("performance_data")) {
+        Some(p) => {
+            let p_hash = p.as_hash().ok_or(ParseFieldError {
+                field: String::from("performance_data"),
+                kind: "hash",
+            })?;
+            parse_performance_data(p_hash, "performance_data", &PerformanceData::default())?
+        }
+        None => PerformanceData::default(),
+    };
+
     match config.get(&Yaml::from_str("mappings")) {
         Some(m_raw) => {
             let mapping_hash = m_raw.as_hash().ok_or(ParseFieldError {
@@ -246,7 +1835,17 @@ pub(crate) fn parse_mappings(config: Hash) -> Result<Vec<Mapping>, anyhow::Error
             })?;
 
             for raw_mapping in mapping_hash {
-                let mapping = parse_mapping(raw_mapping)?;
+                let mut mapping =
+                    parse_mapping(raw_mapping, &global_maintenance, &performance_data)?;
+                mapping.grace_period = grace_period;
+                mapping.partials = partials.clone();
+                resolve_named_template(&mut mapping, &named_templates)?;
+                crate::util::validate_templates(&mapping).with_context(|| {
+                    format!(
+                        "failed to validate the templates of mappings.{} with synthetic data",
+                        mapping.name
+                    )
+                })?;
                 mappings.push(mapping);
             }
 
@@ -256,6 +1855,174 @@ pub(crate) fn parse_mappings(config: Hash) -> Result<Vec<Mapping>, anyhow::Error
     }
 }
 
+/// Parses the optional handlebars partials that may be used in the plugin
+/// output templates of every mapping, e.g. `{{> header }}`.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// templates:
+///   partials:
+///     '<name>': '<template>'
+/// ```
+fn parse_partials(config: &Hash) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("templates")) {
+        Some(t) => t.as_hash().ok_or(ParseFieldError {
+            field: String::from("templates"),
+            kind: "hash",
+        })?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    let partials = match section.get(&Yaml::from_str("partials")) {
+        Some(p) => p.as_hash().ok_or(ParseFieldError {
+            field: String::from("templates.partials"),
+            kind: "hash",
+        })?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    let mut result = BTreeMap::new();
+    for (name, template) in partials {
+        let name = name.as_str().ok_or(ParseFieldError {
+            field: String::from("templates.partials"),
+            kind: "hash with string keys",
+        })?;
+        let field = format!("templates.partials.{}", name);
+        let template = template.as_str().ok_or(ParseFieldError {
+            field: field.clone(),
+            kind: "string",
+        })?;
+        handlebars::Template::compile(template)
+            .with_context(|| format!("failed to parse {} as handlebars template", field))?;
+        result.insert(name.to_string(), template.to_string());
+    }
+    Ok(result)
+}
+
+/// Loads the named templates from the optional templates directory, i.e.
+/// every `*.hbs` file by its name without extension.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// templates_dir: '<path>'
+/// ```
+fn load_templates_dir(config: &Hash) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let dir = match config.get(&Yaml::from_str("templates_dir")) {
+        Some(d) => PathBuf::from(d.as_str().ok_or(ParseFieldError {
+            field: String::from("templates_dir"),
+            kind: "string",
+        })?),
+        None => return Ok(BTreeMap::new()),
+    };
+
+    let entries = std::fs::read_dir(&dir)
+        .with_context(|| format!("failed to read templates_dir {}", dir.display()))?;
+
+    let mut templates = BTreeMap::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read templates_dir {}", dir.display()))?
+            .path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("hbs") {
+            continue;
+        }
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let template = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read template {}", path.display()))?;
+        handlebars::Template::compile(&template).with_context(|| {
+            format!("failed to parse {} as handlebars template", path.display())
+        })?;
+        debug!("Loaded template '{}' from {}", name, path.display());
+        templates.insert(name, template);
+    }
+    Ok(templates)
+}
+
+/// Replaces a reference to a named template as plugin output of a mapping
+/// (e.g. `plugin_output: '@disk_usage'`) by the template itself.
+fn resolve_named_template(
+    mapping: &mut Mapping,
+    named_templates: &BTreeMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    let name = match mapping
+        .plugin_output
+        .as_deref()
+        .and_then(|p| p.strip_prefix('@'))
+    {
+        Some(name)
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.') =>
+        {
+            name
+        }
+        _ => return Ok(()),
+    };
+
+    let template = named_templates.get(name).ok_or_else(|| {
+        anyhow!(
+            "mappings.{}.plugin_output refers to template '{}' that does not exist in templates_dir",
+            mapping.name,
+            name
+        )
+    })?;
+    mapping.plugin_output = Some(template.clone());
+    Ok(())
+}
+
+/// Parses the optional grace period after startup from YAML configuration.
+/// The period starts with the daemon, so it is not restarted when the
+/// configuration is reloaded.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// grace_period:
+///   duration: <seconds>
+///   action: 'unknown'|'skip'   # optional
+/// ```
+fn parse_grace_period(config: &Hash) -> Result<Option<GracePeriod>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("grace_period")) {
+        Some(g) => g.as_hash().ok_or(ParseFieldError {
+            field: String::from("grace_period"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    let duration = section
+        .get(&Yaml::from_str("duration"))
+        .ok_or(MissingFieldError {
+            field: String::from("grace_period.duration"),
+        })?
+        .as_i64()
+        .ok_or(ParseFieldError {
+            field: String::from("grace_period.duration"),
+            kind: "number",
+        })?;
+    let duration = u64::try_from(duration).map_err(|_| ParseFieldError {
+        field: String::from("grace_period.duration"),
+        kind: "number",
+    })?;
+
+    let action = match section.get(&Yaml::from_str("action")) {
+        Some(a) => match a.as_str() {
+            Some("unknown") => GraceAction::Unknown,
+            Some("skip") => GraceAction::Skip,
+            _ => bail!("grace_period.action must be one of 'unknown' or 'skip'"),
+        },
+        None => GraceAction::Unknown,
+    };
+
+    Ok(Some(GracePeriod {
+        until: get_start_time() + Duration::from_secs(duration),
+        action,
+    }))
+}
+
 fn parse_proxy_section(config: &Hash, scheme: &str) -> Result<ProxyConfig, anyhow::Error> {
     let ignore = config
         .get(&Yaml::from_str("ignore"))
@@ -284,6 +2051,73 @@ fn parse_proxy_section(config: &Hash, scheme: &str) -> Result<ProxyConfig, anyho
     Ok(ProxyConfig { ignore, host })
 }
 
+/// Parses the TLS settings of either the Prometheus or Icinga client.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// tls:
+///   min_version: '1.2'|'1.3' # optional
+///   max_version: '1.2'|'1.3' # optional
+///   use_native_roots: <bool>  # optional
+/// ```
+fn parse_tls_section(config: &Hash, section: &str) -> Result<TlsConfig, anyhow::Error> {
+    let parse_version = |key: &str| -> Result<Option<reqwest::tls::Version>, anyhow::Error> {
+        let field = format!("{}.tls.{}", section, key);
+        match config.get(&Yaml::from_str(key)) {
+            Some(val) => {
+                // An unquoted version is parsed as a floating point number.
+                let raw = match val {
+                    Yaml::Real(r) => r.clone(),
+                    Yaml::String(s) => s.clone(),
+                    _ => {
+                        return Err(ParseFieldError {
+                            field,
+                            kind: "string",
+                        }
+                        .into())
+                    }
+                };
+                match raw.as_str() {
+                    "1.2" => Ok(Some(reqwest::tls::Version::TLS_1_2)),
+                    "1.3" => Ok(Some(reqwest::tls::Version::TLS_1_3)),
+                    _ => bail!(
+                        "invalid value in '{}', must be either '1.2' or '1.3'",
+                        field
+                    ),
+                }
+            }
+            None => Ok(None),
+        }
+    };
+
+    let min_version = parse_version("min_version")?;
+    let max_version = parse_version("max_version")?;
+
+    if let (Some(min), Some(max)) = (min_version, max_version) {
+        if min > max {
+            bail!(
+                "'{}.tls.min_version' must not be greater than '{}.tls.max_version'",
+                section,
+                section
+            );
+        }
+    }
+
+    let use_native_roots = match config.get(&Yaml::from_str("use_native_roots")) {
+        Some(val) => val.as_bool().ok_or(ParseFieldError {
+            field: format!("{}.tls.use_native_roots", section),
+            kind: "boolean",
+        })?,
+        None => false,
+    };
+
+    Ok(TlsConfig {
+        min_version,
+        max_version,
+        use_native_roots,
+    })
+}
+
 pub(crate) fn parse_prom_section(config: &Hash) -> Result<PromConfig, anyhow::Error> {
     let default_host = Url::parse("http://localhost:9090")?;
 
@@ -321,55 +2155,302 @@ pub(crate) fn parse_prom_section(config: &Hash) -> Result<PromConfig, anyhow::Er
                 None => ProxyConfig::default(),
             };
 
-            Ok(PromConfig { host, proxy })
+            let tls = match prometheus.get(&Yaml::from_str("tls")) {
+                Some(t) => t
+                    .as_hash()
+                    .ok_or(ParseFieldError {
+                        field: String::from("prometheus.tls"),
+                        kind: "hash",
+                    })
+                    .map_err(anyhow::Error::msg)
+                    .and_then(|s| parse_tls_section(s, "prometheus"))?,
+                None => TlsConfig::default(),
+            };
+
+            Ok(PromConfig { host, proxy, tls })
+        }
+        None => Ok(PromConfig {
+            host: default_host,
+            proxy: ProxyConfig::default(),
+            tls: TlsConfig::default(),
+        }),
+    }
+}
+
+/// Read an optional passphrase from either the environment, a file referenced
+/// by '<key>_file' or the parameter '<key>' itself (in this order of precedence).
+/// A trailing newline is stripped from the content of a passphrase file.
+fn parse_passphrase(
+    auth_hash: &Hash,
+    env_var: &str,
+    key: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    match env::var(env_var) {
+        Ok(val) => return Ok(Some(val)),
+        // The passphrase is simply not provided via the environment.
+        Err(env::VarError::NotPresent) => {}
+        Err(err) => debug!("failed to read passphrase from environment ('{env_var}'): {err}; try to read from configuration file instead"),
+    }
+
+    let file_key = format!("{}_file", key);
+
+    if let Some(val) = auth_hash.get(&Yaml::from_str(&file_key)) {
+        let path = val.as_str().ok_or(ParseFieldError {
+            field: format!("icinga.authentication.{}", file_key),
+            kind: "string",
+        })?;
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read passphrase from file '{}'", path))?;
+        return Ok(Some(
+            content.trim_end_matches(&['\r', '\n'][..]).to_string(),
+        ));
+    }
+
+    match auth_hash.get(&Yaml::from_str(key)) {
+        Some(val) => Ok(Some(
+            val.as_str()
+                .ok_or(ParseFieldError {
+                    field: format!("icinga.authentication.{}", key),
+                    kind: "string",
+                })?
+                .to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn parse_icinga_section(config: &Hash) -> Result<IcingaConfig, anyhow::Error> {
+    let section = {
+        let conf_attr = "icinga";
+        config
+            .get(&Yaml::from_str(conf_attr))
+            .ok_or(MissingFieldError {
+                field: String::from(conf_attr),
+            })?
+            .as_hash()
+            .ok_or(ParseFieldError {
+                field: String::from(conf_attr),
+                kind: "hash",
+            })?
+    };
+
+    let host = match section.get(&Yaml::from_str("host")) {
+        Some(h) => {
+            let raw = h.as_str().ok_or(ParseFieldError {
+                field: String::from("icinga.host"),
+                kind: "string",
+            })?;
+            let url = Url::parse(raw)?;
+            match url.scheme() {
+                "http" | "https" => url,
+                _ => bail!("the scheme in URL '{}' from field 'prometheus.host' must be either 'http' or 'https'", url)
+            }
+        }
+        None => Url::parse("https://localhost:5665")?,
+    };
+
+    let proxy = match section.get(&Yaml::from_str("proxy")) {
+        Some(p) => p
+            .as_hash()
+            .ok_or(ParseFieldError {
+                field: String::from("icinga.proxy"),
+                kind: "hash",
+            })
+            .map_err(anyhow::Error::msg)
+            .and_then(|s| parse_proxy_section(s, host.scheme()))?,
+        None => ProxyConfig::default(),
+    };
+
+    let tls = match section.get(&Yaml::from_str("tls")) {
+        Some(t) => t
+            .as_hash()
+            .ok_or(ParseFieldError {
+                field: String::from("icinga.tls"),
+                kind: "hash",
+            })
+            .map_err(anyhow::Error::msg)
+            .and_then(|s| parse_tls_section(s, "icinga"))?,
+        None => TlsConfig::default(),
+    };
+
+    let connect_timeout = match section.get(&Yaml::from_str("connect_timeout")) {
+        Some(t) => Some(parse_timeout(t, "icinga.connect_timeout")?),
+        None => None,
+    };
+
+    let request_timeout = match section.get(&Yaml::from_str("request_timeout")) {
+        Some(t) => Some(parse_timeout(t, "icinga.request_timeout")?),
+        None => None,
+    };
+
+    let rate_limit = match section.get(&Yaml::from_str("rate_limit")) {
+        Some(r) => {
+            let r_hash = r.as_hash().ok_or(ParseFieldError {
+                field: String::from("icinga.rate_limit"),
+                kind: "hash",
+            })?;
+
+            let requests_per_second = {
+                let conf_attr = "icinga.rate_limit.requests_per_second";
+                let val = r_hash.get(&Yaml::from_str("requests_per_second")).ok_or(
+                    MissingFieldError {
+                        field: String::from(conf_attr),
+                    },
+                )?;
+                let num = val
+                    .as_f64()
+                    .or_else(|| val.as_i64().map(|i| i as f64))
+                    .ok_or(ParseFieldError {
+                        field: String::from(conf_attr),
+                        kind: "number",
+                    })?;
+                if num <= 0.0 {
+                    bail!("{} must be greater than 0, got {}", conf_attr, num);
+                }
+                num
+            };
+
+            let burst = match r_hash.get(&Yaml::from_str("burst")) {
+                Some(b) => {
+                    let conf_attr = "icinga.rate_limit.burst";
+                    let num = b.as_i64().ok_or(ParseFieldError {
+                        field: String::from(conf_attr),
+                        kind: "number",
+                    })?;
+                    u32::try_from(num).ok().filter(|n| *n > 0).ok_or_else(|| {
+                        anyhow!("{} must be greater than 0, got {}", conf_attr, num)
+                    })?
+                }
+                None => 1,
+            };
+
+            Some(RateLimitConfig {
+                requests_per_second,
+                burst,
+            })
         }
-        None => Ok(PromConfig {
-            host: default_host,
-            proxy: ProxyConfig::default(),
-        }),
-    }
-}
+        None => None,
+    };
 
-pub(crate) fn parse_icinga_section(config: &Hash) -> Result<IcingaConfig, anyhow::Error> {
-    let section = {
-        let conf_attr = "icinga";
-        config
-            .get(&Yaml::from_str(conf_attr))
-            .ok_or(MissingFieldError {
-                field: String::from(conf_attr),
-            })?
-            .as_hash()
-            .ok_or(ParseFieldError {
-                field: String::from(conf_attr),
+    let reload_retry = match section.get(&Yaml::from_str("reload_retry")) {
+        Some(r) => {
+            let r_hash = r.as_hash().ok_or(ParseFieldError {
+                field: String::from("icinga.reload_retry"),
                 kind: "hash",
-            })?
+            })?;
+
+            let enabled = match r_hash.get(&Yaml::from_str("enabled")) {
+                Some(val) => val.as_bool().ok_or(ParseFieldError {
+                    field: String::from("icinga.reload_retry.enabled"),
+                    kind: "boolean",
+                })?,
+                None => true,
+            };
+
+            if enabled {
+                let default = ReloadRetryConfig::default();
+
+                let interval = match r_hash.get(&Yaml::from_str("interval")) {
+                    Some(i) => parse_timeout(i, "icinga.reload_retry.interval")?,
+                    None => default.interval,
+                };
+
+                let threshold = match r_hash.get(&Yaml::from_str("threshold")) {
+                    Some(t) => parse_timeout(t, "icinga.reload_retry.threshold")?,
+                    None => default.threshold,
+                };
+
+                Some(ReloadRetryConfig {
+                    interval,
+                    threshold,
+                })
+            } else {
+                None
+            }
+        }
+        None => Some(ReloadRetryConfig::default()),
     };
 
-    let host = match section.get(&Yaml::from_str("host")) {
-        Some(h) => {
-            let raw = h.as_str().ok_or(ParseFieldError {
-                field: String::from("icinga.host"),
-                kind: "string",
+    let max_payload_size = match section.get(&Yaml::from_str("max_payload_size")) {
+        Some(m) => {
+            let conf_attr = "icinga.max_payload_size";
+            let num = m.as_i64().ok_or(ParseFieldError {
+                field: String::from(conf_attr),
+                kind: "number",
             })?;
-            let url = Url::parse(raw)?;
-            match url.scheme() {
-                "http" | "https" => url,
-                _ => bail!("the scheme in URL '{}' from field 'prometheus.host' must be either 'http' or 'https'", url)
+            // Leave enough room for the mandatory parts of the payload.
+            if num < 1024 {
+                bail!("{} must be at least 1024 bytes, got {}", conf_attr, num);
             }
+            Some(num as usize)
         }
-        None => Url::parse("https://localhost:5665")?,
+        None => None,
     };
 
-    let proxy = match section.get(&Yaml::from_str("proxy")) {
-        Some(p) => p
-            .as_hash()
-            .ok_or(ParseFieldError {
-                field: String::from("icinga.proxy"),
+    let startup_check = match section.get(&Yaml::from_str("startup_check")) {
+        Some(val) => val.as_bool().ok_or(ParseFieldError {
+            field: String::from("icinga.startup_check"),
+            kind: "boolean",
+        })?,
+        None => true,
+    };
+
+    let audit_log = match section.get(&Yaml::from_str("audit_log")) {
+        Some(a) => {
+            let a_hash = a.as_hash().ok_or(ParseFieldError {
+                field: String::from("icinga.audit_log"),
                 kind: "hash",
+            })?;
+
+            let path = a_hash
+                .get(&Yaml::from_str("path"))
+                .ok_or(MissingFieldError {
+                    field: String::from("icinga.audit_log.path"),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: String::from("icinga.audit_log.path"),
+                    kind: "string",
+                })?;
+            if path.is_empty() {
+                bail!("icinga.audit_log.path cannot be empty");
+            }
+
+            let max_size = match a_hash.get(&Yaml::from_str("max_size")) {
+                Some(m) => {
+                    let conf_attr = "icinga.audit_log.max_size";
+                    let num = m.as_i64().ok_or(ParseFieldError {
+                        field: String::from(conf_attr),
+                        kind: "number",
+                    })?;
+                    if num < 65536 {
+                        bail!("{} must be at least 65536 bytes, got {}", conf_attr, num);
+                    }
+                    num as u64
+                }
+                None => 10 * 1024 * 1024,
+            };
+
+            let max_files = match a_hash.get(&Yaml::from_str("max_files")) {
+                Some(m) => {
+                    let conf_attr = "icinga.audit_log.max_files";
+                    let num = m.as_i64().ok_or(ParseFieldError {
+                        field: String::from(conf_attr),
+                        kind: "number",
+                    })?;
+                    usize::try_from(num)
+                        .map_err(|_| anyhow!("{} must not be negative, got {}", conf_attr, num))?
+                }
+                None => 5,
+            };
+
+            Some(AuditLogConfig {
+                path: PathBuf::from(path),
+                max_size,
+                max_files,
             })
-            .map_err(anyhow::Error::msg)
-            .and_then(|s| parse_proxy_section(s, host.scheme()))?,
-        None => ProxyConfig::default(),
+        }
+        None => None,
     };
 
     let ca_cert = match section.get(&Yaml::from_str("ca_cert")) {
@@ -462,42 +2543,72 @@ pub(crate) fn parse_icinga_section(config: &Hash) -> Result<IcingaConfig, anyhow
 
             IcingaAuth::Basic(IcingaBasicAuth { username, password })
         }
-        "x509" => {
-            let client_cert = {
-                let conf_attr = "icinga.authentication.client_cert";
-                auth_hash
-                    .get(&Yaml::from_str("client_cert"))
-                    .ok_or(MissingFieldError {
-                        field: String::from(conf_attr),
-                    })?
+        "x509" => match auth_hash.get(&Yaml::from_str("client_p12")) {
+            Some(p12) => {
+                let client_p12 = p12
                     .as_str()
                     .ok_or(ParseFieldError {
-                        field: String::from(conf_attr),
+                        field: String::from("icinga.authentication.client_p12"),
                         kind: "string",
                     })
-                    .map(PathBuf::from)?
-            };
+                    .map(PathBuf::from)?;
 
-            let client_key = {
-                let conf_attr = "icinga.authentication.client_key";
-                auth_hash
-                    .get(&Yaml::from_str("client_key"))
-                    .ok_or(MissingFieldError {
-                        field: String::from(conf_attr),
-                    })?
-                    .as_str()
-                    .ok_or(ParseFieldError {
-                        field: String::from(conf_attr),
-                        kind: "string",
-                    })
-                    .map(PathBuf::from)?
-            };
+                let passphrase = parse_passphrase(
+                    auth_hash,
+                    "V2C_ICINGA_P12_PASSPHRASE",
+                    "client_p12_passphrase",
+                )?
+                .unwrap_or_default();
 
-            IcingaAuth::X509(IcingaX509Auth {
-                client_cert,
-                client_key,
-            })
-        }
+                IcingaAuth::X509(IcingaX509Auth::Pkcs12(IcingaPkcs12Identity {
+                    client_p12,
+                    passphrase,
+                }))
+            }
+            None => {
+                let client_cert = {
+                    let conf_attr = "icinga.authentication.client_cert";
+                    auth_hash
+                        .get(&Yaml::from_str("client_cert"))
+                        .ok_or(MissingFieldError {
+                            field: String::from(conf_attr),
+                        })?
+                        .as_str()
+                        .ok_or(ParseFieldError {
+                            field: String::from(conf_attr),
+                            kind: "string",
+                        })
+                        .map(PathBuf::from)?
+                };
+
+                let client_key = {
+                    let conf_attr = "icinga.authentication.client_key";
+                    auth_hash
+                        .get(&Yaml::from_str("client_key"))
+                        .ok_or(MissingFieldError {
+                            field: String::from(conf_attr),
+                        })?
+                        .as_str()
+                        .ok_or(ParseFieldError {
+                            field: String::from(conf_attr),
+                            kind: "string",
+                        })
+                        .map(PathBuf::from)?
+                };
+
+                let passphrase = parse_passphrase(
+                    auth_hash,
+                    "V2C_ICINGA_KEY_PASSPHRASE",
+                    "client_key_passphrase",
+                )?;
+
+                IcingaAuth::X509(IcingaX509Auth::Pem(IcingaPemIdentity {
+                    client_cert,
+                    client_key,
+                    passphrase,
+                }))
+            }
+        },
         _ => {
             bail!(
                     "invalid value in 'icinga.authentication.method', must be either 'basic-auth' or 'x509'"
@@ -510,9 +2621,388 @@ pub(crate) fn parse_icinga_section(config: &Hash) -> Result<IcingaConfig, anyhow
         ca_cert,
         authentication,
         proxy,
+        tls,
+        connect_timeout,
+        request_timeout,
+        rate_limit,
+        reload_retry,
+        max_payload_size,
+        startup_check,
+        audit_log,
     })
 }
 
+/// Parses the list of backends that check results are submitted to.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// backends:
+///   - type: 'icinga'
+///   - type: 'command_file'
+///     path: '<path_to_command_file>'
+///   - type: 'checkresult_spool'
+///     path: '<path_to_spool_directory>'
+///   - type: 'sensu'
+///     url: '<agent_events_api_url>'    # optional
+///     handlers: ['<handler>']          # optional
+///   - type: 'stdout'
+/// ```
+///
+/// When the section is missing check results are only sent to the Icinga API.
+pub(crate) fn parse_backends_section(config: &Hash) -> Result<Vec<BackendConfig>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("backends")) {
+        Some(b) => b.as_vec().ok_or(ParseFieldError {
+            field: String::from("backends"),
+            kind: "array",
+        })?,
+        None => return Ok(vec![BackendConfig::Icinga]),
+    };
+
+    if section.is_empty() {
+        bail!("'backends' must contain at least one backend");
+    }
+
+    let mut backends = vec![];
+
+    for (index, item) in section.iter().enumerate() {
+        let backend = item.as_hash().ok_or(ParseFieldError {
+            field: format!("backends.{}", index),
+            kind: "hash",
+        })?;
+
+        let backend_type = {
+            let conf_attr = format!("backends.{}.type", index);
+            backend
+                .get(&Yaml::from_str("type"))
+                .ok_or(MissingFieldError {
+                    field: conf_attr.clone(),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: conf_attr,
+                    kind: "string",
+                })?
+        };
+
+        let path = || -> Result<PathBuf, anyhow::Error> {
+            let conf_attr = format!("backends.{}.path", index);
+            let path = backend
+                .get(&Yaml::from_str("path"))
+                .ok_or(MissingFieldError {
+                    field: conf_attr.clone(),
+                })?
+                .as_str()
+                .ok_or(ParseFieldError {
+                    field: conf_attr,
+                    kind: "string",
+                })
+                .map(PathBuf::from)?;
+            Ok(path)
+        };
+
+        let config = match backend_type {
+            "icinga" => BackendConfig::Icinga,
+            "command_file" => BackendConfig::CommandFile(path()?),
+            "checkresult_spool" => BackendConfig::CheckResultSpool(path()?),
+            "sensu" => {
+                let url = match backend.get(&Yaml::from_str("url")) {
+                    Some(u) => {
+                        let raw = u.as_str().ok_or(ParseFieldError {
+                            field: format!("backends.{}.url", index),
+                            kind: "string",
+                        })?;
+                        let url = Url::parse(raw)?;
+                        match url.scheme() {
+                            "http" | "https" => url,
+                            _ => bail!("the scheme in URL '{}' from field 'backends.{}.url' must be either 'http' or 'https'", url, index)
+                        }
+                    }
+                    None => Url::parse("http://127.0.0.1:3031/events")?,
+                };
+
+                let handlers = match backend.get(&Yaml::from_str("handlers")) {
+                    Some(h) => {
+                        let conf_attr = format!("backends.{}.handlers", index);
+                        h.as_vec()
+                            .ok_or(ParseFieldError {
+                                field: conf_attr.clone(),
+                                kind: "array",
+                            })?
+                            .iter()
+                            .map(|handler| {
+                                handler.as_str().map(String::from).ok_or(ParseFieldError {
+                                    field: conf_attr.clone(),
+                                    kind: "array of strings",
+                                })
+                            })
+                            .collect::<Result<Vec<String>, ParseFieldError>>()?
+                    }
+                    None => vec![],
+                };
+
+                BackendConfig::Sensu(SensuConfig { url, handlers })
+            }
+            "stdout" => BackendConfig::Stdout,
+            _ => bail!(
+                "invalid value in 'backends.{}.type', must be one of 'icinga', 'command_file', 'checkresult_spool', 'sensu' or 'stdout'",
+                index
+            ),
+        };
+
+        backends.push(config);
+    }
+
+    Ok(backends)
+}
+
+/// Parses the optional heartbeat section from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// heartbeat:
+///   host: '<host_object>'
+///   service: '<service_object>'   # optional
+///   interval: <heartbeat_interval> # optional
+/// ```
+pub(crate) fn parse_heartbeat_section(
+    config: &Hash,
+) -> Result<Option<HeartbeatConfig>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("heartbeat")) {
+        Some(h) => h.as_hash().ok_or(ParseFieldError {
+            field: String::from("heartbeat"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    let host = section
+        .get(&Yaml::from_str("host"))
+        .ok_or(MissingFieldError {
+            field: String::from("heartbeat.host"),
+        })?
+        .as_str()
+        .ok_or(ParseFieldError {
+            field: String::from("heartbeat.host"),
+            kind: "string",
+        })?
+        .to_string();
+
+    let service = match section.get(&Yaml::from_str("service")) {
+        Some(s) => Some(
+            s.as_str()
+                .ok_or(ParseFieldError {
+                    field: String::from("heartbeat.service"),
+                    kind: "string",
+                })?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let interval = match section.get(&Yaml::from_str("interval")) {
+        Some(i) => {
+            let interval = parse_timeout(i, "heartbeat.interval")?;
+            if interval < Duration::from_secs(10) {
+                bail!("heartbeat.interval must be in the range 10..=3600");
+            }
+            interval
+        }
+        None => Duration::from_secs(60),
+    };
+
+    Ok(Some(HeartbeatConfig {
+        host,
+        service,
+        interval,
+    }))
+}
+
+/// Parses the optional admin section from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// admin:
+///   socket: '<path>'
+/// ```
+pub(crate) fn parse_admin_section(config: &Hash) -> Result<Option<PathBuf>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("admin")) {
+        Some(a) => a.as_hash().ok_or(ParseFieldError {
+            field: String::from("admin"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    let socket = section
+        .get(&Yaml::from_str("socket"))
+        .ok_or(MissingFieldError {
+            field: String::from("admin.socket"),
+        })?
+        .as_str()
+        .ok_or(ParseFieldError {
+            field: String::from("admin.socket"),
+            kind: "string",
+        })?;
+
+    if socket.is_empty() {
+        bail!("admin.socket cannot be empty");
+    }
+
+    Ok(Some(PathBuf::from(socket)))
+}
+
+/// Parses the optional interval of the summary that is regularly logged.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// log_summary:
+///   interval: <summary_interval> # optional
+/// ```
+pub(crate) fn parse_log_summary_section(config: &Hash) -> Result<Option<Duration>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("log_summary")) {
+        Some(s) => s.as_hash().ok_or(ParseFieldError {
+            field: String::from("log_summary"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    match section.get(&Yaml::from_str("interval")) {
+        Some(i) => {
+            let num = i.as_i64().ok_or(ParseFieldError {
+                field: String::from("log_summary.interval"),
+                kind: "number",
+            })?;
+            if num < 60 {
+                bail!("log_summary.interval must be at least 60, got {}", num);
+            }
+            Ok(Some(Duration::from_secs(num as u64)))
+        }
+        None => Ok(Some(Duration::from_secs(900))),
+    }
+}
+
+/// Parses the optional tracing section from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// tracing:
+///   endpoint: '<otlp_http_traces_url>'
+///   service_name: '<name>'             # optional
+/// ```
+pub(crate) fn parse_tracing_section(config: &Hash) -> Result<Option<TracingConfig>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("tracing")) {
+        Some(t) => t.as_hash().ok_or(ParseFieldError {
+            field: String::from("tracing"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    let endpoint = section
+        .get(&Yaml::from_str("endpoint"))
+        .ok_or(MissingFieldError {
+            field: String::from("tracing.endpoint"),
+        })?
+        .as_str()
+        .ok_or(ParseFieldError {
+            field: String::from("tracing.endpoint"),
+            kind: "string",
+        })?;
+    let endpoint =
+        Url::parse(endpoint).with_context(|| "failed to parse tracing.endpoint as URL")?;
+
+    let service_name = match section.get(&Yaml::from_str("service_name")) {
+        Some(s) => s
+            .as_str()
+            .ok_or(ParseFieldError {
+                field: String::from("tracing.service_name"),
+                kind: "string",
+            })?
+            .to_string(),
+        None => String::from("vec2checkd"),
+    };
+
+    Ok(Some(TracingConfig {
+        endpoint,
+        service_name,
+    }))
+}
+
+/// Parses the optional metrics section from YAML configuration.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// metrics:
+///   listen: '<address>:<port>'   # optional
+/// ```
+pub(crate) fn parse_metrics_section(config: &Hash) -> Result<Option<MetricsConfig>, anyhow::Error> {
+    let section = match config.get(&Yaml::from_str("metrics")) {
+        Some(m) => m.as_hash().ok_or(ParseFieldError {
+            field: String::from("metrics"),
+            kind: "hash",
+        })?,
+        None => return Ok(None),
+    };
+
+    let listen = match section.get(&Yaml::from_str("listen")) {
+        Some(l) => l
+            .as_str()
+            .ok_or(ParseFieldError {
+                field: String::from("metrics.listen"),
+                kind: "string",
+            })?
+            .parse()
+            .with_context(|| "failed to parse metrics.listen as socket address")?,
+        None => SocketAddr::from(([127, 0, 0, 1], 9848)),
+    };
+
+    Ok(Some(MetricsConfig { listen }))
+}
+
+/// Parses the optional path of the file that the state of the mappings is
+/// persisted to.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// state_file: '<path>'
+/// ```
+pub(crate) fn parse_state_file(config: &Hash) -> Result<Option<PathBuf>, anyhow::Error> {
+    match config.get(&Yaml::from_str("state_file")) {
+        Some(p) => {
+            let path = p.as_str().ok_or(ParseFieldError {
+                field: String::from("state_file"),
+                kind: "string",
+            })?;
+            if path.is_empty() {
+                bail!("state_file cannot be empty");
+            }
+            Ok(Some(PathBuf::from(path)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Parses the optional log level from YAML configuration, which has the
+/// same format as the `--log-level` flag.
+/// This YAML is expected to have the following format:
+///
+/// ```yaml
+/// log_level: 'info,icinga=debug'
+/// ```
+pub(crate) fn parse_log_level(config: &Hash) -> Result<Option<LogLevel>, anyhow::Error> {
+    match config.get(&Yaml::from_str("log_level")) {
+        Some(l) => {
+            let level = l.as_str().ok_or(ParseFieldError {
+                field: String::from("log_level"),
+                kind: "string",
+            })?;
+            Ok(Some(level.parse()?))
+        }
+        None => Ok(None),
+    }
+}
+
 pub(crate) fn parse_yaml(source: &str) -> Result<Hash, anyhow::Error> {
     yaml_rust::yaml::YamlLoader::load_from_str(source)?[0]
         .clone()
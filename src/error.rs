@@ -36,38 +36,20 @@ impl<'a> fmt::Display for ParseFieldError<'a> {
 
 impl<'a> Error for ParseFieldError<'a> {}
 
+/// The check result could not be sent to one or more backends.
 #[derive(Debug)]
-pub(crate) struct InvalidPluginOutputError<'a> {
-    pub mapping_name: String,
-    pub reference: &'a str,
+pub(crate) struct SubmissionError {
+    pub failed: Vec<String>,
 }
 
-impl<'a> fmt::Display for InvalidPluginOutputError<'a> {
+impl fmt::Display for SubmissionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "configuration parameter '{}.plugin_output' is invalid as it references the non-existent parameter '{}.{}' using the placeholder '${}'",
-            self.mapping_name, self.mapping_name, self.reference, self.reference
+            "failed to send passive check result to {}",
+            self.failed.join("; ")
         )
     }
 }
 
-impl<'a> Error for InvalidPluginOutputError<'a> {}
-
-#[derive(Debug)]
-pub(crate) struct MissingLabelError {
-    pub identifier: String,
-    pub label: String,
-}
-
-impl<'a> fmt::Display for MissingLabelError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "failed to replace plugin output placeholder '{}' with value of label '{}'",
-            self.identifier, self.label
-        )
-    }
-}
-
-impl Error for MissingLabelError {}
+impl Error for SubmissionError {}
@@ -0,0 +1,189 @@
+use crate::types::{
+    CheckResult, DefaultOutput, HeartbeatConfig, Mapping, MappingState, NoData, NonFinitePolicy,
+    PerformanceData, ResultCount, TemplateEngine, ThresholdPair, ThresholdTemplates,
+};
+use crate::util::compute_delta;
+use std::time::{Duration, Instant};
+
+/// Keeps track of the health of the daemon itself, i.e. the number of checks
+/// that were executed and failed since the last heartbeat, and regularly
+/// turns it into a check result for a dedicated Icinga object. When the
+/// daemon dies or hangs the object becomes stale and falls back to its
+/// default state once the TTL of the last heartbeat expires.
+pub(crate) struct Heartbeat {
+    mapping: Mapping,
+    executed: usize,
+    failed: usize,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(config: HeartbeatConfig) -> Self {
+        // The heartbeat is sent to the backends just like the result of
+        // any other mapping, so it is represented by a mapping as well.
+        let mapping = Mapping {
+            name: "vec2checkd heartbeat".to_string(),
+            query: String::new(),
+            thresholds: ThresholdPair::default(),
+            host: config.host,
+            service: config.service,
+            interval: config.interval,
+            // Send the first heartbeat after the first interval has passed.
+            last_apply: Instant::now(),
+            plugin_output: None,
+            performance_data: PerformanceData::default(),
+            sync_vars: false,
+            request_timeout: None,
+            basic_auth: None,
+            state: MappingState::default(),
+            threshold_rules: vec![],
+            baseline_query: None,
+            no_data: NoData::default(),
+            non_finite: NonFinitePolicy::default(),
+            result_count: ResultCount::default(),
+            aggregate: None,
+            transform: None,
+            breach_cycles: 1,
+            flapping: None,
+            state_map: [0, 1, 2, 3],
+            maintenance: vec![],
+            label_states: None,
+            state_from_label: None,
+            include: vec![],
+            exclude: vec![],
+            deduplicate: None,
+            derive: None,
+            default_output: DefaultOutput::default(),
+            expect: None,
+            conditions: None,
+            threshold_templates: ThresholdTemplates::default(),
+            grace_period: None,
+            escalate_after: None,
+            count_breaching: None,
+            group_by: vec![],
+            ignore_unchanged: false,
+            clamp_min: None,
+            clamp_max: None,
+            precision: None,
+            query_error_state: None,
+            partials: Default::default(),
+            plugin_output_strict: true,
+            template_engine: TemplateEngine::default(),
+            max_output_length: None,
+        };
+
+        Heartbeat {
+            mapping,
+            executed: 0,
+            failed: 0,
+        }
+    }
+
+    pub(crate) fn mapping(&self) -> &Mapping {
+        &self.mapping
+    }
+
+    /// Record the outcome of a single check.
+    pub(crate) fn record(&mut self, success: bool) {
+        self.executed += 1;
+        if !success {
+            self.failed += 1;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn delta(&self) -> Duration {
+        compute_delta(&self.mapping)
+    }
+
+    /// Build the check result from the outcomes recorded since the last
+    /// heartbeat and reset the counters.
+    /// `overdue` is the number of mappings that are due for execution but
+    /// have not been executed yet.
+    pub(crate) fn check_result(
+        &mut self,
+        mappings: &[Mapping],
+        overdue: usize,
+        timestamp: f64,
+    ) -> CheckResult {
+        let updates_service = self.mapping.service.is_some();
+
+        let exit_value = if self.failed > 0 && updates_service {
+            1
+        } else {
+            0
+        };
+
+        let exit_status = crate::icinga::exit_value_to_status(updates_service, &exit_value);
+
+        let plugin_output = format!(
+            "[{}] vec2checkd v{} processes {} mapping(s); {} of {} check(s) failed since the last heartbeat, {} check(s) overdue",
+            exit_status,
+            crate::VERSION,
+            mappings.len(),
+            self.failed,
+            self.executed,
+            overdue
+        );
+
+        let performance_data = vec![
+            format!("'mappings'={};;;0;", mappings.len()),
+            format!("'checks'={};;;0;", self.executed),
+            format!("'failed'={};;;0;", self.failed),
+            format!("'overdue'={};;;0;", overdue),
+        ];
+
+        self.mapping.last_apply = Instant::now();
+        self.executed = 0;
+        self.failed = 0;
+
+        CheckResult {
+            exit_value,
+            plugin_output,
+            performance_data: Some(performance_data),
+            execution_start: timestamp,
+            execution_end: timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_check_result() {
+        let mut heartbeat = Heartbeat::new(HeartbeatConfig {
+            host: "foo".to_string(),
+            service: Some("vec2checkd".to_string()),
+            interval: Duration::from_secs(60),
+        });
+
+        heartbeat.record(true);
+        heartbeat.record(false);
+        heartbeat.record(true);
+
+        let mappings = vec![heartbeat.mapping().clone()];
+        let result = heartbeat.check_result(&mappings, 0, 1640995200.0);
+        assert_eq!(result.exit_value, 1);
+        assert_eq!(
+            result.plugin_output,
+            format!(
+                "[WARNING] vec2checkd v{} processes 1 mapping(s); 1 of 3 check(s) failed since the last heartbeat, 0 check(s) overdue",
+                crate::VERSION
+            )
+        );
+
+        // The counters are reset after each heartbeat.
+        let result = heartbeat.check_result(&mappings, 2, 1640995260.0);
+        assert_eq!(result.exit_value, 0);
+        assert_eq!(
+            result.performance_data,
+            Some(vec![
+                "'mappings'=1;;;0;".to_string(),
+                "'checks'=0;;;0;".to_string(),
+                "'failed'=0;;;0;".to_string(),
+                "'overdue'=2;;;0;".to_string(),
+            ])
+        );
+    }
+}
@@ -1,6 +1,240 @@
+use crate::icinga::{check_threshold_pair, exit_value_to_status};
+use crate::types::{Mapping, ThresholdPair};
 use handlebars::{
-    Context as HandlebarsContext, Handlebars, Helper, Output, RenderContext, RenderError,
+    BlockContext, Context as HandlebarsContext, Handlebars, Helper, HelperDef, JsonRender,
+    JsonValue, Output, RenderContext, RenderError, Renderable, ScopedJson, StringOutput,
 };
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Register all custom helpers, so that they are available in every
+/// template.
+pub(crate) fn register_helpers(handlebars: &mut Handlebars) {
+    handlebars.register_helper("truncate", Box::new(truncate));
+    handlebars.register_helper("humanize_bytes", Box::new(humanize_bytes));
+    handlebars.register_helper("humanize_duration", Box::new(humanize_duration));
+    handlebars.register_helper("percent", Box::new(percent));
+    handlebars.register_helper("round", Box::new(Rounding(f64::round)));
+    handlebars.register_helper("floor", Box::new(Rounding(f64::floor)));
+    handlebars.register_helper("ceil", Box::new(Rounding(f64::ceil)));
+    handlebars.register_helper("add", Box::new(Arithmetic(|a, b| Some(a + b))));
+    handlebars.register_helper("sub", Box::new(Arithmetic(|a, b| Some(a - b))));
+    handlebars.register_helper("mul", Box::new(Arithmetic(|a, b| Some(a * b))));
+    handlebars.register_helper(
+        "div",
+        Box::new(Arithmetic(|a, b| if b == 0.0 { None } else { Some(a / b) })),
+    );
+    handlebars.register_helper("sort_by", Box::new(SortBy));
+    handlebars.register_helper("filter", Box::new(Filter));
+    handlebars.register_helper("group_by", Box::new(GroupBy));
+    handlebars.register_helper("sum", Box::new(Aggregate(|v| Some(v.iter().sum()))));
+    handlebars.register_helper(
+        "avg",
+        Box::new(Aggregate(|v| {
+            if v.is_empty() {
+                None
+            } else {
+                Some(v.iter().sum::<f64>() / v.len() as f64)
+            }
+        })),
+    );
+    handlebars.register_helper(
+        "min",
+        Box::new(Aggregate(|v| v.iter().copied().reduce(f64::min))),
+    );
+    handlebars.register_helper(
+        "max",
+        Box::new(Aggregate(|v| v.iter().copied().reduce(f64::max))),
+    );
+    handlebars.register_helper("count", Box::new(Aggregate(|v| Some(v.len() as f64))));
+    handlebars.register_helper("regex_extract", Box::new(RegexExtract));
+    handlebars.register_helper("regex_replace", Box::new(RegexReplace));
+    handlebars.register_helper("join", Box::new(Join));
+    handlebars.register_helper("upper", Box::new(StringTransform(str::to_uppercase)));
+    handlebars.register_helper("lower", Box::new(StringTransform(str::to_lowercase)));
+    handlebars.register_helper("trim", Box::new(StringTransform(|s| s.trim().to_string())));
+    handlebars.register_helper("truncate_chars", Box::new(TruncateChars));
+    handlebars.register_helper("len", Box::new(Len));
+    handlebars.register_helper("first", Box::new(Element(<[JsonValue]>::first)));
+    handlebars.register_helper("last", Box::new(Element(<[JsonValue]>::last)));
+    handlebars.register_helper("json", Box::new(json));
+}
+
+/// Register the helpers that depend on the configuration of a mapping,
+/// e.g. its thresholds.
+pub(crate) fn register_mapping_helpers(handlebars: &mut Handlebars, mapping: &Mapping) {
+    handlebars.register_helper(
+        "threshold_check",
+        Box::new(ThresholdCheck {
+            thresholds: mapping.thresholds.clone(),
+        }),
+    );
+    handlebars.register_helper(
+        "state_for_value",
+        Box::new(StateForValue {
+            thresholds: mapping.thresholds.clone(),
+            updates_service: mapping.service.is_some(),
+        }),
+    );
+}
+
+/// Read the parameter at the given position as floating point number.
+/// Strings are parsed as well, as label values are always strings.
+fn float_param(h: &Helper, idx: usize) -> Result<f64, RenderError> {
+    let value = h
+        .param(idx)
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": missing a floating point number as parameter {}",
+                h.name(),
+                idx
+            ))
+        })?
+        .value();
+    value
+        .as_f64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse::<f64>().ok()))
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": failed to parse parameter {} as float",
+                h.name(),
+                idx
+            ))
+        })
+}
+
+/// Convert a number to a JSON value that is rendered without a fractional
+/// part when there is none (5.0_f64 => 5), just like "truncate" does.
+fn number_to_json(num: f64) -> JsonValue {
+    if num.fract() == 0.0 && num.abs() < i64::MAX as f64 {
+        JsonValue::from(num as i64)
+    } else {
+        JsonValue::from(num)
+    }
+}
+
+/// Read the parameter at the given position as array.
+fn array_param<'a>(h: &'a Helper, idx: usize) -> Result<&'a Vec<JsonValue>, RenderError> {
+    h.param(idx)
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": missing an array as parameter {}",
+                h.name(),
+                idx
+            ))
+        })?
+        .value()
+        .as_array()
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": failed to parse parameter {} as array",
+                h.name(),
+                idx
+            ))
+        })
+}
+
+/// Read the parameter at the given position as string.
+fn str_param<'a>(h: &'a Helper, idx: usize) -> Result<&'a str, RenderError> {
+    h.param(idx)
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": missing a string as parameter {}",
+                h.name(),
+                idx
+            ))
+        })?
+        .value()
+        .as_str()
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": failed to parse parameter {} as string",
+                h.name(),
+                idx
+            ))
+        })
+}
+
+/// Look up a value by a dotted path, e.g. "labels.node".
+fn lookup<'a>(value: &'a JsonValue, path: &str) -> Option<&'a JsonValue> {
+    path.split('.')
+        .try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+/// Render the block of a block helper once per item, the item being the
+/// context within the block (with `@index`, `@first` and `@last`, just like
+/// "#each"). The `{{else}}` block is rendered if there are no items.
+fn render_block<'reg: 'rc, 'rc>(
+    items: Vec<JsonValue>,
+    h: &Helper<'reg, 'rc>,
+    r: &'reg Handlebars<'reg>,
+    ctx: &'rc HandlebarsContext,
+    rc: &mut RenderContext<'reg, 'rc>,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let template = h.template().ok_or_else(|| {
+        RenderError::new(format!(
+            "Helper \"{}\": must be used as block helper or subexpression",
+            h.name()
+        ))
+    })?;
+
+    if items.is_empty() {
+        if let Some(inverse) = h.inverse() {
+            inverse.render(r, ctx, rc, out)?;
+        }
+        return Ok(());
+    }
+
+    rc.push_block(BlockContext::new());
+    let len = items.len();
+    for (i, item) in items.into_iter().enumerate() {
+        set_block_item(rc, i, len, item);
+        template.render(r, ctx, rc, out)?;
+    }
+    rc.pop_block();
+    Ok(())
+}
+
+/// Make an item the context of the current block, see `render_block`.
+fn set_block_item(rc: &mut RenderContext, index: usize, len: usize, item: JsonValue) {
+    if let Some(block) = rc.block_mut() {
+        block.set_local_var("index", JsonValue::from(index));
+        block.set_local_var("first", JsonValue::from(index == 0));
+        block.set_local_var("last", JsonValue::from(index == len - 1));
+        block.set_base_value(item);
+    }
+}
+
+/// Compile the regular expression at the given parameter position.
+fn regex_param(h: &Helper, idx: usize) -> Result<Regex, RenderError> {
+    let pattern = str_param(h, idx)?;
+    Regex::new(pattern).map_err(|e| {
+        RenderError::new(format!(
+            "Helper \"{}\": failed to compile regular expression \"{}\": {}",
+            h.name(),
+            pattern,
+            e
+        ))
+    })
+}
+
+/// Read the optional 'prec' hash parameter as number of decimals.
+fn precision_param(h: &Helper, default: usize) -> Result<usize, RenderError> {
+    match h.hash_get("prec") {
+        None => Ok(default),
+        Some(p) => {
+            let i = p.value().as_u64().ok_or_else(|| {
+                RenderError::new(format!(
+                    "Helper \"{}\": failed to parse parameter 'prec' as integer",
+                    h.name()
+                ))
+            })?;
+            Ok(usize::try_from(i).unwrap())
+        }
+    }
+}
 
 /// A handlebars helper that convert a floating point number to a string and truncates
 /// it at a given number of decimals.
@@ -52,10 +286,755 @@ pub(crate) fn truncate(
     Ok(())
 }
 
+/// A handlebars helper that formats a number of bytes in a human-readable
+/// way, e.g. 1234567 => "1.2 MiB". Binary (IEC) units are used by default,
+/// decimal (SI) units with `units="si"`. The number of decimals defaults
+/// to 1 and can be changed with `prec`.
+pub(crate) fn humanize_bytes(
+    h: &Helper,
+    _: &Handlebars,
+    _: &HandlebarsContext,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let bytes = float_param(h, 0)?;
+    let precision = precision_param(h, 1)?;
+
+    let (base, units) = match h.hash_get("units").map(|u| u.value().as_str()) {
+        None | Some(Some("iec")) => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+        Some(Some("si")) => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"]),
+        Some(_) => {
+            return Err(RenderError::new(format!(
+                "Helper \"{}\": parameter 'units' must be either \"iec\" or \"si\"",
+                h.name()
+            )))
+        }
+    };
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        out.write(&format!("{} {}", value, units[unit]))?;
+    } else {
+        out.write(&format!(
+            "{num:.prec$} {unit}",
+            num = value,
+            prec = precision,
+            unit = units[unit]
+        ))?;
+    }
+    Ok(())
+}
+
+/// A handlebars helper that formats a number of seconds in a human-readable
+/// way, e.g. 186300 => "2d 3h 45m". Only the most significant units are
+/// shown, by default 3 (change with `parts`). Durations below one second
+/// are shown in milliseconds.
+pub(crate) fn humanize_duration(
+    h: &Helper,
+    _: &Handlebars,
+    _: &HandlebarsContext,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let seconds = float_param(h, 0)?;
+    let parts = match h.hash_get("parts") {
+        None => 3,
+        Some(p) => p.value().as_u64().filter(|p| *p > 0).ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": failed to parse parameter 'parts' as positive integer",
+                h.name()
+            ))
+        })? as usize,
+    };
+
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let seconds = seconds.abs();
+
+    if seconds < 1.0 {
+        out.write(&format!("{}{}ms", sign, (seconds * 1000.0).round()))?;
+        return Ok(());
+    }
+
+    let mut remainder = seconds.round() as u64;
+    let mut components = vec![];
+    for (unit, length) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        let count = remainder / length;
+        remainder %= length;
+        if count > 0 {
+            components.push(format!("{}{}", count, unit));
+        }
+    }
+    components.truncate(parts);
+
+    out.write(&format!("{}{}", sign, components.join(" ")))?;
+    Ok(())
+}
+
+/// A handlebars helper that converts a ratio (0..1) to a percentage with
+/// a given number of decimals (default 1), e.g. 0.875 => "87.5%".
+pub(crate) fn percent(
+    h: &Helper,
+    _: &Handlebars,
+    _: &HandlebarsContext,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let ratio = float_param(h, 0)?;
+    let precision = precision_param(h, 1)?;
+
+    out.write(&format!(
+        "{num:.prec$}%",
+        num = ratio * 100.0,
+        prec = precision
+    ))?;
+    Ok(())
+}
+
+/// Handlebars helpers that round a floating point number ("round", "floor"
+/// and "ceil"). The number is rounded to a given number of decimals `prec`
+/// (default 0) or to a multiple of `step` (e.g. 0.5). The result is a
+/// number, so these helpers may be used in subexpressions.
+pub(crate) struct Rounding(pub fn(f64) -> f64);
+
+impl HelperDef for Rounding {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = float_param(h, 0)?;
+
+        let step = match h.hash_get("step") {
+            Some(s) => s.value().as_f64().filter(|s| *s > 0.0).ok_or_else(|| {
+                RenderError::new(format!(
+                    "Helper \"{}\": failed to parse parameter 'step' as positive float",
+                    h.name()
+                ))
+            })?,
+            None => 10f64.powi(-(precision_param(h, 0)? as i32)),
+        };
+
+        // Remove float noise from scaling back by the step, e.g. results
+        // like 1.3000000000000003 for a step of 0.1.
+        let rounded = (self.0)(value / step) * step;
+        let rounded = (rounded * 1e9).round() / 1e9;
+
+        Ok(ScopedJson::Derived(number_to_json(rounded)))
+    }
+}
+
+/// Handlebars helpers for basic arithmetic on two numbers ("add", "sub",
+/// "mul" and "div"). Numeric strings such as label values are accepted.
+/// The result is a number, so these helpers may be nested, e.g.
+/// `{{ sub labels.capacity (div this.value 1073741824) }}`. An undefined
+/// result (i.e. a division by zero) fails rendering.
+pub(crate) struct Arithmetic(pub fn(f64, f64) -> Option<f64>);
+
+impl HelperDef for Arithmetic {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let a = float_param(h, 0)?;
+        let b = float_param(h, 1)?;
+
+        let result = (self.0)(a, b).ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": the result of {} and {} is undefined",
+                h.name(),
+                a,
+                b
+            ))
+        })?;
+
+        Ok(ScopedJson::Derived(number_to_json(result)))
+    }
+}
+
+/// A handlebars helper that sorts an array of objects (e.g. `data`) by the
+/// value at a dotted path, e.g. `{{#each (sort_by data "value" order="desc")}}`
+/// or `(sort_by data "labels.node")`. Numbers are compared numerically,
+/// everything else as string. Items without the path are sorted last.
+pub(crate) struct SortBy;
+
+impl HelperDef for SortBy {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, 0)?;
+        let path = str_param(h, 1)?;
+
+        let descending = match h.hash_get("order").map(|o| o.value().as_str()) {
+            None | Some(Some("asc")) => false,
+            Some(Some("desc")) => true,
+            Some(_) => {
+                return Err(RenderError::new(format!(
+                    "Helper \"{}\": parameter 'order' must be either \"asc\" or \"desc\"",
+                    h.name()
+                )))
+            }
+        };
+
+        let mut sorted = items.clone();
+        sorted.sort_by(|a, b| match (lookup(a, path), lookup(b, path)) {
+            (Some(a), Some(b)) => {
+                let ordering = match (a.as_f64(), b.as_f64()) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    _ => a.to_string().cmp(&b.to_string()),
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+
+        Ok(ScopedJson::Derived(JsonValue::Array(sorted)))
+    }
+}
+
+/// A handlebars helper that keeps only the items of an array (e.g. `data`)
+/// whose `exit_status` is one of the given states, e.g.
+/// `{{#filter data state="CRITICAL"}}{{this.labels.node}} {{/filter}}` or
+/// `state="WARNING,CRITICAL"`. States are compared case-insensitively.
+/// As block helper the block is rendered once per matching item (with
+/// `@index`, `@first` and `@last`) and the `{{else}}` block if nothing
+/// matches. As subexpression the filtered array is returned, e.g.
+/// `{{#each (filter data state="CRITICAL")}}`.
+pub(crate) struct Filter;
+
+impl Filter {
+    fn filter(h: &Helper) -> Result<Vec<JsonValue>, RenderError> {
+        let items = array_param(h, 0)?;
+        let states: Vec<String> = h
+            .hash_get("state")
+            .and_then(|s| s.value().as_str())
+            .ok_or_else(|| {
+                RenderError::new(format!(
+                    "Helper \"{}\": missing a string as parameter 'state'",
+                    h.name()
+                ))
+            })?
+            .split(',')
+            .map(|s| s.trim().to_uppercase())
+            .collect();
+
+        Ok(items
+            .iter()
+            .filter(|item| {
+                lookup(item, "exit_status")
+                    .and_then(|s| s.as_str())
+                    .map(|s| states.contains(&s.to_uppercase()))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+impl HelperDef for Filter {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        Ok(ScopedJson::Derived(JsonValue::Array(Self::filter(h)?)))
+    }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc HandlebarsContext,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> Result<(), RenderError> {
+        render_block(Self::filter(h)?, h, r, ctx, rc, out)
+    }
+}
+
+/// A handlebars helper that groups an array of objects (e.g. `data`) by the
+/// value at a dotted path, e.g. `{{#group_by data "labels.node"}}`. Every
+/// group is an object with the group's `key`, the number of items (`size`)
+/// and the `items` themselves. Groups are ordered by key, items without the
+/// path are grouped under an empty key. As block helper the block is rendered
+/// once per group, as subexpression the array of groups is returned.
+pub(crate) struct GroupBy;
+
+impl GroupBy {
+    fn group(h: &Helper) -> Result<Vec<JsonValue>, RenderError> {
+        let items = array_param(h, 0)?;
+        let path = str_param(h, 1)?;
+
+        let mut groups: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+        for item in items {
+            let key = match lookup(item, path) {
+                None | Some(JsonValue::Null) => String::new(),
+                Some(JsonValue::String(s)) => s.clone(),
+                Some(v) => v.to_string(),
+            };
+            groups.entry(key).or_default().push(item.clone());
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(key, items)| {
+                serde_json::json!({
+                    "key": key,
+                    "size": items.len(),
+                    "items": items,
+                })
+            })
+            .collect())
+    }
+}
+
+impl HelperDef for GroupBy {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        Ok(ScopedJson::Derived(JsonValue::Array(Self::group(h)?)))
+    }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc HandlebarsContext,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> Result<(), RenderError> {
+        render_block(Self::group(h)?, h, r, ctx, rc, out)
+    }
+}
+
+/// Handlebars helpers that aggregate the numbers at a dotted path (default
+/// "value") of an array of objects like `data` ("sum", "avg", "min", "max"
+/// and "count"), e.g. `{{ avg data }}` or `{{ max data "baseline" }}`.
+/// Numeric strings such as label values are accepted, items without a number
+/// at the path are ignored. The result is a number, so these helpers may be
+/// used in subexpressions, e.g. `{{ percent (max data) }}`. Without any
+/// numbers "avg", "min" and "max" return nothing.
+pub(crate) struct Aggregate(pub fn(&[f64]) -> Option<f64>);
+
+impl HelperDef for Aggregate {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, 0)?;
+        let path = match h.param(1) {
+            Some(_) => str_param(h, 1)?,
+            None => "value",
+        };
+
+        let values: Vec<f64> = items
+            .iter()
+            .filter_map(|item| lookup(item, path))
+            .filter_map(|v| {
+                v.as_f64()
+                    .or_else(|| v.as_str().and_then(|s| s.trim().parse::<f64>().ok()))
+            })
+            .collect();
+
+        Ok(ScopedJson::Derived(
+            (self.0)(&values).map_or(JsonValue::Null, number_to_json),
+        ))
+    }
+}
+
+/// A handlebars helper that extracts a part of a string using a regular
+/// expression, e.g. the short hostname from an instance label with
+/// `{{ regex_extract labels.instance "^([^.:]+)" }}`. The first capture group
+/// is returned (or the whole match if there is none), another group may be
+/// selected by index or name with `group`. Nothing is returned if the regular
+/// expression or the group does not match.
+pub(crate) struct RegexExtract;
+
+impl HelperDef for RegexExtract {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = str_param(h, 0)?;
+        let regex = regex_param(h, 1)?;
+
+        let captures = match regex.captures(value) {
+            Some(c) => c,
+            None => return Ok(ScopedJson::Derived(JsonValue::from(""))),
+        };
+
+        let group = match h.hash_get("group").map(|g| g.value()) {
+            None if captures.len() > 1 => captures.get(1),
+            None => captures.get(0),
+            Some(JsonValue::String(name)) => captures.name(name),
+            Some(g) => match g.as_u64() {
+                Some(i) => captures.get(i as usize),
+                None => {
+                    return Err(RenderError::new(format!(
+                        "Helper \"{}\": parameter 'group' must be an integer or a string",
+                        h.name()
+                    )))
+                }
+            },
+        };
+
+        Ok(ScopedJson::Derived(JsonValue::from(
+            group.map_or("", |m| m.as_str()),
+        )))
+    }
+}
+
+/// A handlebars helper that replaces all matches of a regular expression in
+/// a string, e.g. `{{ regex_replace labels.instance ":\\d+$" "" }}` to strip
+/// the port from an instance label. The replacement may refer to capture
+/// groups, e.g. "$1" or "${name}".
+pub(crate) struct RegexReplace;
+
+impl HelperDef for RegexReplace {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = str_param(h, 0)?;
+        let regex = regex_param(h, 1)?;
+        let replacement = str_param(h, 2)?;
+
+        Ok(ScopedJson::Derived(JsonValue::from(
+            regex.replace_all(value, replacement).into_owned(),
+        )))
+    }
+}
+
+/// A handlebars helper that joins the items of an array with a separator
+/// (default ", "), e.g. `{{ join (filter data state="CRITICAL") path="labels.node" }}`.
+/// With `path` the value at this dotted path of each item is used (items
+/// without it are skipped), otherwise the items themselves. As block helper
+/// the block is rendered once per item and the results are joined, e.g.
+/// `{{#join data " | "}}{{labels.node}}={{value}}{{/join}}`.
+pub(crate) struct Join;
+
+impl Join {
+    fn separator<'a>(h: &'a Helper) -> Result<&'a str, RenderError> {
+        match h.param(1) {
+            Some(_) => str_param(h, 1),
+            None => Ok(", "),
+        }
+    }
+}
+
+impl HelperDef for Join {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, 0)?;
+        let separator = Self::separator(h)?;
+
+        let values: Vec<String> = match h.hash_get("path").map(|p| p.value()) {
+            None => items.iter().map(|item| item.render()).collect(),
+            Some(JsonValue::String(path)) => items
+                .iter()
+                .filter_map(|item| lookup(item, path))
+                .map(|value| value.render())
+                .collect(),
+            Some(_) => {
+                return Err(RenderError::new(format!(
+                    "Helper \"{}\": failed to parse parameter 'path' as string",
+                    h.name()
+                )))
+            }
+        };
+
+        Ok(ScopedJson::Derived(JsonValue::from(values.join(separator))))
+    }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc HandlebarsContext,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> Result<(), RenderError> {
+        let template = match h.template() {
+            Some(t) => t,
+            None => {
+                let joined = self.call_inner(h, r, ctx, rc)?;
+                out.write(&joined.render())?;
+                return Ok(());
+            }
+        };
+        let items = array_param(h, 0)?;
+        let separator = Self::separator(h)?;
+
+        let mut values = Vec::with_capacity(items.len());
+        rc.push_block(BlockContext::new());
+        for (i, item) in items.iter().enumerate() {
+            set_block_item(rc, i, items.len(), item.clone());
+            let mut output = StringOutput::new();
+            template.render(r, ctx, rc, &mut output)?;
+            values.push(
+                output
+                    .into_string()
+                    .map_err(|e| RenderError::new(format!("Helper \"{}\": {}", h.name(), e)))?,
+            );
+        }
+        rc.pop_block();
+
+        out.write(&values.join(separator))?;
+        Ok(())
+    }
+}
+
+/// A handlebars helper that returns the number of items of an array (e.g.
+/// `{{ len data }}`), the number of keys of an object (e.g. `{{ len labels }}`)
+/// or the number of characters of a string.
+pub(crate) struct Len;
+
+impl HelperDef for Len {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let len = match h.param(0).map(|p| p.value()) {
+            Some(JsonValue::Array(a)) => a.len(),
+            Some(JsonValue::Object(o)) => o.len(),
+            Some(JsonValue::String(s)) => s.chars().count(),
+            _ => {
+                return Err(RenderError::new(format!(
+                    "Helper \"{}\": missing an array, object or string as parameter 0",
+                    h.name()
+                )))
+            }
+        };
+        Ok(ScopedJson::Derived(JsonValue::from(len)))
+    }
+}
+
+/// Handlebars helpers that return the first or last item of an array ("first"
+/// and "last"), especially after sorting, e.g.
+/// `{{#with (first (sort_by data "value" order="desc"))}}{{labels.node}}{{/with}}`.
+/// Nothing is returned if the array is empty.
+pub(crate) struct Element(pub fn(&[JsonValue]) -> Option<&JsonValue>);
+
+impl HelperDef for Element {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, 0)?;
+        Ok(ScopedJson::Derived(
+            (self.0)(items).cloned().unwrap_or(JsonValue::Null),
+        ))
+    }
+}
+
+/// A handlebars helper that serializes any value as compact JSON, e.g.
+/// `{{ json labels }}` renders `{"instance":"node-01","job":"node"}`. The
+/// output is never HTML-escaped.
+pub(crate) fn json(
+    h: &Helper,
+    _: &Handlebars,
+    _: &HandlebarsContext,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let value = h
+        .param(0)
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": missing a value to serialize",
+                h.name()
+            ))
+        })?
+        .value();
+    let serialized = serde_json::to_string(value).map_err(|e| {
+        RenderError::new(format!(
+            "Helper \"{}\": failed to serialize value: {}",
+            h.name(),
+            e
+        ))
+    })?;
+    out.write(&serialized)?;
+    Ok(())
+}
+
+/// A handlebars helper that checks a value against the mapping's warning and
+/// critical ranges just like the check itself does, e.g.
+/// `{{#if (threshold_check (div value 1024))}}...{{/if}}`. The result is true
+/// if the value lies in either range, only one range is checked with
+/// `level="warning"` or `level="critical"`. Ranges that are not configured
+/// (or rendered per time series from a template) are never alerting.
+pub(crate) struct ThresholdCheck {
+    pub thresholds: ThresholdPair,
+}
+
+impl HelperDef for ThresholdCheck {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = float_param(h, 0)?;
+
+        let ranges = match h.hash_get("level").map(|l| l.value().as_str()) {
+            None => vec![self.thresholds.warning, self.thresholds.critical],
+            Some(Some("warning")) => vec![self.thresholds.warning],
+            Some(Some("critical")) => vec![self.thresholds.critical],
+            Some(_) => {
+                return Err(RenderError::new(format!(
+                    "Helper \"{}\": parameter 'level' must be either \"warning\" or \"critical\"",
+                    h.name()
+                )))
+            }
+        };
+
+        let alerts = ranges.iter().flatten().any(|range| range.check(value));
+        Ok(ScopedJson::Derived(JsonValue::from(alerts)))
+    }
+}
+
+/// A handlebars helper that returns the state of a value according to the
+/// mapping's thresholds (including `unknown` and `ok` ranges) just like the
+/// check itself does, e.g. `{{ state_for_value (div value 1024) }}`. The
+/// state is "OK", "WARNING", "CRITICAL" or "UNKNOWN" for services and "UP"
+/// or "DOWN" for hosts.
+pub(crate) struct StateForValue {
+    pub thresholds: ThresholdPair,
+    pub updates_service: bool,
+}
+
+impl HelperDef for StateForValue {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = float_param(h, 0)?;
+        let (_, exit_value) = check_threshold_pair(self.updates_service, &self.thresholds, value);
+        Ok(ScopedJson::Derived(JsonValue::from(exit_value_to_status(
+            self.updates_service,
+            &exit_value,
+        ))))
+    }
+}
+
+/// Handlebars helpers that transform a string like a label value ("upper",
+/// "lower" and "trim"). The result is a string, so these helpers may be used
+/// in subexpressions, e.g. `{{ upper (trim labels.severity) }}`.
+pub(crate) struct StringTransform(pub fn(&str) -> String);
+
+impl HelperDef for StringTransform {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = str_param(h, 0)?;
+        Ok(ScopedJson::Derived(JsonValue::from((self.0)(value))))
+    }
+}
+
+/// A handlebars helper that limits a string to a given number of characters,
+/// e.g. `{{ truncate_chars labels.description 20 }}`. Truncated strings end
+/// with a `suffix` (default "...") that counts towards the limit.
+pub(crate) struct TruncateChars;
+
+impl HelperDef for TruncateChars {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let value = str_param(h, 0)?;
+        let limit = h.param(1).and_then(|l| l.value().as_u64()).ok_or_else(|| {
+            RenderError::new(format!(
+                "Helper \"{}\": missing a positive integer as parameter 1",
+                h.name()
+            ))
+        })? as usize;
+        let suffix = match h.hash_get("suffix").map(|s| s.value()) {
+            None => "...",
+            Some(JsonValue::String(s)) => s.as_str(),
+            Some(_) => {
+                return Err(RenderError::new(format!(
+                    "Helper \"{}\": failed to parse parameter 'suffix' as string",
+                    h.name()
+                )))
+            }
+        };
+
+        if value.chars().count() <= limit {
+            return Ok(ScopedJson::Derived(JsonValue::from(value)));
+        }
+
+        let suffix: String = suffix.chars().take(limit).collect();
+        let truncated: String = value
+            .chars()
+            .take(limit - suffix.chars().count())
+            .chain(suffix.chars())
+            .collect();
+        Ok(ScopedJson::Derived(JsonValue::from(truncated)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use handlebars::Handlebars;
+    use nagios_range::NagiosRange;
 
     #[test]
     fn test_truncation_with_default() {
@@ -124,4 +1103,493 @@ mod tests {
         let tpl = "{{truncate foo}}";
         assert!(handlebars.render_template(tpl, &()).is_err());
     }
+
+    fn render(tpl: &str) -> Result<String, handlebars::RenderError> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        handlebars.render_template(tpl, &())
+    }
+
+    #[test]
+    fn test_humanize_bytes() {
+        assert_eq!(render("{{humanize_bytes 1234567}}").unwrap(), "1.2 MiB");
+        assert_eq!(
+            render("{{humanize_bytes 1234567 units=\"si\" prec=2}}").unwrap(),
+            "1.23 MB"
+        );
+        assert_eq!(render("{{humanize_bytes 512}}").unwrap(), "512 B");
+        assert_eq!(render("{{humanize_bytes -2048}}").unwrap(), "-2.0 KiB");
+        assert!(render("{{humanize_bytes 1024 units=\"foo\"}}").is_err());
+        assert!(render("{{humanize_bytes \"foo\"}}").is_err());
+    }
+
+    #[test]
+    fn test_humanize_duration() {
+        assert_eq!(render("{{humanize_duration 186310}}").unwrap(), "2d 3h 45m");
+        assert_eq!(
+            render("{{humanize_duration 186310 parts=4}}").unwrap(),
+            "2d 3h 45m 10s"
+        );
+        assert_eq!(render("{{humanize_duration 3600}}").unwrap(), "1h");
+        assert_eq!(render("{{humanize_duration 90.4}}").unwrap(), "1m 30s");
+        assert_eq!(render("{{humanize_duration 0.25}}").unwrap(), "250ms");
+        assert_eq!(render("{{humanize_duration -61}}").unwrap(), "-1m 1s");
+        assert!(render("{{humanize_duration 60 parts=0}}").is_err());
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(render("{{percent 0.875}}").unwrap(), "87.5%");
+        assert_eq!(render("{{percent 0.87512 prec=2}}").unwrap(), "87.51%");
+        assert_eq!(render("{{percent 1 prec=0}}").unwrap(), "100%");
+        assert!(render("{{percent}}").is_err());
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(render("{{round 5.5}}").unwrap(), "6");
+        assert_eq!(render("{{floor 5.7}}").unwrap(), "5");
+        assert_eq!(render("{{ceil 5.2}}").unwrap(), "6");
+        assert_eq!(render("{{round 1.26 prec=1}}").unwrap(), "1.3");
+        assert_eq!(render("{{round 1.26 step=0.5}}").unwrap(), "1.5");
+        assert_eq!(render("{{floor 1.26 step=0.1}}").unwrap(), "1.2");
+        assert_eq!(render("{{round 1234 step=100}}").unwrap(), "1200");
+        assert_eq!(
+            render("{{percent (round 0.8749 prec=2)}}").unwrap(),
+            "87.0%"
+        );
+        assert!(render("{{round 5.5 step=0}}").is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(render("{{add 1 2.5}}").unwrap(), "3.5");
+        assert_eq!(render("{{sub \"100\" 25}}").unwrap(), "75");
+        assert_eq!(render("{{mul 3 4}}").unwrap(), "12");
+        assert_eq!(render("{{div 5368709120 1073741824}}").unwrap(), "5");
+        assert_eq!(render("{{truncate (sub 10 (div 5 2))}}").unwrap(), "7.50");
+        assert!(render("{{div 1 0}}").is_err());
+        assert!(render("{{add 1}}").is_err());
+        assert!(render("{{add 1 \"foo\"}}").is_err());
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "b"}, "value": 2.5},
+                {"labels": {"node": "c"}, "value": 10},
+                {"labels": {}, "value": 1},
+                {"labels": {"node": "a"}, "value": 7},
+            ]
+        });
+
+        let tpl = r#"{{#each (sort_by data "value" order="desc")}}{{this.value}} {{/each}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "10 7 2.5 1 "
+        );
+
+        let tpl = r#"{{#each (sort_by data "labels.node")}}{{this.value}} {{/each}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "7 2.5 10 1 "
+        );
+
+        let tpl = r#"{{#each (sort_by data "value" order="up")}}{{/each}}"#;
+        assert!(handlebars.render_template(tpl, &context).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "a"}, "value": 1, "exit_status": "OK"},
+                {"labels": {"node": "b"}, "value": 9, "exit_status": "CRITICAL"},
+                {"labels": {"node": "c"}, "value": 5, "exit_status": "WARNING"},
+                {"labels": {"node": "d"}, "value": 8, "exit_status": "CRITICAL"},
+            ]
+        });
+
+        let tpl = r#"{{#filter data state="CRITICAL"}}{{#unless @first}}, {{/unless}}{{this.labels.node}}={{value}}{{/filter}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "b=9, d=8"
+        );
+
+        let tpl =
+            r#"{{#filter data state="warning, critical"}}{{@index}}{{labels.node}} {{/filter}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "0b 1c 2d "
+        );
+
+        let tpl = r#"{{#filter data state="UNKNOWN"}}{{labels.node}}{{else}}none{{/filter}}"#;
+        assert_eq!(handlebars.render_template(tpl, &context).unwrap(), "none");
+
+        let tpl =
+            r#"{{#each (sort_by (filter data state="CRITICAL") "value")}}{{labels.node}}{{/each}}"#;
+        assert_eq!(handlebars.render_template(tpl, &context).unwrap(), "db");
+
+        let tpl = r#"{{#filter data}}{{/filter}}"#;
+        assert!(handlebars.render_template(tpl, &context).is_err());
+    }
+
+    #[test]
+    fn test_group_by() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "b", "disk": "sda"}, "value": 1},
+                {"labels": {"node": "a", "disk": "sda"}, "value": 2},
+                {"labels": {"node": "b", "disk": "sdb"}, "value": 3},
+                {"labels": {"disk": "sdc"}, "value": 4},
+            ]
+        });
+
+        let tpl = r#"{{#group_by data "labels.node"}}[{{key}}: {{size}}{{#each items}} {{labels.disk}}{{/each}}]{{/group_by}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "[: 1 sdc][a: 1 sda][b: 2 sda sdb]"
+        );
+
+        let tpl = r#"{{#each (group_by data "value")}}{{key}}{{/each}}"#;
+        assert_eq!(handlebars.render_template(tpl, &context).unwrap(), "1234");
+
+        let tpl = r#"{{#group_by data}}{{/group_by}}"#;
+        assert!(handlebars.render_template(tpl, &context).is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"size": "100"}, "value": 0.43},
+                {"labels": {"size": "50"}, "value": 0.91},
+                {"labels": {}, "value": 0.2},
+            ],
+            "empty": []
+        });
+
+        let tpl =
+            r#"{{count data}} volumes, avg {{percent (avg data)}}, max {{percent (max data)}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "3 volumes, avg 51.3%, max 91.0%"
+        );
+
+        let tpl = r#"{{sum data "labels.size"}} {{count data "labels.size"}} {{min data}}"#;
+        assert_eq!(
+            handlebars.render_template(tpl, &context).unwrap(),
+            "150 2 0.2"
+        );
+
+        let tpl = r#"{{sum empty}} {{count empty}} [{{avg empty}}]"#;
+        assert_eq!(handlebars.render_template(tpl, &context).unwrap(), "0 0 []");
+
+        assert!(handlebars.render_template("{{sum 5}}", &context).is_err());
+    }
+
+    #[test]
+    fn test_regex_extract() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "labels": {"instance": "node-01.example.com:9100"}
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(
+            render(r#"{{regex_extract labels.instance "^([^.:]+)"}}"#).unwrap(),
+            "node-01"
+        );
+        assert_eq!(
+            render(r#"{{regex_extract labels.instance "\\d+$"}}"#).unwrap(),
+            "9100"
+        );
+        assert_eq!(
+            render(r#"{{regex_extract labels.instance "^(\\w+)-(\\d+)" group=2}}"#).unwrap(),
+            "01"
+        );
+        assert_eq!(
+            render(r#"{{regex_extract labels.instance ":(?P<port>\\d+)" group="port"}}"#).unwrap(),
+            "9100"
+        );
+        assert_eq!(
+            render(r#"[{{regex_extract labels.instance "^foo"}}]"#).unwrap(),
+            "[]"
+        );
+        assert!(render(r#"{{regex_extract labels.instance "("}}"#).is_err());
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "labels": {"instance": "node-01.example.com:9100"}
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(
+            render(r#"{{regex_replace labels.instance ":\\d+$" ""}}"#).unwrap(),
+            "node-01.example.com"
+        );
+        assert_eq!(
+            render(r#"{{regex_replace labels.instance "^([^.]+)\\..*$" "host $1"}}"#).unwrap(),
+            "host node-01"
+        );
+        assert!(render(r#"{{regex_replace labels.instance "x"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "a"}, "value": 1, "exit_status": "OK"},
+                {"labels": {"node": "b"}, "value": 9.5, "exit_status": "CRITICAL"},
+                {"labels": {}, "value": 8, "exit_status": "CRITICAL"},
+                {"labels": {"node": "d"}, "value": 7, "exit_status": "CRITICAL"},
+            ],
+            "names": ["x", "y", "z"]
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(
+            render(r#"{{join (filter data state="CRITICAL") path="labels.node"}}"#).unwrap(),
+            "b, d"
+        );
+        assert_eq!(render(r#"{{join names "/"}}"#).unwrap(), "x/y/z");
+        assert_eq!(
+            render(r#"{{join data path="value"}}"#).unwrap(),
+            "1, 9.5, 8, 7"
+        );
+        assert_eq!(
+            render(
+                r#"{{#join (filter data state="CRITICAL") " | "}}{{@index}}:{{value}}{{/join}}"#
+            )
+            .unwrap(),
+            "0:9.5 | 1:8 | 2:7"
+        );
+        assert_eq!(
+            render(r#"{{#each (group_by data "exit_status")}}{{key}}: {{join items path="labels.node"}}; {{/each}}"#)
+                .unwrap(),
+            "CRITICAL: b, d; OK: a; "
+        );
+        assert!(render(r#"{{join data path=1}}"#).is_err());
+    }
+
+    #[test]
+    fn test_string_transforms() {
+        assert_eq!(render(r#"{{upper "critical"}}"#).unwrap(), "CRITICAL");
+        assert_eq!(render(r#"{{lower "Node-01"}}"#).unwrap(), "node-01");
+        assert_eq!(render(r#"[{{trim "  sda \n"}}]"#).unwrap(), "[sda]");
+        assert_eq!(
+            render(r#"{{upper (trim " warning ")}}"#).unwrap(),
+            "WARNING"
+        );
+        assert!(render("{{upper 5}}").is_err());
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(
+            render(r#"{{truncate_chars "Disk almost full" 10}}"#).unwrap(),
+            "Disk al..."
+        );
+        assert_eq!(
+            render(r#"{{truncate_chars "Disk almost full" 10 suffix="…"}}"#).unwrap(),
+            "Disk almo…"
+        );
+        assert_eq!(
+            render(r#"{{truncate_chars "Disk almost full" 10 suffix=""}}"#).unwrap(),
+            "Disk almos"
+        );
+        assert_eq!(render(r#"{{truncate_chars "Disk" 10}}"#).unwrap(), "Disk");
+        assert_eq!(render(r#"{{truncate_chars "Disk" 2}}"#).unwrap(), "..");
+        assert_eq!(render(r#"{{truncate_chars "Grüße" 4}}"#).unwrap(), "G...");
+        assert!(render(r#"{{truncate_chars "Disk"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_len() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "a", "disk": "sda"}, "exit_status": "OK"},
+                {"labels": {"node": "b"}, "exit_status": "CRITICAL"},
+            ]
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(render("{{len data}}").unwrap(), "2");
+        assert_eq!(
+            render(r#"{{len (filter data state="CRITICAL")}}"#).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            render("{{#each data}}{{len this.labels}}{{/each}}").unwrap(),
+            "21"
+        );
+        assert_eq!(render(r#"{{len "Grüße"}}"#).unwrap(), "5");
+        assert_eq!(render(r#"{{#if (len data)}}yes{{/if}}"#).unwrap(), "yes");
+        assert!(render("{{len 5}}").is_err());
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "data": [
+                {"labels": {"node": "a"}, "value": 5},
+                {"labels": {"node": "b"}, "value": 9},
+                {"labels": {"node": "c"}, "value": 1},
+            ],
+            "names": ["x", "y"],
+            "empty": []
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(
+            render(r#"{{#with (first (sort_by data "value" order="desc"))}}{{labels.node}}: {{value}}{{/with}}"#)
+                .unwrap(),
+            "b: 9"
+        );
+        assert_eq!(
+            render(r#"{{#with (last data)}}{{labels.node}}{{/with}}"#).unwrap(),
+            "c"
+        );
+        assert_eq!(render("{{first names}}{{last names}}").unwrap(), "xy");
+        assert_eq!(
+            render("{{#with (first empty)}}{{value}}{{else}}none{{/with}}").unwrap(),
+            "none"
+        );
+        assert!(render("{{first 5}}").is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        let context = serde_json::json!({
+            "labels": {"job": "node", "instance": "node-01"},
+            "value": 5.5
+        });
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(
+            render("{{json labels}}").unwrap(),
+            r#"{"instance":"node-01","job":"node"}"#
+        );
+        assert_eq!(render("{{json value}}").unwrap(), "5.5");
+        assert!(render("{{json}}").is_err());
+    }
+
+    #[test]
+    fn test_threshold_check() {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        handlebars.register_helper(
+            "threshold_check",
+            Box::new(ThresholdCheck {
+                thresholds: ThresholdPair {
+                    warning: Some(NagiosRange::from("@10:20").unwrap()),
+                    critical: Some(NagiosRange::from("20").unwrap()),
+                    ..ThresholdPair::default()
+                },
+            }),
+        );
+        let context = serde_json::json!({"value": 15, "label": "25"});
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(render("{{threshold_check 5}}").unwrap(), "false");
+        assert_eq!(render("{{threshold_check value}}").unwrap(), "true");
+        assert_eq!(render("{{threshold_check label}}").unwrap(), "true");
+        assert_eq!(
+            render(r#"{{threshold_check value level="critical"}}"#).unwrap(),
+            "false"
+        );
+        assert_eq!(
+            render(r#"{{#if (threshold_check label level="critical")}}!{{/if}}"#).unwrap(),
+            "!"
+        );
+        assert!(render(r#"{{threshold_check value level="unknown"}}"#).is_err());
+
+        handlebars.register_helper(
+            "threshold_check",
+            Box::new(ThresholdCheck {
+                thresholds: ThresholdPair::default(),
+            }),
+        );
+        assert_eq!(
+            handlebars
+                .render_template("{{threshold_check 100}}", &context)
+                .unwrap(),
+            "false"
+        );
+    }
+
+    #[test]
+    fn test_state_for_value() {
+        let thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("10").unwrap()),
+            critical: Some(NagiosRange::from("20").unwrap()),
+            unknown: Some(NagiosRange::from("@-1:-1").unwrap()),
+            ..ThresholdPair::default()
+        };
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        register_helpers(&mut handlebars);
+        handlebars.register_helper(
+            "state_for_value",
+            Box::new(StateForValue {
+                thresholds: thresholds.clone(),
+                updates_service: true,
+            }),
+        );
+        let context = serde_json::json!({"value": 15});
+
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(render("{{state_for_value 5}}").unwrap(), "OK");
+        assert_eq!(render("{{state_for_value value}}").unwrap(), "WARNING");
+        assert_eq!(
+            render("{{state_for_value (mul value 2)}}").unwrap(),
+            "CRITICAL"
+        );
+        assert_eq!(render("{{state_for_value -1}}").unwrap(), "UNKNOWN");
+        assert!(render("{{state_for_value}}").is_err());
+
+        handlebars.register_helper(
+            "state_for_value",
+            Box::new(StateForValue {
+                thresholds,
+                updates_service: false,
+            }),
+        );
+        let render = |tpl: &str| handlebars.render_template(tpl, &context);
+        assert_eq!(render("{{state_for_value value}}").unwrap(), "UP");
+        assert_eq!(render("{{state_for_value 25}}").unwrap(), "DOWN");
+    }
 }
@@ -0,0 +1,361 @@
+use anyhow::{bail, Context};
+use chrono::Local;
+use env_logger::filter::{Builder, Filter};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The syslog facility "daemon".
+const SYSLOG_FACILITY: u8 = 3;
+
+/// The destination of log messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogTarget {
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl FromStr for LogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "syslog" => Ok(LogTarget::Syslog),
+            "journald" => Ok(LogTarget::Journald),
+            _ => bail!(
+                "invalid log target '{}', must be one of 'stderr', 'syslog' or 'journald'",
+                s
+            ),
+        }
+    }
+}
+
+/// The log levels that are given on the command line, i.e. a level for
+/// all modules of vec2checkd and/or levels for individual modules, e.g.
+/// "info,icinga=debug". Module names are relative to the crate.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct LogLevel {
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut directives = vec![];
+
+        for directive in s.split(',').map(str::trim) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (module.trim(), level.trim()),
+                None => ("", directive),
+            };
+            let level = match LevelFilter::from_str(level) {
+                Ok(l) => l,
+                Err(_) => bail!(
+                    "invalid log level '{}', must be one of 'off', 'error', 'warn', 'info', 'debug' or 'trace'",
+                    level
+                ),
+            };
+            let crate_name = env!("CARGO_CRATE_NAME");
+            let module = match module {
+                "" => crate_name.to_string(),
+                m if m == crate_name || m.starts_with(&format!("{}::", crate_name)) => {
+                    m.to_string()
+                }
+                m => format!("{}::{}", crate_name, m),
+            };
+            directives.push((module, level));
+        }
+
+        Ok(LogLevel { directives })
+    }
+}
+
+/// The filter specification from the RUST_LOG environment variable. As with
+/// env_logger only errors are logged when it is not set.
+fn env_filter() -> String {
+    std::env::var("RUST_LOG").unwrap_or_else(|_| String::from("error"))
+}
+
+/// Build the filter of log messages from RUST_LOG and the log levels on
+/// the command line, which take precedence for the modules they name.
+fn build_filter(spec: &str, level: &LogLevel) -> Filter {
+    let mut builder = Builder::new();
+    builder.parse(spec);
+    for (module, level) in level.directives.iter() {
+        builder.filter_module(module, *level);
+    }
+    builder.build()
+}
+
+/// Initialize the logger that writes to the given target. Log messages
+/// are filtered by the RUST_LOG environment variable in any case, the
+/// given log levels are applied on top of it.
+pub(crate) fn init(target: LogTarget, level: &LogLevel) -> Result<(), anyhow::Error> {
+    let path = match target {
+        LogTarget::Stderr => {
+            let mut builder = env_logger::Builder::from_env(
+                env_logger::Env::default().default_filter_or("error"),
+            );
+            for (module, level) in level.directives.iter() {
+                builder.filter_module(module, *level);
+            }
+            builder.init();
+            return Ok(());
+        }
+        LogTarget::Syslog => SYSLOG_SOCKET,
+        LogTarget::Journald => JOURNALD_SOCKET,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .connect(path)
+        .with_context(|| format!("failed to connect to log socket {}", path))?;
+
+    let filter = build_filter(&env_filter(), level);
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(SocketLogger {
+        target,
+        filter,
+        path,
+        socket,
+    }))?;
+
+    Ok(())
+}
+
+/// A logger that sends each message as a datagram to the local syslog
+/// daemon or to journald.
+struct SocketLogger {
+    target: LogTarget,
+    filter: Filter,
+    path: &'static str,
+    socket: UnixDatagram,
+}
+
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        let datagram = match self.target {
+            LogTarget::Journald => {
+                format_journald_entry(record, &message, current_mapping().as_deref())
+            }
+            _ => format_syslog_message(record.level(), &message).into_bytes(),
+        };
+
+        // Reconnect once in case the syslog daemon or journald was restarted,
+        // there is no other place than stderr to report the failure to.
+        let result = self.socket.send(&datagram).or_else(|_| {
+            self.socket.connect(self.path)?;
+            self.socket.send(&datagram)
+        });
+        if let Err(err) = result {
+            eprintln!("failed to send log message: {}: {}", err, message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Map a log level to a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// The name of the mapping that a span, e.g. the `check` span, refers to.
+struct MappingName(String);
+
+/// Records the `mapping` field of spans, so that log messages that are
+/// written while such a span is entered can be attributed to the mapping.
+pub(crate) struct MappingLayer;
+
+impl<S> Layer<S> for MappingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        let mut visitor = MappingVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(name), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(MappingName(name));
+        }
+    }
+}
+
+/// Extracts the `mapping` field of a span.
+struct MappingVisitor(Option<String>);
+
+impl Visit for MappingVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "mapping" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "mapping" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// The name of the mapping of the innermost span on the current thread that
+/// carries one, i.e. the mapping whose check is currently executed.
+fn current_mapping() -> Option<String> {
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let id = registry.current_span().id()?.clone();
+        registry.span(&id)?.scope().find_map(|span| {
+            span.extensions()
+                .get::<MappingName>()
+                .map(|name| name.0.clone())
+        })
+    })
+}
+
+/// Format a message in the traditional BSD syslog format (RFC 3164) as it
+/// is expected on the local syslog socket.
+fn format_syslog_message(level: Level, message: &str) -> String {
+    format!(
+        "<{}>{} vec2checkd[{}]: {}",
+        SYSLOG_FACILITY * 8 + severity(level),
+        Local::now().format("%b %e %H:%M:%S"),
+        std::process::id(),
+        message
+    )
+}
+
+/// Format a message as an entry of the native journald protocol, i.e. as a
+/// list of fields that carries the name of the mapping in MAPPING; ref:
+/// https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+fn format_journald_entry(record: &Record, message: &str, mapping: Option<&str>) -> Vec<u8> {
+    let mut entry = vec![];
+
+    let mut field = |name: &str, value: &str| {
+        entry.extend_from_slice(name.as_bytes());
+        // Values that span multiple lines are prefixed by their length.
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+
+    field("MESSAGE", message);
+    field("PRIORITY", &severity(record.level()).to_string());
+    field("SYSLOG_IDENTIFIER", "vec2checkd");
+    field("TARGET", record.target());
+    if let Some(name) = mapping {
+        field("MAPPING", name);
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(
+            LogLevel::from_str("info,icinga=debug, vec2checkd::util = trace").unwrap(),
+            LogLevel {
+                directives: vec![
+                    (String::from("vec2checkd"), LevelFilter::Info),
+                    (String::from("vec2checkd::icinga"), LevelFilter::Debug),
+                    (String::from("vec2checkd::util"), LevelFilter::Trace),
+                ]
+            }
+        );
+        assert_eq!(
+            LogLevel::from_str("icinga=verbose")
+                .unwrap_err()
+                .to_string(),
+            "invalid log level 'verbose', must be one of 'off', 'error', 'warn', 'info', 'debug' or 'trace'"
+        );
+    }
+
+    #[test]
+    fn test_build_filter() {
+        let enabled = |filter: &Filter, target: &str, level: Level| {
+            filter.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+
+        let filter = build_filter("warn", &LogLevel::from_str("info,icinga=debug").unwrap());
+        assert!(enabled(&filter, "vec2checkd::icinga", Level::Debug));
+        assert!(enabled(&filter, "vec2checkd::util", Level::Info));
+        assert!(!enabled(&filter, "vec2checkd::util", Level::Debug));
+        assert!(enabled(&filter, "reqwest::connect", Level::Warn));
+        assert!(!enabled(&filter, "reqwest::connect", Level::Info));
+
+        // The command line takes precedence over RUST_LOG.
+        let filter = build_filter("vec2checkd=trace", &LogLevel::from_str("warn").unwrap());
+        assert!(!enabled(&filter, "vec2checkd", Level::Info));
+    }
+
+    #[test]
+    fn test_current_mapping() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(MappingLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            assert_eq!(current_mapping(), None);
+            let check = tracing::trace_span!("check", mapping = %"Node status");
+            let _entered = check.enter();
+            tracing::trace_span!("query").in_scope(|| {
+                assert_eq!(current_mapping().as_deref(), Some("Node status"));
+            });
+        });
+    }
+
+    #[test]
+    fn test_format_syslog_message() {
+        let message = format_syslog_message(Level::Warn, "'foo': bar");
+        assert!(message.starts_with("<28>"));
+        assert!(message.ends_with(&format!(" vec2checkd[{}]: 'foo': bar", std::process::id())));
+    }
+
+    #[test]
+    fn test_format_journald_entry() {
+        let record = Record::builder()
+            .level(Level::Error)
+            .target("vec2checkd")
+            .build();
+        assert_eq!(
+            format_journald_entry(&record, "'foo': failed to finish check", Some("foo")),
+            b"MESSAGE='foo': failed to finish check\nPRIORITY=3\nSYSLOG_IDENTIFIER=vec2checkd\nTARGET=vec2checkd\nMAPPING=foo\n"
+        );
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\nc d\nPRIORITY=3\n");
+        assert!(format_journald_entry(&record, "a\nb\nc d", None).starts_with(&expected));
+    }
+}
@@ -1,22 +1,42 @@
+mod admin;
+mod audit;
+mod backend;
 mod config;
 mod error;
+mod heartbeat;
 mod helpers;
 mod icinga;
+mod logging;
+mod metrics;
+mod otlp;
 mod prometheus;
+mod ratelimit;
+mod state;
+mod summary;
 mod types;
 mod util;
 
+use crate::admin::{AdminSocket, Command};
+use crate::error::SubmissionError;
+use crate::heartbeat::Heartbeat;
 use crate::icinga::*;
-use crate::types::Mapping;
+use crate::metrics::Metrics;
+use crate::state::StateFile;
+use crate::summary::SummaryLog;
+use crate::types::{BackendConfig, IcingaConfig, Mapping};
 use crate::util::*;
+use anyhow::{bail, Context};
 use gumdrop::Options;
-use log::{debug, error, info, warn};
-use std::fs::File;
-use std::io::Read;
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace_span, warn, Instrument};
+use tracing_subscriber::layer::SubscriberExt;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long the main loop waits when no mapping is scheduled.
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Options)]
 struct AppOptions {
     #[options(help = "print help message", short = "h")]
@@ -27,6 +47,20 @@ struct AppOptions {
 
     #[options(help = "path to the configuration file", short = "c")]
     config: String,
+
+    #[options(
+        help = "where log messages are written to: stderr (default), syslog or journald",
+        short = "l",
+        meta = "TARGET"
+    )]
+    log_target: Option<String>,
+
+    #[options(
+        help = "log level of vec2checkd and/or of individual modules, e.g. info,icinga=debug",
+        no_short,
+        meta = "LEVEL"
+    )]
+    log_level: Option<String>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -38,41 +72,52 @@ async fn main() -> Result<(), anyhow::Error> {
         std::process::exit(0);
     }
 
-    env_logger::init();
-
-    info!("Start vec2checkd version {}", &VERSION);
+    let log_target = match opts.log_target.as_deref().unwrap_or("stderr").parse() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let config = {
-        info!("Parse configuration from '{}'", opts.config);
+    // The log level may be set in the configuration file, so it is read
+    // before logging is initialized. Errors are logged afterwards.
+    let config = if opts.config.is_empty() {
+        Err(anyhow::anyhow!(
+            "path to configuration file cannot be empty"
+        ))
+    } else {
+        read_config(&opts.config)
+    };
 
-        if opts.config.is_empty() {
-            error!("Path to configuration file cannot be empty. Exit");
+    // The command line takes precedence over the configuration file.
+    let log_level = match (&opts.log_level, &config) {
+        (Some(l), _) => l.parse().map(Some),
+        (None, Ok(cfg)) => config::parse_log_level(cfg),
+        (None, Err(_)) => Ok(None),
+    };
+    let log_level = match log_level {
+        Ok(l) => l.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{:#}", e);
             std::process::exit(1);
         }
+    };
 
-        let mut file = match File::open(&opts.config) {
-            Ok(f) => f,
-            Err(e) => {
-                error!(
-                    "Failed to read configuration file '{}': {:#}",
-                    opts.config, e
-                );
-                std::process::exit(1);
-            }
-        };
+    if let Err(e) = logging::init(log_target, &log_level) {
+        eprintln!("Failed to initialize logging: {:#}", e);
+        std::process::exit(1);
+    }
 
-        let mut raw_conf = String::new();
-        file.read_to_string(&mut raw_conf)?;
+    info!("Start vec2checkd version {}", &VERSION);
+    get_start_time();
 
-        match config::parse_yaml(&raw_conf) {
-            Ok(cfg) => cfg,
-            Err(e) => {
-                error!(
-                    "Failed to parse configuration file '{}': {:#}",
-                    opts.config, e
-                );
-                std::process::exit(1);
-            }
+    info!("Parse configuration from '{}'", opts.config);
+    let config = match config {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            error!("Failed to load configuration: {:#}", e);
+            std::process::exit(1);
         }
     };
 
@@ -90,7 +135,23 @@ async fn main() -> Result<(), anyhow::Error> {
         std::process::exit(0);
     }
 
-    let prom_client = {
+    let state_file = match config::parse_state_file(&config) {
+        Ok(p) => p.map(StateFile::new),
+        Err(e) => {
+            error!("Failed to parse state file from configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(state_file) = &state_file {
+        match state_file.load(&mut mappings) {
+            Ok(restored) => info!("Restored the state of {} mapping(s)", restored),
+            // Starting with a fresh state is preferable to not starting at all.
+            Err(e) => warn!("Failed to restore the state of the mappings: {:#}", e),
+        }
+    }
+
+    let mut prom_client = {
         info!("Read Prometheus section from configuration and initialize API client");
         let c = match config::parse_prom_section(&config) {
             Ok(c) => c,
@@ -108,34 +169,371 @@ async fn main() -> Result<(), anyhow::Error> {
         }
     };
 
-    let icinga_client = {
+    let mut backend_configs = match config::parse_backends_section(&config) {
+        Ok(b) => b,
+        Err(e) => {
+            error!(
+                "Failed to parse backends section from configuration: {:#}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // The Icinga section is only mandatory when check results are sent
+    // to the Icinga API.
+    let mut icinga_config = if backend_configs
+        .iter()
+        .any(|b| matches!(b, BackendConfig::Icinga))
+    {
         info!("Read Icinga section from configuration and initialize API client");
-        let c = match config::parse_icinga_section(&config) {
-            Ok(c) => c,
+        match config::parse_icinga_section(&config) {
+            Ok(c) => Some(c),
             Err(e) => {
                 error!("Failed to parse Icinga section from configuration: {:#}", e);
                 std::process::exit(1);
             }
-        };
-        match IcingaClient::new(c) {
+        }
+    } else {
+        None
+    };
+
+    let icinga_client = icinga_config
+        .as_ref()
+        .map(|c| match IcingaClient::new(c.clone()) {
             Ok(clt) => clt,
             Err(e) => {
                 error!("Failed to initialize Icinga API client: {:#}", e);
                 std::process::exit(1)
             }
+        });
+
+    let mut backends = match backend::build(&backend_configs, icinga_client.as_ref()) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Failed to initialize backends: {:#}", e);
+            std::process::exit(1)
+        }
+    };
+
+    let mut tls_watcher = FileWatcher::new(
+        icinga_config
+            .as_ref()
+            .map(|c| c.tls_files())
+            .unwrap_or_default(),
+    );
+
+    if let (Some(clt), Some(cfg)) = (&icinga_client, &icinga_config) {
+        if cfg.startup_check {
+            info!("Check connectivity to and permissions on the Icinga API");
+            if let Err(e) = clt.probe(None).await {
+                error!("Icinga API check failed: {:#}", e);
+                std::process::exit(1);
+            }
+            for mapping in mappings
+                .iter()
+                .filter(|mapping| mapping.basic_auth.is_some())
+            {
+                if let Err(e) = clt.probe(mapping.basic_auth.as_ref()).await {
+                    error!(
+                        "'{}': Icinga API check with the credentials of the mapping failed: {:#}",
+                        mapping.name, e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    for mapping in mappings.iter().filter(|mapping| mapping.sync_vars) {
+        debug!(
+            "'{}': sync mapping metadata to custom variables of the Icinga object",
+            mapping.name
+        );
+        let result = match &icinga_client {
+            Some(clt) => clt.sync_vars(mapping).await,
+            None => Err(anyhow::anyhow!("the Icinga backend is not configured")),
+        };
+        if let Err(err) = result {
+            warn!(
+                "'{}': failed to sync mapping metadata to Icinga custom variables: {:#}",
+                mapping.name, err
+            );
+        }
+    }
+
+    let mut heartbeat = match config::parse_heartbeat_section(&config) {
+        Ok(h) => h.map(Heartbeat::new),
+        Err(e) => {
+            error!(
+                "Failed to parse heartbeat section from configuration: {:#}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let mut summary_log = match config::parse_log_summary_section(&config) {
+        Ok(s) => s.map(SummaryLog::new),
+        Err(e) => {
+            error!(
+                "Failed to parse log_summary section from configuration: {:#}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let otlp_layer = match config::parse_tracing_section(&config) {
+        Ok(Some(tracing_config)) => {
+            info!("Export traces to {}", tracing_config.endpoint);
+            match otlp::layer(tracing_config) {
+                Ok(l) => Some(l),
+                Err(e) => {
+                    error!("Failed to initialize trace export: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!(
+                "Failed to parse tracing section from configuration: {:#}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let subscriber = tracing_subscriber::registry()
+        .with(logging::MappingLayer)
+        .with(otlp_layer);
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        error!("Failed to initialize tracing subscriber: {:#}", e);
+        std::process::exit(1);
+    }
+
+    let metrics = match config::parse_metrics_section(&config) {
+        Ok(m) => m.map(|metrics_config| {
+            let spool_dirs = backend_configs
+                .iter()
+                .filter_map(|b| match b {
+                    BackendConfig::CheckResultSpool(dir) => Some(dir.clone()),
+                    _ => None,
+                })
+                .collect();
+            let metrics = Metrics::new(spool_dirs);
+            info!(
+                "Serve metrics, health and readiness endpoints at http://{}",
+                metrics_config.listen
+            );
+            if let Err(e) = metrics.clone().serve(metrics_config.listen) {
+                error!("Failed to start metrics listener: {:#}", e);
+                std::process::exit(1);
+            }
+            metrics
+        }),
+        Err(e) => {
+            error!(
+                "Failed to parse metrics section from configuration: {:#}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let admin = match config::parse_admin_section(&config) {
+        Ok(Some(path)) => match AdminSocket::bind(&path) {
+            Ok(socket) => {
+                info!("Accept admin commands at {:?}", path);
+                Some(socket)
+            }
+            Err(e) => {
+                error!("Failed to initialize admin interface: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            error!("Failed to parse admin section from configuration: {:#}", e);
+            std::process::exit(1);
         }
     };
 
+    // Admin commands that were received while waiting for the next check.
+    let mut admin_requests = vec![];
+
+    // Mappings that were paused via the admin interface.
+    let mut paused: HashSet<String> = HashSet::new();
+
+    // Mappings that were added by a reload and are executed right away.
+    let mut added: HashSet<String> = HashSet::new();
+
     info!("Execute every check once regardless of the configured intervals and then enter the periodic check loop");
     let mut initial_check = true;
     loop {
-        for mapping in mappings
-            .iter_mut()
-            .filter(|mapping| compute_delta(mapping).as_secs() <= 1 || initial_check)
-        {
+        // Rebuild the Icinga API client when certificates or keys were
+        // rotated. Keep the previous client when the new material is
+        // invalid, e.g. because only one of the files was replaced yet.
+        if tls_watcher.changed() {
+            info!("TLS material of the Icinga API client changed, re-initialize client");
+            // The watcher only tracks files when the Icinga section was parsed.
+            match IcingaClient::new(icinga_config.clone().unwrap()) {
+                Ok(clt) => match backend::build(&backend_configs, Some(&clt)) {
+                    Ok(b) => backends = b,
+                    Err(e) => error!("Failed to re-initialize backends: {:#}", e),
+                },
+                Err(e) => error!(
+                    "Failed to re-initialize Icinga API client, keep using the previous one: {:#}",
+                    e
+                ),
+            }
+        }
+
+        if let Some(admin) = &admin {
+            admin_requests.extend(admin.pending());
+        }
+
+        // Mappings that are executed on demand regardless of their schedule
+        // and the requests that are answered with the outcome.
+        let mut run_requests: HashMap<String, Vec<admin::Request>> = HashMap::new();
+
+        for request in admin_requests.drain(..) {
+            match &request.command {
+                Command::Run(name) if mappings.iter().any(|m| &m.name == name) => {
+                    info!("'{}': execute check on demand", name);
+                    run_requests.entry(name.clone()).or_default().push(request);
+                }
+                Command::Status(Some(name)) if !mappings.iter().any(|m| &m.name == name) => {
+                    let err = format!("unknown mapping '{}'", name);
+                    request.reply(serde_json::json!({ "error": err }));
+                }
+                Command::Status(name) => {
+                    let response = admin::status_response(
+                        mappings
+                            .iter()
+                            .filter(|m| name.is_none() || name.as_ref() == Some(&m.name)),
+                        &paused,
+                    );
+                    request.reply(response);
+                }
+                Command::Pause(name) if mappings.iter().any(|m| &m.name == name) => {
+                    info!("'{}': pause check", name);
+                    paused.insert(name.clone());
+                    let response = serde_json::json!({ "mapping": name, "paused": true });
+                    request.reply(response);
+                }
+                Command::Resume(name) if mappings.iter().any(|m| &m.name == name) => {
+                    info!("'{}': resume check", name);
+                    paused.remove(name);
+                    let response = serde_json::json!({ "mapping": name, "paused": false });
+                    request.reply(response);
+                }
+                Command::Run(name) | Command::Pause(name) | Command::Resume(name) => {
+                    let err = format!("unknown mapping '{}'", name);
+                    request.reply(serde_json::json!({ "error": err }));
+                }
+                Command::Reload => {
+                    info!("Reload configuration from '{}'", opts.config);
+                    let reloaded = match Reloaded::load(&opts.config) {
+                        Ok(r) => r,
+                        Err(e) => {
+                            error!(
+                                "Failed to reload configuration, keep using the previous one: {:#}",
+                                e
+                            );
+                            request.reply(serde_json::json!({ "error": format!("{:#}", e) }));
+                            continue;
+                        }
+                    };
+
+                    // Mappings that are still configured keep their state and
+                    // schedule, new ones are executed right away.
+                    let mut previous: HashMap<String, Mapping> =
+                        mappings.drain(..).map(|m| (m.name.clone(), m)).collect();
+                    mappings = reloaded.mappings;
+                    for mapping in mappings.iter_mut() {
+                        match previous.remove(&mapping.name) {
+                            Some(prev) => {
+                                mapping.state = prev.state;
+                                mapping.last_apply = prev.last_apply;
+                            }
+                            None => {
+                                added.insert(mapping.name.clone());
+                            }
+                        }
+                    }
+                    paused.retain(|name| !previous.contains_key(name));
+                    added.retain(|name| !previous.contains_key(name));
+
+                    prom_client = reloaded.prom_client;
+                    icinga_config = reloaded.icinga_config;
+                    backend_configs = reloaded.backend_configs;
+                    backends = reloaded.backends;
+                    tls_watcher = FileWatcher::new(
+                        icinga_config
+                            .as_ref()
+                            .map(|c| c.tls_files())
+                            .unwrap_or_default(),
+                    );
+
+                    if let Some(clt) = &reloaded.icinga_client {
+                        for mapping in mappings.iter().filter(|mapping| mapping.sync_vars) {
+                            if let Err(err) = clt.sync_vars(mapping).await {
+                                warn!(
+                                    "'{}': failed to sync mapping metadata to Icinga custom variables: {:#}",
+                                    mapping.name, err
+                                );
+                            }
+                        }
+                    }
+
+                    info!(
+                        "Reloaded configuration, {} mapping(s) added, {} removed",
+                        added.len(),
+                        previous.len()
+                    );
+                    request.reply(serde_json::json!({
+                        "success": true,
+                        "mappings": mappings.len(),
+                        "added": added.iter().collect::<Vec<_>>(),
+                        "removed": previous.keys().collect::<Vec<_>>(),
+                    }));
+                }
+            }
+        }
+
+        // Paused mappings are only executed on demand.
+        let on_demand: HashSet<String> = run_requests
+            .keys()
+            .cloned()
+            .chain(added.drain().filter(|name| !paused.contains(name)))
+            .collect();
+
+        let mut executed = false;
+
+        for mapping in mappings.iter_mut().filter(|mapping| {
+            ((compute_delta(mapping).as_secs() <= 1 || initial_check)
+                && !paused.contains(&mapping.name))
+                || on_demand.contains(&mapping.name)
+        }) {
+            executed = true;
+
             let context = &mapping.name;
 
             let task_start = Instant::now();
+            let execution_time = chrono::Local::now();
+
+            // The delay of the execution after the check was due, which
+            // grows when previous checks take longer than expected.
+            let scheduler_lag = if initial_check {
+                Duration::ZERO
+            } else {
+                mapping
+                    .last_apply
+                    .elapsed()
+                    .saturating_sub(mapping.interval)
+            };
 
             debug!(
                 "{}: update last check time, set to {:?}",
@@ -144,8 +542,33 @@ async fn main() -> Result<(), anyhow::Error> {
 
             mapping.last_apply = task_start;
 
-            match execute_task(prom_client.clone(), icinga_client.clone(), mapping.clone()).await {
-                Ok(Ok(())) => {
+            // Every execution of a check is a trace of its own that covers
+            // the query, the processing and rendering of the result and the
+            // submission to the backends.
+            let span = trace_span!(
+                "check",
+                mapping = %mapping.name,
+                host = %mapping.host,
+                service = mapping.service.as_deref(),
+                scheduler_lag_ms = scheduler_lag.as_secs_f64() * 1000.0
+            );
+
+            let result = execute_task(prom_client.clone(), backends.clone(), mapping.clone())
+                .instrument(span.clone())
+                .await;
+
+            let _entered = span.enter();
+
+            // Keep the reason of a failure for the response to admin commands.
+            let failure = match &result {
+                Ok(Ok(_)) => None,
+                Ok(Err(err)) => Some(format!("{:#}", err)),
+                Err(err) => Some(err.to_string()),
+            };
+
+            let (success, submission_failed) = match result {
+                Ok(Ok(state)) => {
+                    mapping.state = state;
                     debug!(
                         "'{}': check finished in {} millisecond(s)",
                         context,
@@ -156,6 +579,7 @@ async fn main() -> Result<(), anyhow::Error> {
                         context,
                         compute_delta(mapping).as_secs()
                     );
+                    (true, false)
                 }
                 Ok(Err(err)) => {
                     error!("'{}': failed to finish check: {:?}", context, err);
@@ -164,6 +588,7 @@ async fn main() -> Result<(), anyhow::Error> {
                         context,
                         compute_delta(mapping).as_secs()
                     );
+                    (false, err.is::<SubmissionError>())
                 }
                 Err(err) => {
                     error!("'{}': failed to finish check: {:?}", context, err);
@@ -172,11 +597,176 @@ async fn main() -> Result<(), anyhow::Error> {
                         context,
                         compute_delta(mapping).as_secs()
                     );
+                    (false, false)
                 }
+            };
+
+            mapping.state.last_execution = Some(execution_time);
+            mapping.state.last_error = failure.clone();
+
+            if let Some(metrics) = &metrics {
+                metrics.record_check(
+                    mapping,
+                    task_start.elapsed(),
+                    scheduler_lag,
+                    success,
+                    submission_failed,
+                );
+            }
+
+            if let Some(heartbeat) = heartbeat.as_mut() {
+                heartbeat.record(success);
+            }
+
+            if let Some(summary_log) = summary_log.as_mut() {
+                summary_log.record(mapping, task_start.elapsed(), success);
+            }
+
+            if let Some(requests) = run_requests.remove(&mapping.name) {
+                let response =
+                    admin::run_response(mapping, task_start.elapsed(), failure.as_deref());
+                for request in requests {
+                    request.reply(response.clone());
+                }
+            }
+        }
+
+        // A mapping may have been removed by a reload in the meantime.
+        for (name, requests) in run_requests {
+            for request in requests {
+                let err = format!("unknown mapping '{}'", name);
+                request.reply(serde_json::json!({ "error": err }));
+            }
+        }
+
+        if let (Some(state_file), true) = (&state_file, executed) {
+            if let Err(e) = state_file.save(&mappings) {
+                error!("Failed to persist the state of the mappings: {:#}", e);
+            }
+        }
+
+        if let Some(heartbeat) = heartbeat.as_mut() {
+            if heartbeat.delta().as_secs() <= 1 {
+                let overdue = mappings
+                    .iter()
+                    .filter(|mapping| !paused.contains(&mapping.name))
+                    .filter(|mapping| compute_delta(mapping).is_zero())
+                    .count();
+                let result = heartbeat.check_result(&mappings, overdue, get_unix_timestamp()?);
+                for backend in backends.iter() {
+                    if let Err(err) = backend.send(heartbeat.mapping(), &result).await {
+                        error!("Failed to send heartbeat to {}: {:#}", backend.name(), err);
+                    }
+                }
+            }
+        }
+
+        if let Some(summary_log) = summary_log.as_mut() {
+            if summary_log.delta().as_secs() <= 1 {
+                for line in summary_log.summarize(&mappings) {
+                    info!("{}", line);
+                }
+            }
+        }
+
+        if initial_check {
+            if let Some(metrics) = &metrics {
+                metrics.set_ready();
             }
         }
+
         initial_check = false;
-        let sleep_secs = mappings.iter().map(compute_delta).min().unwrap();
-        std::thread::sleep(sleep_secs);
+        // Paused mappings are overdue, but the loop must not spin on them.
+        // When every mapping is paused only admin commands wake it up.
+        let mut sleep_secs = mappings
+            .iter()
+            .filter(|mapping| !paused.contains(&mapping.name))
+            .map(compute_delta)
+            .min()
+            .unwrap_or(IDLE_INTERVAL);
+        if let Some(heartbeat) = &heartbeat {
+            sleep_secs = sleep_secs.min(heartbeat.delta());
+        }
+        if let Some(summary_log) = &summary_log {
+            sleep_secs = sleep_secs.min(summary_log.delta());
+        }
+        match &admin {
+            Some(admin) => admin_requests.extend(admin.wait(sleep_secs)),
+            None => std::thread::sleep(sleep_secs),
+        }
+    }
+}
+
+/// Read the configuration file and parse it as YAML.
+fn read_config(path: &str) -> Result<yaml_rust::yaml::Hash, anyhow::Error> {
+    let raw_conf = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read configuration file '{}'", path))?;
+    config::parse_yaml(&raw_conf)
+        .with_context(|| format!("failed to parse configuration file '{}'", path))
+}
+
+/// The parts of the configuration that are applied again when it is
+/// reloaded via the admin interface. Everything else, e.g. the metrics
+/// endpoint or the admin socket, is only read on startup.
+struct Reloaded {
+    mappings: Vec<Mapping>,
+    prom_client: prometheus_http_query::Client,
+    icinga_config: Option<IcingaConfig>,
+    icinga_client: Option<IcingaClient>,
+    backend_configs: Vec<BackendConfig>,
+    backends: Vec<backend::Backend>,
+}
+
+impl Reloaded {
+    /// Read and validate the configuration file. Unlike on startup an
+    /// invalid configuration is not fatal, the error is returned instead
+    /// so that the previous configuration stays in place.
+    fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let config = read_config(path)?;
+
+        let mappings = config::parse_mappings(config.clone())
+            .context("failed to parse mappings from configuration")?;
+        if mappings.is_empty() {
+            bail!("no mappings configured");
+        }
+
+        let prom_client = prometheus::create_client(
+            config::parse_prom_section(&config)
+                .context("failed to parse Prometheus section from configuration")?,
+        )
+        .context("failed to initialize Prometheus API client")?;
+
+        let backend_configs = config::parse_backends_section(&config)
+            .context("failed to parse backends section from configuration")?;
+
+        let icinga_config = if backend_configs
+            .iter()
+            .any(|b| matches!(b, BackendConfig::Icinga))
+        {
+            Some(
+                config::parse_icinga_section(&config)
+                    .context("failed to parse Icinga section from configuration")?,
+            )
+        } else {
+            None
+        };
+
+        let icinga_client = icinga_config
+            .as_ref()
+            .map(|c| IcingaClient::new(c.clone()))
+            .transpose()
+            .context("failed to initialize Icinga API client")?;
+
+        let backends = backend::build(&backend_configs, icinga_client.as_ref())
+            .context("failed to initialize backends")?;
+
+        Ok(Reloaded {
+            mappings,
+            prom_client,
+            icinga_config,
+            icinga_client,
+            backend_configs,
+            backends,
+        })
     }
 }
@@ -0,0 +1,320 @@
+use crate::types::Mapping;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// The telemetry that is collected about the executions of a single mapping.
+#[derive(Debug, Default)]
+struct MappingMetrics {
+    checks: u64,
+    failures: u64,
+    submission_failures: u64,
+    check_duration: Duration,
+    query_duration: Option<Duration>,
+    scheduler_lag: Duration,
+    last_exit_value: Option<u8>,
+}
+
+/// Collects telemetry about vec2checkd itself that is exposed in the
+/// Prometheus text format, so that the daemon can be monitored by the very
+/// Prometheus it queries. The collector can be cloned and shared with the
+/// thread that serves the metrics as well as the health and readiness
+/// endpoints.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    mappings: Arc<Mutex<BTreeMap<String, MappingMetrics>>>,
+    spool_dirs: Vec<PathBuf>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Metrics {
+    /// Create a collector. The number of pending check results in each of
+    /// the spool directories is determined whenever the metrics are scraped.
+    pub(crate) fn new(spool_dirs: Vec<PathBuf>) -> Self {
+        Metrics {
+            mappings: Arc::default(),
+            spool_dirs,
+            ready: Arc::default(),
+        }
+    }
+
+    /// Report vec2checkd as ready, i.e. the API clients are initialized and
+    /// every check was executed at least once.
+    pub(crate) fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    /// Record an execution of a mapping. The duration of the query and the
+    /// last exit value are taken from the state of the mapping.
+    pub(crate) fn record_check(
+        &self,
+        mapping: &Mapping,
+        duration: Duration,
+        scheduler_lag: Duration,
+        success: bool,
+        submission_failed: bool,
+    ) {
+        let mut mappings = self.mappings.lock().unwrap();
+        let metrics = mappings.entry(mapping.name.clone()).or_default();
+        metrics.checks += 1;
+        if !success {
+            metrics.failures += 1;
+        }
+        if submission_failed {
+            metrics.submission_failures += 1;
+        }
+        metrics.check_duration = duration;
+        metrics.query_duration = mapping.state.timing.query;
+        metrics.scheduler_lag = scheduler_lag;
+        metrics.last_exit_value = mapping.state.last_exit_value;
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let mappings = self.mappings.lock().unwrap();
+        let mut out = String::new();
+
+        let mut family =
+            |name: &str,
+             kind: &str,
+             help: &str,
+             value: &dyn Fn(&MappingMetrics) -> Option<String>| {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} {}", name, kind);
+                for (mapping, metrics) in mappings.iter() {
+                    if let Some(value) = value(metrics) {
+                        let _ = writeln!(
+                            out,
+                            "{}{{mapping=\"{}\"}} {}",
+                            name,
+                            escape_label_value(mapping),
+                            value
+                        );
+                    }
+                }
+            };
+
+        family(
+            "vec2checkd_checks_total",
+            "counter",
+            "Number of executed checks.",
+            &|m| Some(m.checks.to_string()),
+        );
+        family(
+            "vec2checkd_check_failures_total",
+            "counter",
+            "Number of checks that failed to finish.",
+            &|m| Some(m.failures.to_string()),
+        );
+        family(
+            "vec2checkd_submission_failures_total",
+            "counter",
+            "Number of checks whose result could not be sent to at least one backend.",
+            &|m| Some(m.submission_failures.to_string()),
+        );
+        family(
+            "vec2checkd_check_duration_seconds",
+            "gauge",
+            "Duration of the last execution of the check.",
+            &|m| Some(m.check_duration.as_secs_f64().to_string()),
+        );
+        family(
+            "vec2checkd_query_duration_seconds",
+            "gauge",
+            "Duration of the last PromQL query of the check.",
+            &|m| m.query_duration.map(|d| d.as_secs_f64().to_string()),
+        );
+        family(
+            "vec2checkd_scheduler_lag_seconds",
+            "gauge",
+            "Delay of the last execution of the check after it was due.",
+            &|m| Some(m.scheduler_lag.as_secs_f64().to_string()),
+        );
+        family(
+            "vec2checkd_last_exit_value",
+            "gauge",
+            "Exit value of the last check result that was submitted.",
+            &|m| m.last_exit_value.map(|v| v.to_string()),
+        );
+
+        if !self.spool_dirs.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP vec2checkd_spool_depth Number of check results in the spool directory that were not reaped yet."
+            );
+            let _ = writeln!(out, "# TYPE vec2checkd_spool_depth gauge");
+            for dir in self.spool_dirs.iter() {
+                match count_spooled_results(dir) {
+                    Ok(depth) => {
+                        let _ = writeln!(
+                            out,
+                            "vec2checkd_spool_depth{{path=\"{}\"}} {}",
+                            escape_label_value(&dir.to_string_lossy()),
+                            depth
+                        );
+                    }
+                    Err(err) => warn!("Failed to determine spool depth: {:#}", err),
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Bind to the given address and serve the metrics in a separate thread,
+    /// as the main loop blocks while it waits for the next check to be due.
+    pub(crate) fn serve(self, addr: SocketAddr) -> Result<(), anyhow::Error> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("failed to bind metrics listener to {}", addr))?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| self.handle(stream));
+                if let Err(err) = result {
+                    debug!("Failed to handle HTTP request: {:#}", err);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Answer a single HTTP request. Only the request line is evaluated.
+    fn handle(&self, stream: TcpStream) -> Result<(), anyhow::Error> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // Consume the request headers before responding.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = self.route(parts.next(), parts.next());
+
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Determine the status and body of the response to a request.
+    /// The liveness endpoint succeeds as long as the listener is running.
+    fn route(&self, method: Option<&str>, path: Option<&str>) -> (&'static str, String) {
+        match (method, path) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), Some("/healthz")) => ("200 OK", String::from("OK\n")),
+            (Some("GET"), Some("/readyz")) if self.ready.load(Ordering::Relaxed) => {
+                ("200 OK", String::from("OK\n"))
+            }
+            (Some("GET"), Some("/readyz")) => {
+                ("503 Service Unavailable", String::from("Not Ready\n"))
+            }
+            (Some("GET"), Some(_)) => ("404 Not Found", String::from("Not Found\n")),
+            _ => (
+                "405 Method Not Allowed",
+                String::from("Method Not Allowed\n"),
+            ),
+        }
+    }
+}
+
+/// Count the check results in a checkresult spool directory, i.e. the
+/// files that are marked as complete by an accompanying ".ok" file.
+fn count_spooled_results(dir: &PathBuf) -> Result<usize, anyhow::Error> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read spool directory {:?}", dir))?;
+    Ok(entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".ok"))
+        .count())
+}
+
+/// Escape a label value as required by the Prometheus text format.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("Node status"), "Node status");
+        assert_eq!(
+            escape_label_value("a \"quoted\" \\ name\n"),
+            "a \\\"quoted\\\" \\\\ name\\n"
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let metrics = Metrics::new(vec![]);
+        assert_eq!(metrics.route(Some("GET"), Some("/healthz")).0, "200 OK");
+        assert_eq!(
+            metrics.route(Some("GET"), Some("/readyz")).0,
+            "503 Service Unavailable"
+        );
+        metrics.set_ready();
+        assert_eq!(metrics.route(Some("GET"), Some("/readyz")).0, "200 OK");
+        assert_eq!(metrics.route(Some("GET"), Some("/")).0, "404 Not Found");
+        assert_eq!(
+            metrics.route(Some("POST"), Some("/metrics")).0,
+            "405 Method Not Allowed"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new(vec![]);
+        assert!(!metrics.render().contains("mapping="));
+
+        metrics.mappings.lock().unwrap().insert(
+            "Node status".to_string(),
+            MappingMetrics {
+                checks: 3,
+                failures: 1,
+                submission_failures: 1,
+                check_duration: Duration::from_millis(250),
+                query_duration: None,
+                scheduler_lag: Duration::ZERO,
+                last_exit_value: Some(2),
+            },
+        );
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE vec2checkd_checks_total counter\n"));
+        assert!(rendered.contains("vec2checkd_checks_total{mapping=\"Node status\"} 3\n"));
+        assert!(rendered.contains("vec2checkd_check_failures_total{mapping=\"Node status\"} 1\n"));
+        assert!(
+            rendered.contains("vec2checkd_check_duration_seconds{mapping=\"Node status\"} 0.25\n")
+        );
+        assert!(!rendered.contains("vec2checkd_query_duration_seconds{"));
+        assert!(rendered.contains("vec2checkd_last_exit_value{mapping=\"Node status\"} 2\n"));
+        assert!(!rendered.contains("vec2checkd_spool_depth"));
+    }
+}
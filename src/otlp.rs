@@ -0,0 +1,53 @@
+use crate::types::TracingConfig;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::time::Duration;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Build a tracing layer that exports the spans of vec2checkd, e.g. of each
+/// execution of a check, to an OpenTelemetry collector via OTLP/HTTP.
+/// Log messages are unaffected as they are still passed on to the logger.
+pub(crate) fn layer<S>(config: TracingConfig) -> Result<impl Layer<S>, anyhow::Error>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(config.endpoint.as_str())
+        .with_timeout(Duration::from_secs(10))
+        .build()?;
+
+    let resource = Resource::builder()
+        .with_service_name(config.service_name)
+        .with_attributes([
+            KeyValue::new("service.version", crate::VERSION),
+            KeyValue::new("host.name", crate::util::get_hostname()),
+        ])
+        .build();
+
+    // The batch processor exports the spans from a background thread, so
+    // the checks are never delayed by a slow or unreachable collector.
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_filter(filter_fn(is_exported)))
+}
+
+/// Spans of dependencies like the HTTP client are ignored. Only warnings and
+/// errors are attached to the spans as events, an error additionally marks
+/// the span as failed.
+fn is_exported(metadata: &Metadata<'_>) -> bool {
+    metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+        && (metadata.is_span() || *metadata.level() <= Level::WARN)
+}
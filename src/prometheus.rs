@@ -11,7 +11,21 @@ pub(crate) fn create_client(config: crate::types::PromConfig) -> Result<Client,
         }
     }
 
+    if let Some(version) = config.tls.min_version {
+        builder = builder.min_tls_version(version);
+    }
+
+    if let Some(version) = config.tls.max_version {
+        builder = builder.max_tls_version(version);
+    }
+
+    if config.tls.use_native_roots {
+        for cert in crate::util::load_native_root_certificates()? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
     let base_client = builder.build()?;
 
-    Ok(Client::from(base_client, &config.host.to_string())?)
+    Ok(Client::from(base_client, config.host.as_ref())?)
 }
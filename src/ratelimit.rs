@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token bucket that is refilled at a constant rate and holds at most
+/// `burst` tokens. Each request consumes one token.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Consume a token and return the time to wait until the token
+    /// is actually available. The token is reserved immediately, so
+    /// concurrent callers queue up behind each other.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Limits the rate of outgoing requests to an API. The limiter can be
+/// cloned and shared across tokio tasks.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: f64, burst: u32) -> Self {
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(
+                requests_per_second,
+                burst,
+                Instant::now(),
+            ))),
+        }
+    }
+
+    /// Wait until the next request may be sent.
+    pub(crate) async fn acquire(&self) {
+        let wait = self.bucket.lock().unwrap().reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 3, now);
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(bucket.reserve(now), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(now), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 1, now);
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(
+            bucket.reserve(now + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        // The refill never exceeds the burst size.
+        assert_eq!(
+            bucket.reserve(now + Duration::from_secs(10)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            bucket.reserve(now + Duration::from_secs(10)),
+            Duration::from_secs(1)
+        );
+    }
+}
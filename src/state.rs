@@ -0,0 +1,172 @@
+use crate::types::{Mapping, MappingState};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// The part of the state of a mapping that survives a restart of the
+/// daemon, so that e.g. the number of consecutive threshold breaches or
+/// the flap history are not reset and no notifications are re-triggered.
+/// Label sets cannot be used as keys of JSON objects, thus the maps of
+/// the in-memory state are stored as lists of pairs.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedState {
+    breaches: Vec<(BTreeMap<String, String>, u32)>,
+    overall_breaches: u32,
+    result_count_breaches: u32,
+    history: Vec<u8>,
+    stable_exit_value: Option<u8>,
+    samples: Vec<(BTreeMap<String, String>, f64, f64)>,
+    #[serde(default)]
+    last_exit_value: Option<u8>,
+    #[serde(default)]
+    unchanged_cycles: u32,
+    #[serde(default)]
+    warning_cycles: u32,
+    #[serde(default)]
+    previous_values: Vec<(BTreeMap<String, String>, f64)>,
+}
+
+impl From<&MappingState> for PersistedState {
+    fn from(state: &MappingState) -> Self {
+        PersistedState {
+            breaches: state
+                .breaches
+                .iter()
+                .map(|(labels, count)| (labels.clone(), *count))
+                .collect(),
+            overall_breaches: state.overall_breaches,
+            result_count_breaches: state.result_count_breaches,
+            history: state.history.iter().copied().collect(),
+            stable_exit_value: state.stable_exit_value,
+            samples: state
+                .samples
+                .iter()
+                .map(|(labels, (value, time))| (labels.clone(), *value, *time))
+                .collect(),
+            last_exit_value: state.last_exit_value,
+            unchanged_cycles: state.unchanged_cycles,
+            warning_cycles: state.warning_cycles,
+            previous_values: state
+                .previous_values
+                .iter()
+                .map(|(labels, value)| (labels.clone(), *value))
+                .collect(),
+        }
+    }
+}
+
+impl PersistedState {
+    fn restore(self, state: &mut MappingState) {
+        state.breaches = self.breaches.into_iter().collect();
+        state.overall_breaches = self.overall_breaches;
+        state.result_count_breaches = self.result_count_breaches;
+        state.history = self.history.into_iter().collect();
+        state.stable_exit_value = self.stable_exit_value;
+        state.samples = self
+            .samples
+            .into_iter()
+            .map(|(labels, value, time)| (labels, (value, time)))
+            .collect();
+        state.last_exit_value = self.last_exit_value;
+        state.unchanged_cycles = self.unchanged_cycles;
+        state.warning_cycles = self.warning_cycles;
+        state.previous_values = self.previous_values.into_iter().collect();
+    }
+}
+
+/// A JSON file that holds the state of all mappings, keyed by the name of
+/// each mapping.
+pub(crate) struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        StateFile { path }
+    }
+
+    /// Restore the state of the mappings from the file and return the
+    /// number of mappings that were restored. Mappings that are not in the
+    /// file (yet) keep their initial state. A missing file is not an error.
+    pub(crate) fn load(&self, mappings: &mut [Mapping]) -> Result<usize, anyhow::Error> {
+        let raw = match fs::read_to_string(&self.path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read state file {:?}", self.path))
+            }
+        };
+
+        let mut states: HashMap<String, PersistedState> = serde_json::from_str(&raw)
+            .with_context(|| format!("failed to parse state file {:?}", self.path))?;
+
+        let mut restored = 0;
+        for mapping in mappings.iter_mut() {
+            if let Some(state) = states.remove(&mapping.name) {
+                state.restore(&mut mapping.state);
+                restored += 1;
+            }
+        }
+
+        Ok(restored)
+    }
+
+    /// Write the state of the mappings to the file. The state is written
+    /// to a temporary file first that replaces the previous file, so that
+    /// a crash while writing does not leave a truncated file behind.
+    pub(crate) fn save(&self, mappings: &[Mapping]) -> Result<(), anyhow::Error> {
+        let states: HashMap<&str, PersistedState> = mappings
+            .iter()
+            .map(|mapping| (mapping.name.as_str(), PersistedState::from(&mapping.state)))
+            .collect();
+
+        let raw = serde_json::to_string(&states)?;
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        fs::write(&tmp_path, raw)
+            .with_context(|| format!("failed to write state file {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to replace state file {:?}", self.path))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persisted_state_round_trip() {
+        let labels = BTreeMap::from([("node".to_string(), "worker-01".to_string())]);
+        let mut state = MappingState::default();
+        state.breaches.insert(labels.clone(), 2);
+        state.overall_breaches = 2;
+        state.result_count_breaches = 1;
+        state.history.extend([0, 2, 2]);
+        state.stable_exit_value = Some(2);
+        state.samples.insert(labels, (42.0, 1640995200.0));
+        state.last_exit_value = Some(2);
+        state.unchanged_cycles = 3;
+
+        let raw = serde_json::to_string(&PersistedState::from(&state)).unwrap();
+        let mut restored = MappingState::default();
+        serde_json::from_str::<PersistedState>(&raw)
+            .unwrap()
+            .restore(&mut restored);
+
+        assert_eq!(restored.breaches, state.breaches);
+        assert_eq!(restored.overall_breaches, 2);
+        assert_eq!(restored.result_count_breaches, 1);
+        assert_eq!(restored.history, state.history);
+        assert_eq!(restored.stable_exit_value, Some(2));
+        assert_eq!(restored.samples, state.samples);
+        assert_eq!(restored.last_exit_value, Some(2));
+        assert_eq!(restored.unchanged_cycles, 3);
+    }
+}
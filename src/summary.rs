@@ -0,0 +1,168 @@
+use crate::types::Mapping;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The outcomes of the checks of a single mapping since the last summary.
+#[derive(Debug, Default)]
+struct MappingStats {
+    executed: u32,
+    failed: u32,
+    duration: Duration,
+    query_duration: Duration,
+    queries: u32,
+}
+
+/// Regularly sums up the executions of every mapping in the log, so that
+/// operators get a quick picture of the health of vec2checkd without the
+/// metrics endpoint or debug logs.
+pub(crate) struct SummaryLog {
+    interval: Duration,
+    last_summary: Instant,
+    stats: HashMap<String, MappingStats>,
+}
+
+impl SummaryLog {
+    pub(crate) fn new(interval: Duration) -> Self {
+        SummaryLog {
+            interval,
+            last_summary: Instant::now(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Record the outcome of a single check. The duration of the query is
+    /// taken from the state of the mapping after a successful check.
+    pub(crate) fn record(&mut self, mapping: &Mapping, duration: Duration, success: bool) {
+        let stats = self.stats.entry(mapping.name.clone()).or_default();
+        stats.executed += 1;
+        stats.duration += duration;
+        if !success {
+            stats.failed += 1;
+        } else if let Some(query) = mapping.state.timing.query {
+            stats.query_duration += query;
+            stats.queries += 1;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn delta(&self) -> Duration {
+        self.interval.saturating_sub(self.last_summary.elapsed())
+    }
+
+    /// Build the summary lines from the outcomes recorded since the last
+    /// summary and reset the counters. The first line covers all mappings,
+    /// every other line a single mapping including its current state.
+    pub(crate) fn summarize(&mut self, mappings: &[Mapping]) -> Vec<String> {
+        let executed: u32 = self.stats.values().map(|s| s.executed).sum();
+        let failed: u32 = self.stats.values().map(|s| s.failed).sum();
+
+        let mut lines = vec![format!(
+            "Summary of the last {} second(s): {} check(s) of {} mapping(s) executed, {} failed",
+            self.last_summary.elapsed().as_secs(),
+            executed,
+            mappings.len(),
+            failed
+        )];
+
+        for mapping in mappings.iter() {
+            let stats = self.stats.remove(&mapping.name).unwrap_or_default();
+
+            let mut line = format!(
+                "'{}': {} check(s) executed, {} failed",
+                mapping.name, stats.executed, stats.failed
+            );
+            if stats.executed > 0 {
+                line.push_str(&format!(
+                    ", average duration {:.1} ms",
+                    stats.duration.as_secs_f64() * 1000.0 / stats.executed as f64
+                ));
+            }
+            if stats.queries > 0 {
+                line.push_str(&format!(
+                    ", average query duration {:.1} ms",
+                    stats.query_duration.as_secs_f64() * 1000.0 / stats.queries as f64
+                ));
+            }
+            match mapping.state.last_exit_value {
+                Some(exit_value) => line.push_str(&format!(
+                    ", current state {}",
+                    crate::icinga::real_exit_value_to_status(
+                        mapping.service.is_some(),
+                        &exit_value
+                    )
+                )),
+                None => line.push_str(", no check result submitted yet"),
+            }
+
+            lines.push(line);
+        }
+
+        self.stats.clear();
+        self.last_summary = Instant::now();
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heartbeat::Heartbeat;
+    use crate::types::HeartbeatConfig;
+
+    fn seed_mapping(name: &str) -> Mapping {
+        let mut mapping = Heartbeat::new(HeartbeatConfig {
+            host: "foo".to_string(),
+            service: Some("bar".to_string()),
+            interval: Duration::from_secs(60),
+        })
+        .mapping()
+        .clone();
+        mapping.name = name.to_string();
+        mapping
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut first = seed_mapping("first");
+        let second = seed_mapping("second");
+        let mut summary = SummaryLog::new(Duration::from_secs(900));
+
+        first.state.timing.query = Some(Duration::from_millis(20));
+        first.state.last_exit_value = Some(2);
+        summary.record(&first, Duration::from_millis(100), true);
+        summary.record(&first, Duration::from_millis(200), false);
+
+        let mappings = vec![first, second];
+        let lines = summary.summarize(&mappings);
+        assert_eq!(
+            lines[0],
+            "Summary of the last 0 second(s): 2 check(s) of 2 mapping(s) executed, 1 failed"
+        );
+        assert_eq!(
+            lines[1],
+            "'first': 2 check(s) executed, 1 failed, average duration 150.0 ms, average query duration 20.0 ms, current state CRITICAL"
+        );
+        assert_eq!(
+            lines[2],
+            "'second': 0 check(s) executed, 0 failed, no check result submitted yet"
+        );
+
+        // The counters are reset after each summary.
+        let lines = summary.summarize(&mappings);
+        assert_eq!(
+            lines[1],
+            "'first': 0 check(s) executed, 0 failed, current state CRITICAL"
+        );
+
+        // Host objects are submitted as UP (0) or DOWN (1).
+        let mut host = seed_mapping("host");
+        host.service = None;
+        host.state.last_exit_value = Some(1);
+        let lines = summary.summarize(&[host]);
+        assert_eq!(
+            lines[1],
+            "'host': 0 check(s) executed, 0 failed, current state DOWN"
+        );
+    }
+}
@@ -1,9 +1,16 @@
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime, SecondsFormat, Weekday};
+use handlebars::Handlebars;
 use nagios_range::NagiosRange;
+use regex::Regex;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::default::Default;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use url::Url;
 
@@ -14,6 +21,16 @@ use url::Url;
 pub(crate) struct ThresholdPair {
     pub warning: Option<NagiosRange>,
     pub critical: Option<NagiosRange>,
+    pub unknown: Option<NagiosRange>,
+    pub ok: Option<OkRange>,
+}
+
+/// A range of values that are considered OK. Values outside of this
+/// range result in the configured state (as a service exit value).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OkRange {
+    pub range: NagiosRange,
+    pub exit_value: u8,
 }
 
 /// NagiosRange does not impl Serialize, so the blanket impl does
@@ -25,13 +42,59 @@ impl Serialize for ThresholdPair {
     where
         S: Serializer,
     {
-        let mut tp = serializer.serialize_struct("ThresholdPair", 2)?;
+        let mut tp = serializer.serialize_struct("ThresholdPair", 4)?;
         tp.serialize_field("warning", &self.warning.map(|w| w.to_string()))?;
         tp.serialize_field("critical", &self.critical.map(|c| c.to_string()))?;
+        tp.serialize_field("unknown", &self.unknown.map(|u| u.to_string()))?;
+        tp.serialize_field("ok", &self.ok.map(|o| o.range.to_string()))?;
         tp.end()
     }
 }
 
+/// The identity of the vec2checkd instance that renders a template, so
+/// that outputs can be traced back to their origin.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) struct DaemonInfo {
+    pub hostname: &'static str,
+    pub version: &'static str,
+}
+
+/// The warning and critical ranges of a mapping as numbers, so that
+/// templates may compute e.g. the headroom to a threshold.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ThresholdBounds {
+    pub warning: Option<RangeBounds>,
+    pub critical: Option<RangeBounds>,
+}
+
+impl ThresholdBounds {
+    pub(crate) fn from(thresholds: &ThresholdPair) -> Self {
+        ThresholdBounds {
+            warning: thresholds.warning.as_ref().map(RangeBounds::from),
+            critical: thresholds.critical.as_ref().map(RangeBounds::from),
+        }
+    }
+}
+
+/// The bounds of a single range. Infinite bounds are `None`, `inside`
+/// is true when values inside of the range alert (the '@' prefix).
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) struct RangeBounds {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub inside: bool,
+}
+
+impl From<&NagiosRange> for RangeBounds {
+    fn from(range: &NagiosRange) -> Self {
+        RangeBounds {
+            start: (!range.start_is_infinite()).then(|| *range.start()),
+            end: (!range.end_is_infinite()).then(|| *range.end()),
+            inside: range.checks_inside(),
+        }
+    }
+}
+
 /// A single mapping built from the configuration. This contains
 /// all necessary information to execute a PromQL query, process
 /// the resulting set of time series and convert the data to
@@ -50,7 +113,556 @@ pub(crate) struct Mapping {
     pub interval: Duration,
     pub last_apply: Instant,
     pub plugin_output: Option<String>,
+    /// Render the plugin output in strict mode, i.e. fail on missing fields
+    /// instead of rendering them empty.
+    pub plugin_output_strict: bool,
+    pub template_engine: TemplateEngine,
+    /// Limit the plugin output to this many bytes by dropping trailing
+    /// lines, see `icinga::truncate_plugin_output`.
+    pub max_output_length: Option<usize>,
     pub performance_data: PerformanceData,
+    pub sync_vars: bool,
+    pub request_timeout: Option<Duration>,
+    pub basic_auth: Option<IcingaBasicAuth>,
+    pub threshold_rules: Vec<ThresholdRule>,
+    pub baseline_query: Option<String>,
+    pub no_data: NoData,
+    pub non_finite: NonFinitePolicy,
+    pub result_count: ResultCount,
+    /// Submit a check result with this state instead of skipping the
+    /// submission when the query (or the processing of its result) fails.
+    pub query_error_state: Option<u8>,
+    /// Handlebars partials shared by all mappings, see `templates.partials`.
+    pub partials: Arc<BTreeMap<String, String>>,
+    pub aggregate: Option<Aggregation>,
+    pub transform: Option<Transform>,
+    pub breach_cycles: u32,
+    pub flapping: Option<FlapDetection>,
+    /// Maps each state that results from the threshold checks (indexed by
+    /// the service exit value) to the state that is used instead.
+    pub state_map: [u8; 4],
+    pub maintenance: Vec<MaintenanceWindow>,
+    pub label_states: Option<LabelStates>,
+    pub state_from_label: Option<StateFromLabel>,
+    /// Only time series that match all of these matchers are processed.
+    pub include: Vec<LabelMatcher>,
+    /// Time series that match any of these matchers are dropped.
+    pub exclude: Vec<LabelMatcher>,
+    pub deduplicate: Option<Deduplication>,
+    pub derive: Option<Derivation>,
+    pub default_output: DefaultOutput,
+    pub expect: Option<Expectation>,
+    pub conditions: Option<Conditions>,
+    pub threshold_templates: ThresholdTemplates,
+    pub grace_period: Option<GracePeriod>,
+    /// Escalate a WARNING state to CRITICAL once it persisted for this many
+    /// consecutive executions.
+    pub escalate_after: Option<u32>,
+    /// Check the number of time series whose value is within this range
+    /// against the thresholds instead of the values themselves.
+    pub count_breaching: Option<NagiosRange>,
+    /// Aggregate the time series per group of equal values of these labels
+    /// instead of the whole result set.
+    pub group_by: Vec<String>,
+    /// Drop time series whose value did not change since the previous
+    /// execution.
+    pub ignore_unchanged: bool,
+    /// Limit the values of the time series to this range.
+    pub clamp_min: Option<f64>,
+    pub clamp_max: Option<f64>,
+    /// Round the values to this number of decimal places before they are
+    /// checked against the thresholds.
+    pub precision: Option<u32>,
+    pub state: MappingState,
+}
+
+/// Take the state of a time series directly from one of its labels (e.g.
+/// `severity` on recorded alerts), bypassing the thresholds. Time series
+/// without the label or with an unknown label value are checked against
+/// the thresholds as usual.
+#[derive(Debug, Clone)]
+pub(crate) struct StateFromLabel {
+    pub label: String,
+    pub states: HashMap<String, u8>,
+}
+
+impl StateFromLabel {
+    pub(crate) fn exit_value(&self, labels: &BTreeMap<String, String>) -> Option<u8> {
+        labels
+            .get(&self.label)
+            .and_then(|v| self.states.get(v))
+            .copied()
+    }
+}
+
+/// Evaluate the value of a label (e.g. `state` of `node_systemd_unit_state`)
+/// against a table of states instead of comparing the sample value of each
+/// time series with thresholds.
+#[derive(Debug, Clone)]
+pub(crate) struct LabelStates {
+    pub label: String,
+    pub states: HashMap<String, u8>,
+    /// The state of time series whose label value is not in the table.
+    pub default: u8,
+}
+
+impl LabelStates {
+    /// Return the (service) exit value for a label set. Time series whose
+    /// sample value is zero are considered inactive and always OK, as this
+    /// is how info and state set metrics mark label values that do not apply.
+    pub(crate) fn exit_value(&self, labels: &BTreeMap<String, String>, value: f64) -> u8 {
+        if value == 0.0 {
+            return 0;
+        }
+        labels
+            .get(&self.label)
+            .and_then(|v| self.states.get(v))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A period after the start of the daemon during which non-OK results are
+/// not submitted as-is, e.g. while exporters are still warming up.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GracePeriod {
+    pub until: Instant,
+    pub action: GraceAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum GraceAction {
+    /// Submit UNKNOWN instead (skip for host objects).
+    Unknown,
+    /// Do not submit anything.
+    Skip,
+}
+
+/// A recurring period of (local) time during which the results of a mapping
+/// are either not submitted at all or forced to OK, e.g. for nightly batch
+/// jobs that are known to cause noise.
+#[derive(Debug, Clone)]
+pub(crate) struct MaintenanceWindow {
+    /// The days on which the window starts, every day if empty.
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub action: MaintenanceAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MaintenanceAction {
+    Skip,
+    Ok,
+}
+
+impl MaintenanceWindow {
+    /// Return true if the window covers the given point in time. Windows
+    /// whose end is before their start span midnight.
+    pub(crate) fn contains(&self, now: NaiveDateTime) -> bool {
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let time = now.time();
+
+        if self.start <= self.end {
+            starts_on(now.weekday()) && time >= self.start && time < self.end
+        } else {
+            (starts_on(now.weekday()) && time >= self.start)
+                || (starts_on(now.weekday().pred()) && time < self.end)
+        }
+    }
+}
+
+/// A mapping is considered flapping when its overall state changed more
+/// than `max_changes` times within the last `window` executions.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FlapDetection {
+    pub window: usize,
+    pub max_changes: usize,
+}
+
+/// A linear transformation that is applied to the value of each time
+/// series before it is evaluated, e.g. to convert bytes to GiB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Transform {
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Transform {
+    #[inline]
+    pub(crate) fn apply(&self, value: f64) -> f64 {
+        value * self.factor + self.offset
+    }
+}
+
+/// The aggregation operators that may be used to reduce the result set of
+/// a PromQL query to a single value before it is checked against thresholds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Aggregation {
+    Sum,
+    Avg,
+    Max,
+    Min,
+    Count,
+    /// The given percentile (0-100) of the values, interpolated linearly
+    /// between the closest ranks.
+    Percentile(f64),
+}
+
+impl Aggregation {
+    pub(crate) fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Max => values.iter().copied().fold(f64::NAN, f64::max),
+            Aggregation::Min => values.iter().copied().fold(f64::NAN, f64::min),
+            Aggregation::Count => values.len() as f64,
+            Aggregation::Percentile(p) => {
+                let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+                if sorted.is_empty() {
+                    return f64::NAN;
+                }
+                sorted.sort_by(f64::total_cmp);
+                let rank = p / 100.0 * (sorted.len() - 1) as f64;
+                let lower = sorted[rank.floor() as usize];
+                let upper = sorted[rank.ceil() as usize];
+                lower + (upper - lower) * rank.fract()
+            }
+        }
+    }
+}
+
+/// Which of several time series with identical label sets is kept, e.g.
+/// when HA Prometheus pairs behind a load balancer return duplicates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Deduplication {
+    Max,
+    Min,
+    Last,
+}
+
+/// Evaluate the change of each time series since the previous execution
+/// instead of its value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Derivation {
+    /// The difference between the current and the previous value.
+    Delta,
+    /// The per-second increase of a counter, taking counter resets into
+    /// account.
+    Rate,
+}
+
+/// Check that the value of each time series equals one of the expected
+/// values (e.g. the number of replicas of a deployment) instead of
+/// comparing it with thresholds.
+#[derive(Debug, Clone)]
+pub(crate) struct Expectation {
+    pub values: Vec<f64>,
+    /// The state (as a service exit value) of time series with a value that
+    /// is not expected.
+    pub exit_value: u8,
+}
+
+impl Expectation {
+    pub(crate) fn matches(&self, value: f64) -> bool {
+        self.values.contains(&value)
+    }
+}
+
+/// Additional PromQL queries with individual thresholds whose states are
+/// combined with the state of the main query into the overall state, e.g.
+/// "disk usage above 90% AND the prediction says it is full in 4h".
+#[derive(Debug, Clone)]
+pub(crate) struct Conditions {
+    pub operator: BoolOp,
+    pub items: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BoolOp {
+    /// The overall state is the best of all states, i.e. every condition
+    /// must be met to raise it.
+    And,
+    /// The overall state is the worst of all states.
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Condition {
+    pub name: String,
+    pub query: String,
+    pub thresholds: ThresholdPair,
+}
+
+/// The outcome of evaluating a single condition, exposed to templates.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct ConditionResult {
+    pub name: String,
+    pub values: Vec<f64>,
+    pub exit_value: u8,
+    pub exit_status: String,
+}
+
+impl Conditions {
+    /// Combine the (service) exit value of the main query with the exit
+    /// values of the conditions.
+    pub(crate) fn combine(&self, exit_value: u8, results: &[ConditionResult]) -> u8 {
+        let values = results.iter().map(|r| r.exit_value);
+        match self.operator {
+            BoolOp::And => values.fold(exit_value, u8::min),
+            BoolOp::Or => values.fold(exit_value, u8::max),
+        }
+    }
+}
+
+/// Options of the default plugin output, i.e. when no template is configured.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DefaultOutput {
+    /// The number of non-OK time series with the worst states that are listed
+    /// when the query result contains multiple items.
+    pub top: usize,
+    /// Append one line per non-OK time series to the first line.
+    pub long: bool,
+    /// The labels that identify a time series in the output, all if empty.
+    pub labels: Vec<String>,
+}
+
+/// The expected number of time series in a PromQL query result and the
+/// state (as a service exit value) when the number is out of bounds.
+#[derive(Debug, Clone)]
+pub(crate) struct ResultCount {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+    pub exit_value: u8,
+}
+
+impl Default for ResultCount {
+    fn default() -> Self {
+        ResultCount {
+            min: None,
+            max: None,
+            exit_value: 2,
+        }
+    }
+}
+
+/// The template engine that renders the plugin output of a mapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum TemplateEngine {
+    #[default]
+    Handlebars,
+    /// Jinja2-style templates rendered by MiniJinja.
+    Jinja,
+}
+
+/// How to handle time series whose value is NaN or ±Inf.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum NonFinitePolicy {
+    /// Set the state of the time series to UNKNOWN (DOWN for hosts).
+    #[default]
+    Unknown,
+    /// Drop the time series from the query result.
+    Drop,
+    /// Replace the value of the time series with a fixed value.
+    Substitute(f64),
+}
+
+/// The state (as a service exit value) and optionally a plugin output
+/// template that are used when a PromQL query returns an empty result.
+#[derive(Debug, Clone)]
+pub(crate) struct NoData {
+    pub exit_value: u8,
+    pub plugin_output: Option<String>,
+}
+
+impl Default for NoData {
+    fn default() -> Self {
+        NoData {
+            exit_value: 3,
+            plugin_output: None,
+        }
+    }
+}
+
+impl Mapping {
+    /// Return true if a time series with the given label set passes the
+    /// include and exclude filters of the mapping.
+    pub(crate) fn selects(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.include.iter().all(|m| m.matches(labels))
+            && !self.exclude.iter().any(|m| m.matches(labels))
+    }
+
+    /// Return the thresholds of the first rule that matches the label set
+    /// of a time series or the default thresholds of the mapping if none
+    /// of the rules match.
+    /// Templated thresholds of the mapping are rendered from the label set;
+    /// when that fails the static thresholds are returned.
+    pub(crate) fn thresholds_for(
+        &self,
+        labels: &BTreeMap<String, String>,
+    ) -> Cow<'_, ThresholdPair> {
+        self.try_thresholds_for(labels)
+            .unwrap_or(Cow::Borrowed(&self.thresholds))
+    }
+
+    /// Like `thresholds_for` but fails when templated thresholds cannot be
+    /// rendered from the label set.
+    pub(crate) fn try_thresholds_for(
+        &self,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<Cow<'_, ThresholdPair>, anyhow::Error> {
+        if let Some(rule) = self
+            .threshold_rules
+            .iter()
+            .find(|rule| rule.matchers.iter().all(|m| m.matches(labels)))
+        {
+            return Ok(Cow::Borrowed(&rule.thresholds));
+        }
+
+        if self.threshold_templates.is_empty() {
+            return Ok(Cow::Borrowed(&self.thresholds));
+        }
+
+        self.threshold_templates
+            .render(&self.thresholds, labels)
+            .map(Cow::Owned)
+    }
+}
+
+/// Warning and critical ranges that are handlebars templates over the labels
+/// of a time series, e.g. `@0:{{ labels.capacity }}`, for exporters that
+/// publish per-entity limits as labels.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ThresholdTemplates {
+    pub warning: Option<String>,
+    pub critical: Option<String>,
+}
+
+impl ThresholdTemplates {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.warning.is_none() && self.critical.is_none()
+    }
+
+    /// Render and parse the templates for the label set of a time series
+    /// and return them together with the static ranges in `base`.
+    pub(crate) fn render(
+        &self,
+        base: &ThresholdPair,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<ThresholdPair, anyhow::Error> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        let context = serde_json::json!({ "labels": labels });
+
+        let render = |template: &str| -> Result<NagiosRange, anyhow::Error> {
+            let raw = handlebars
+                .render_template(template, &context)
+                .with_context(|| format!("failed to render threshold template '{}'", template))?;
+            NagiosRange::from(raw.trim())
+                .with_context(|| format!("failed to parse rendered threshold '{}'", raw))
+        };
+
+        let mut thresholds = base.clone();
+        if let Some(warning) = &self.warning {
+            thresholds.warning = Some(render(warning)?);
+        }
+        if let Some(critical) = &self.critical {
+            thresholds.critical = Some(render(critical)?);
+        }
+        Ok(thresholds)
+    }
+}
+
+/// A set of thresholds that only applies to time series whose labels
+/// match all of the label matchers.
+#[derive(Debug, Clone)]
+pub(crate) struct ThresholdRule {
+    pub matchers: Vec<LabelMatcher>,
+    pub thresholds: ThresholdPair,
+}
+
+/// A label matcher analogous to those used in PromQL selectors,
+/// e.g. `mountpoint="/var"` or `device=~"sd.*"`.
+#[derive(Debug, Clone)]
+pub(crate) struct LabelMatcher {
+    pub label: String,
+    pub op: LabelMatchOp,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum LabelMatchOp {
+    Equal(String),
+    NotEqual(String),
+    Regex(Regex),
+    NotRegex(Regex),
+}
+
+impl LabelMatcher {
+    pub(crate) fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        // As in PromQL a missing label is equivalent to an empty label value.
+        let value = labels.get(&self.label).map(String::as_str).unwrap_or("");
+        match &self.op {
+            LabelMatchOp::Equal(v) => value == v,
+            LabelMatchOp::NotEqual(v) => value != v,
+            LabelMatchOp::Regex(re) => re.is_match(value),
+            LabelMatchOp::NotRegex(re) => !re.is_match(value),
+        }
+    }
+}
+
+/// The state of a mapping that is carried over from one execution to the
+/// next one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MappingState {
+    pub timing: Timing,
+    /// The number of consecutive executions in which each time series
+    /// (identified by its label set) breached a threshold.
+    pub breaches: HashMap<BTreeMap<String, String>, u32>,
+    /// The number of consecutive executions in which any time series
+    /// breached a threshold.
+    pub overall_breaches: u32,
+    /// The number of consecutive executions in which the number of time
+    /// series in the query result was out of bounds.
+    pub result_count_breaches: u32,
+    /// The overall exit values of the most recent executions.
+    pub history: VecDeque<u8>,
+    /// The last exit value that was reported while the mapping was not
+    /// flapping.
+    pub stable_exit_value: Option<u8>,
+    /// The value of each time series and the time (as UNIX timestamp) it
+    /// was queried at in the previous execution, used to derive deltas
+    /// and rates.
+    pub samples: HashMap<BTreeMap<String, String>, (f64, f64)>,
+    /// The value of each time series in the previous execution, used to
+    /// drop time series that did not change.
+    pub previous_values: HashMap<BTreeMap<String, String>, f64>,
+    /// The overall exit value that was reported in the previous execution.
+    pub last_exit_value: Option<u8>,
+    /// The number of executions since the overall exit value last changed.
+    pub unchanged_cycles: u32,
+    /// The number of consecutive executions with an overall WARNING state.
+    pub warning_cycles: u32,
+    /// The outcomes of the conditions in the current execution.
+    pub conditions: Vec<ConditionResult>,
+    /// The time of the most recent execution, whether it succeeded or not.
+    pub last_execution: Option<DateTime<Local>>,
+    /// The error of the most recent execution if it failed.
+    pub last_error: Option<String>,
+    /// The reason why no check result was submitted in the most recent
+    /// execution, e.g. an active maintenance window.
+    pub skipped: Option<&'static str>,
+}
+
+/// Durations measured while processing a mapping.
+/// The check result is rendered before it is submitted to the backends,
+/// thus `submission` refers to the previous execution of the mapping
+/// while `query` and `execution_start` refer to the current one when the
+/// plugin output is rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Timing {
+    pub query: Option<Duration>,
+    pub submission: Option<Duration>,
+    pub execution_start: Option<DateTime<Local>>,
 }
 
 /// This render context contains all information that may be accessed
@@ -73,11 +685,27 @@ impl<'a> PerformanceDataRenderContext<'a> {
             name: &mapping.name,
             host: &mapping.host,
             service: &mapping.service,
-            labels: &labels,
+            labels,
         }
     }
 }
 
+/// This render context contains the formatted parts of a single
+/// performance data entry and may be accessed in a handlebars template
+/// that replaces the default format of the entry. `min` and `max` are
+/// always empty.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PerformanceDataEntryRenderContext<'a> {
+    pub label: &'a str,
+    pub value: String,
+    pub uom: &'a str,
+    pub warning: String,
+    pub critical: String,
+    pub min: &'a str,
+    pub max: &'a str,
+    pub labels: &'a BTreeMap<String, String>,
+}
+
 /// This render context contains all information that may be accessed
 /// in a handlebars template to build the Icinga plugin output if the
 /// generic default output does not suffice.
@@ -92,6 +720,8 @@ pub(crate) struct PluginOutputRenderContext<'a> {
     pub name: &'a str,
     pub query: &'a str,
     pub thresholds: &'a ThresholdPair,
+    pub threshold_bounds: ThresholdBounds,
+    pub daemon: DaemonInfo,
     pub host: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub service: &'a Option<String>,
@@ -99,6 +729,21 @@ pub(crate) struct PluginOutputRenderContext<'a> {
     pub data: &'a [Data],
     pub exit_value: &'a u8,
     pub exit_status: &'a str,
+    pub query_duration: Option<f64>,
+    pub submission_duration: Option<f64>,
+    pub execution_start: Option<String>,
+    pub execution_end: String,
+    pub execution_duration: Option<f64>,
+    pub previous_exit_value: Option<u8>,
+    pub previous_exit_status: Option<String>,
+    pub state_changed: bool,
+    pub cycles_since_change: Option<u32>,
+    pub conditions: &'a [ConditionResult],
+    pub num_results: usize,
+    pub num_ok: usize,
+    pub num_warning: usize,
+    pub num_critical: usize,
+    pub num_unknown: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub is_ok: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -119,16 +764,55 @@ impl<'a> PluginOutputRenderContext<'a> {
         exit_status: &'a str,
     ) -> Self {
         let updates_service = mapping.service.is_some();
+        let execution_start = mapping.state.timing.execution_start;
+        let execution_end = Local::now();
+        let previous_exit_value = mapping.state.last_exit_value;
+        let state_changed = previous_exit_value.is_some_and(|prev| prev != *exit_value);
+        // Count the states of the time series before they are collapsed to
+        // UP/DOWN for host objects, see `Data::temp_exit_value`.
+        let count = |exit_value: u8| {
+            data.iter()
+                .filter(|item| item.temp_exit_value == exit_value)
+                .count()
+        };
         PluginOutputRenderContext {
             name: &mapping.name,
             query: &mapping.query,
             thresholds: &mapping.thresholds,
+            threshold_bounds: ThresholdBounds::from(&mapping.thresholds),
+            daemon: DaemonInfo {
+                hostname: crate::util::get_hostname(),
+                version: crate::VERSION,
+            },
             host: &mapping.host,
             service: &mapping.service,
             interval: mapping.interval.as_secs(),
             data,
             exit_value,
             exit_status,
+            query_duration: mapping.state.timing.query.map(as_millis),
+            submission_duration: mapping.state.timing.submission.map(as_millis),
+            execution_start: execution_start.map(as_rfc3339),
+            execution_end: as_rfc3339(execution_end),
+            execution_duration: execution_start
+                .and_then(|start| (execution_end - start).to_std().ok().map(as_millis)),
+            previous_exit_value,
+            previous_exit_status: previous_exit_value
+                .map(|prev| crate::icinga::real_exit_value_to_status(updates_service, &prev)),
+            state_changed,
+            conditions: &mapping.state.conditions,
+            cycles_since_change: previous_exit_value.map(|_| {
+                if state_changed {
+                    0
+                } else {
+                    mapping.state.unchanged_cycles + 1
+                }
+            }),
+            num_results: data.len(),
+            num_ok: count(0),
+            num_warning: count(1),
+            num_critical: count(2),
+            num_unknown: count(3),
             is_ok: if updates_service {
                 Some(*exit_value == 0)
             } else {
@@ -158,6 +842,19 @@ impl<'a> PluginOutputRenderContext<'a> {
     }
 }
 
+/// Points in time are exposed to templates as RFC 3339 strings in local
+/// time, e.g. "2022-03-01T12:03:45+01:00".
+#[inline]
+fn as_rfc3339(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Durations are exposed to templates as (fractional) milliseconds.
+#[inline]
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
 /// This is just a helper type that is created from the original
 /// data type returned as part of the PromQL query result.
 /// It only stores the label set and the value and discards
@@ -168,19 +865,34 @@ impl<'a> PluginOutputRenderContext<'a> {
 pub(crate) struct TimeSeries {
     pub labels: BTreeMap<String, String>,
     pub value: f64,
+    pub baseline: Option<f64>,
 }
 
 impl TimeSeries {
+    /// The deviation of the value from the baseline in percent. The
+    /// deviation is undefined when there is no baseline or the baseline
+    /// is zero while the value is not.
+    pub(crate) fn deviation(&self) -> Option<f64> {
+        match self.baseline {
+            Some(baseline) if baseline != 0.0 => {
+                Some((self.value - baseline) / baseline.abs() * 100.0)
+            }
+            Some(_) if self.value == 0.0 => Some(0.0),
+            _ => None,
+        }
+    }
+
     /// Create a `TimeSeries` from `prometheus_http_query::response::InstantVector`.
     pub(crate) fn from(instant_vector: &prometheus_http_query::response::InstantVector) -> Self {
         TimeSeries {
             labels: BTreeMap::from_iter(
                 instant_vector
                     .metric()
-                    .into_iter()
+                    .iter()
                     .map(|(k, v)| (k.to_owned(), v.to_owned())),
             ),
             value: instant_vector.sample().value(),
+            baseline: None,
         }
     }
 }
@@ -199,6 +911,10 @@ pub(crate) struct Data {
     pub labels: BTreeMap<String, String>,
     pub value: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deviation: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub is_ok: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub is_warning: Option<bool>,
@@ -216,6 +932,30 @@ pub(crate) struct Data {
 }
 
 impl Data {
+    /// Replace the exit values and status of the data point, e.g. when
+    /// a threshold breach is suppressed.
+    pub(crate) fn set_state(
+        &mut self,
+        updates_service: bool,
+        real_exit_value: u8,
+        temp_exit_value: u8,
+        exit_status: String,
+    ) {
+        let labels = std::mem::take(&mut self.labels);
+        let time_series = TimeSeries {
+            labels,
+            value: self.value,
+            baseline: self.baseline,
+        };
+        *self = Data::from(
+            updates_service,
+            time_series,
+            real_exit_value,
+            temp_exit_value,
+            exit_status,
+        );
+    }
+
     /// Create a `Data` point from a `TimeSeries` and additional data.
     /// The newly created data point simply extends the time
     /// series data with the "check data" that resulted from
@@ -228,9 +968,12 @@ impl Data {
         temp_exit_value: u8,
         exit_status: String,
     ) -> Self {
+        let deviation = time_series.deviation();
         Data {
             labels: time_series.labels,
             value: time_series.value,
+            baseline: time_series.baseline,
+            deviation,
             is_ok: if updates_service {
                 Some(real_exit_value == 0)
             } else {
@@ -263,18 +1006,130 @@ impl Data {
     }
 }
 
+/// The result of processing a mapping, i.e. the exit value, plugin output
+/// and performance data, that is ultimately submitted to each backend.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct CheckResult {
+    pub exit_value: u8,
+    pub plugin_output: String,
+    pub performance_data: Option<Vec<String>>,
+    pub execution_start: f64,
+    pub execution_end: f64,
+}
+
+/// The destinations check results are submitted to.
+#[derive(Debug, Clone)]
+pub(crate) enum BackendConfig {
+    Icinga,
+    CommandFile(PathBuf),
+    CheckResultSpool(PathBuf),
+    Sensu(SensuConfig),
+    Stdout,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SensuConfig {
+    pub url: Url,
+    pub handlers: Vec<String>,
+}
+
+/// The Icinga object that is updated with the health of vec2checkd itself.
+pub(crate) struct HeartbeatConfig {
+    pub host: String,
+    pub service: Option<String>,
+    pub interval: Duration,
+}
+
+/// The OpenTelemetry collector that the spans of vec2checkd are exported to.
+pub(crate) struct TracingConfig {
+    pub endpoint: Url,
+    pub service_name: String,
+}
+
+/// The HTTP listener that exposes telemetry about vec2checkd itself.
+pub(crate) struct MetricsConfig {
+    pub listen: SocketAddr,
+}
+
 pub(crate) struct PromConfig {
     pub host: Url,
     pub proxy: ProxyConfig,
+    pub tls: TlsConfig,
 }
 
+#[derive(Clone)]
 pub(crate) struct IcingaConfig {
     pub host: Url,
     pub ca_cert: Option<PathBuf>,
     pub authentication: IcingaAuth,
     pub proxy: ProxyConfig,
+    pub tls: TlsConfig,
+    pub connect_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub reload_retry: Option<ReloadRetryConfig>,
+    pub max_payload_size: Option<usize>,
+    pub startup_check: bool,
+    pub audit_log: Option<AuditLogConfig>,
+}
+
+/// The file that every payload sent to the Icinga API is recorded in.
+/// The file is rotated when it would exceed `max_size` bytes.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditLogConfig {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+/// Determines how submissions are retried while Icinga is reloading
+/// and answers with HTTP 503. Retries are sent every `interval` until
+/// the time since the first attempt would exceed `threshold`.
+#[derive(Clone)]
+pub(crate) struct ReloadRetryConfig {
+    pub interval: Duration,
+    pub threshold: Duration,
+}
+
+impl Default for ReloadRetryConfig {
+    fn default() -> Self {
+        ReloadRetryConfig {
+            interval: Duration::from_secs(2),
+            threshold: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RateLimitConfig {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+impl IcingaConfig {
+    /// Return the paths of all files that contain TLS material used
+    /// by the Icinga API client, i.e. the CA certificate and the client
+    /// certificate and key (or PKCS#12 bundle).
+    pub(crate) fn tls_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        if let Some(ca_cert) = &self.ca_cert {
+            files.push(ca_cert.clone());
+        }
+        match &self.authentication {
+            IcingaAuth::Basic(_) => {}
+            IcingaAuth::X509(IcingaX509Auth::Pem(pem)) => {
+                files.push(pem.client_cert.clone());
+                files.push(pem.client_key.clone());
+            }
+            IcingaAuth::X509(IcingaX509Auth::Pkcs12(p12)) => {
+                files.push(p12.client_p12.clone());
+            }
+        }
+        files
+    }
 }
 
+#[derive(Clone)]
 pub(crate) enum IcingaAuth {
     Basic(IcingaBasicAuth),
     X509(IcingaX509Auth),
@@ -286,12 +1141,47 @@ pub(crate) struct IcingaBasicAuth {
     pub password: String,
 }
 
-pub(crate) struct IcingaX509Auth {
+/// Mappings are logged in debug mode, so make sure the password
+/// does not end up in the logs.
+impl std::fmt::Debug for IcingaBasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IcingaBasicAuth")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum IcingaX509Auth {
+    Pem(IcingaPemIdentity),
+    Pkcs12(IcingaPkcs12Identity),
+}
+
+#[derive(Clone)]
+pub(crate) struct IcingaPemIdentity {
     pub client_cert: PathBuf,
     pub client_key: PathBuf,
+    pub passphrase: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct IcingaPkcs12Identity {
+    pub client_p12: PathBuf,
+    pub passphrase: String,
+}
+
+/// Restricts the TLS protocol versions a client may negotiate.
+/// Note that rustls only implements TLS 1.2 and 1.3 and a fixed set
+/// of secure cipher suites.
+#[derive(Clone, Default)]
+pub(crate) struct TlsConfig {
+    pub min_version: Option<reqwest::tls::Version>,
+    pub max_version: Option<reqwest::tls::Version>,
+    pub use_native_roots: bool,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub(crate) struct ProxyConfig {
     pub ignore: bool,
     pub host: Option<reqwest::Proxy>,
@@ -301,7 +1191,39 @@ pub(crate) struct ProxyConfig {
 pub(crate) struct PerformanceData {
     pub enabled: bool,
     pub label: Option<String>,
+    /// Replace the mapping name and the slash that precede the checksum in
+    /// default labels.
+    pub label_prefix: Option<String>,
+    /// A static unit of measurement or a handlebars template.
     pub uom: Option<String>,
+    /// Detect the unit of measurement from the metric name when no `uom`
+    /// is configured.
+    pub auto_uom: bool,
+    pub warning: PerfdataThreshold,
+    pub critical: PerfdataThreshold,
+    /// Write thresholds as plain numbers instead of Nagios ranges.
+    pub numeric_thresholds: bool,
+    /// Round values to this number of decimal places.
+    pub precision: Option<usize>,
+    /// Keep trailing zeros of rounded values.
+    pub fixed: bool,
+    /// Fail instead of replacing characters in labels that are not
+    /// permitted in performance data.
+    pub strict_labels: bool,
+    pub sort: PerfdataOrder,
+    /// Limit the number of entries to this count.
+    pub max_items: Option<usize>,
+    /// The order in which entries are selected when there are more than
+    /// `max_items`, either `Label` or `Value`.
+    pub select: PerfdataOrder,
+    pub non_finite: PerfdataNonFinite,
+    /// Append entries with the number of results per state and the query
+    /// duration.
+    pub summary: bool,
+    /// A handlebars template that replaces the default format of an entry.
+    pub format: Option<String>,
+    /// Emit a single entry with this value when the query result is empty.
+    pub absent: Option<f64>,
 }
 
 impl Default for PerformanceData {
@@ -309,7 +1231,71 @@ impl Default for PerformanceData {
         PerformanceData {
             enabled: true,
             label: None,
+            label_prefix: None,
             uom: None,
+            auto_uom: false,
+            warning: PerfdataThreshold::default(),
+            critical: PerfdataThreshold::default(),
+            numeric_thresholds: false,
+            precision: None,
+            fixed: false,
+            strict_labels: false,
+            sort: PerfdataOrder::default(),
+            max_items: None,
+            select: PerfdataOrder::Value,
+            non_finite: PerfdataNonFinite::default(),
+            summary: false,
+            format: None,
+            absent: None,
+        }
+    }
+}
+
+/// Define how NaN and ±Inf values are written into the performance data.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum PerfdataNonFinite {
+    /// Leave the entry out.
+    #[default]
+    Drop,
+    /// Write this value instead.
+    Substitute(f64),
+}
+
+/// The order of the entries in the performance data.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum PerfdataOrder {
+    /// The order of the time series in the query result.
+    #[default]
+    Query,
+    /// Ascending by performance data label.
+    Label,
+    /// Descending by value, ties are ordered by label.
+    Value,
+}
+
+/// The threshold that is written into the performance data, which may
+/// differ from the threshold that is used to determine the state.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum PerfdataThreshold {
+    /// Use the threshold that applies to the time series.
+    #[default]
+    Alerting,
+    /// Leave the threshold out of the performance data.
+    Omit,
+    Range(NagiosRange),
+}
+
+impl PerfdataThreshold {
+    /// Return the threshold to write into the performance data given the
+    /// alerting threshold of a time series.
+    pub(crate) fn resolve<'a>(
+        &'a self,
+        alerting: Option<&'a NagiosRange>,
+    ) -> Option<&'a NagiosRange> {
+        match self {
+            PerfdataThreshold::Alerting => alerting,
+            PerfdataThreshold::Omit => None,
+            PerfdataThreshold::Range(range) => Some(range),
         }
     }
 }
@@ -326,9 +1312,10 @@ mod tests {
         let time_series = TimeSeries {
             labels: labels.clone(),
             value: 5.0,
+            baseline: None,
         };
         let result = Data {
-            labels: labels,
+            labels,
             value: 5.0,
             is_ok: None,
             is_warning: None,
@@ -338,6 +1325,8 @@ mod tests {
             real_exit_value: 0,
             temp_exit_value: 0,
             exit_status: "UP".to_string(),
+            baseline: None,
+            deviation: None,
         };
         assert_eq!(
             Data::from(false, time_series, 0, 0, "UP".to_string()),
@@ -352,9 +1341,10 @@ mod tests {
         let time_series = TimeSeries {
             labels: labels.clone(),
             value: 5.0,
+            baseline: None,
         };
         let result = Data {
-            labels: labels,
+            labels,
             value: 5.0,
             is_ok: Some(true),
             is_warning: Some(false),
@@ -364,10 +1354,36 @@ mod tests {
             real_exit_value: 0,
             temp_exit_value: 0,
             exit_status: "OK".to_string(),
+            baseline: None,
+            deviation: None,
         };
         assert_eq!(
             Data::from(true, time_series, 0, 0, "OK".to_string()),
             result
         );
     }
+
+    #[test]
+    fn test_threshold_bounds() {
+        let thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("@10:20").unwrap()),
+            critical: Some(NagiosRange::from("~:90").unwrap()),
+            ..ThresholdPair::default()
+        };
+        let bounds = serde_json::to_value(ThresholdBounds::from(&thresholds)).unwrap();
+        assert_eq!(
+            bounds,
+            serde_json::json!({
+                "warning": {"start": 10.0, "end": 20.0, "inside": true},
+                "critical": {"start": null, "end": 90.0, "inside": false}
+            })
+        );
+
+        let bounds =
+            serde_json::to_value(ThresholdBounds::from(&ThresholdPair::default())).unwrap();
+        assert_eq!(
+            bounds,
+            serde_json::json!({"warning": null, "critical": null})
+        );
+    }
 }
@@ -1,11 +1,22 @@
+use crate::backend::Backend;
+use crate::error::SubmissionError;
 use crate::icinga;
-use crate::types::{Data, Mapping, TimeSeries};
+use crate::types::{
+    Aggregation, CheckResult, Condition, ConditionResult, Conditions, Data, Deduplication,
+    Derivation, GraceAction, MaintenanceAction, Mapping, MappingState, NonFinitePolicy, TimeSeries,
+};
 use anyhow::anyhow;
-use anyhow::Context;
-use log::debug;
-use std::time::{Duration, SystemTime};
+use anyhow::{bail, Context};
+use chrono::{Local, NaiveDateTime};
+use nagios_range::NagiosRange;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, trace_span, warn, Instrument};
 
-type TaskResult = Result<Result<(), anyhow::Error>, tokio::task::JoinError>;
+type TaskResult = Result<Result<MappingState, anyhow::Error>, tokio::task::JoinError>;
 
 #[inline]
 pub(crate) fn compute_delta(mapping: &Mapping) -> Duration {
@@ -14,14 +25,92 @@ pub(crate) fn compute_delta(mapping: &Mapping) -> Duration {
         .saturating_sub(mapping.last_apply.elapsed())
 }
 
+/// Return the current UNIX timestamp with millisecond precision.
 #[inline]
-pub(crate) fn get_unix_timestamp() -> Result<u64, anyhow::Error> {
+pub(crate) fn get_unix_timestamp() -> Result<f64, anyhow::Error> {
     let timestamp = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .with_context(|| "failed to retrieve current UNIX timestamp")?
-        .as_secs();
+        .as_millis();
 
-    Ok(timestamp)
+    Ok(timestamp as f64 / 1000.0)
+}
+
+/// Return the host name of the machine vec2checkd runs on (empty if it
+/// cannot be determined). It is read only once as it does not change.
+pub(crate) fn get_hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|hostname| hostname.trim().to_string())
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_default()
+    })
+}
+
+/// Return the time vec2checkd was started, i.e. when this function was
+/// first called, which happens right at the start.
+pub(crate) fn get_start_time() -> Instant {
+    static START_TIME: OnceLock<Instant> = OnceLock::new();
+    *START_TIME.get_or_init(Instant::now)
+}
+
+/// Load the CA certificates from the trust store of the operating system,
+/// so that e.g. corporate CA certificates are trusted by the API clients.
+pub(crate) fn load_native_root_certificates() -> Result<Vec<reqwest::Certificate>, anyhow::Error> {
+    let certs = rustls_native_certs::load_native_certs()
+        .with_context(|| "failed to load CA certificates from the system trust store")?;
+
+    debug!(
+        "Loaded {} CA certificate(s) from the system trust store",
+        certs.len()
+    );
+
+    // Skip certificates that cannot be parsed instead of failing altogether
+    // as system trust stores tend to contain a few odd certificates.
+    Ok(certs
+        .into_iter()
+        .filter_map(|cert| reqwest::Certificate::from_der(&cert.0).ok())
+        .collect())
+}
+
+/// Keeps track of the modification times of a set of files in order to
+/// detect when any of them was changed, e.g. when certificates are rotated.
+pub(crate) struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl FileWatcher {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modification_time(&path);
+                (path, modified)
+            })
+            .collect();
+        FileWatcher { files }
+    }
+
+    /// Return true if at least one file was modified, created or removed
+    /// since the last call.
+    pub(crate) fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modification_time(path);
+            if modified != *last_modified {
+                debug!("{:?} was modified since the last check", path);
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[inline]
+fn modification_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
 /// Convert each time series to a set of data points that contains the
@@ -33,9 +122,72 @@ fn process_time_series(mapping: &Mapping, time_series: Vec<TimeSeries>) -> Vec<D
     time_series
         .into_iter()
         .map(|ts| {
-            let value = ts.value;
-            let (real_exit_value, temp_exit_value) = icinga::check_thresholds(mapping, value);
             let updates_service = mapping.service.is_some();
+
+            // A state taken from a label overrides any threshold check.
+            if let Some(exit_value) = mapping
+                .state_from_label
+                .as_ref()
+                .and_then(|s| s.exit_value(&ts.labels))
+            {
+                let temp_exit_value = mapping.state_map[exit_value as usize];
+                let real_exit_value = icinga::to_real_exit_value(updates_service, temp_exit_value);
+                let exit_status = icinga::exit_value_to_status(updates_service, &temp_exit_value);
+                return Data::from(
+                    updates_service,
+                    ts,
+                    real_exit_value,
+                    temp_exit_value,
+                    exit_status,
+                );
+            }
+
+            // Check the deviation from the baseline instead of the value
+            // itself when a baseline query is configured. Time series without
+            // a (non-zero) baseline cannot deviate and are considered OK.
+            let value = if mapping.baseline_query.is_some() {
+                match ts.deviation() {
+                    Some(deviation) => deviation,
+                    None => {
+                        debug!(
+                            "'{}': no baseline for time series {:?}, skip threshold check",
+                            mapping.name, ts.labels
+                        );
+                        return Data::from(
+                            mapping.service.is_some(),
+                            ts,
+                            0,
+                            0,
+                            icinga::exit_value_to_status(mapping.service.is_some(), &0),
+                        );
+                    }
+                }
+            } else {
+                ts.value
+            };
+            if !value.is_finite() && mapping.non_finite == NonFinitePolicy::Unknown {
+                let real_exit_value = if updates_service { 3 } else { 1 };
+                let exit_status = icinga::exit_value_to_status(updates_service, &3);
+                return Data::from(updates_service, ts, real_exit_value, 3, exit_status);
+            }
+            let temp_exit_value = match (&mapping.label_states, &mapping.expect) {
+                (Some(label_states), _) => label_states.exit_value(&ts.labels, ts.value),
+                (None, Some(expect)) if expect.matches(value) => 0,
+                (None, Some(expect)) => expect.exit_value,
+                // Time series that templated thresholds cannot be rendered
+                // for are UNKNOWN rather than checked against incomplete
+                // thresholds.
+                (None, None) => match mapping.try_thresholds_for(&ts.labels) {
+                    Ok(thresholds) => icinga::check_thresholds(mapping, &thresholds, value).1,
+                    Err(e) => {
+                        warn!("'{}': time series {:?}: {:#}", mapping.name, ts.labels, e);
+                        3
+                    }
+                },
+            };
+            // Remap the state as configured, e.g. WARNING to CRITICAL.
+            let temp_exit_value = mapping.state_map[temp_exit_value as usize];
+            let real_exit_value = icinga::to_real_exit_value(updates_service, temp_exit_value);
             let exit_status = icinga::exit_value_to_status(updates_service, &temp_exit_value);
             Data::from(
                 updates_service,
@@ -48,20 +200,830 @@ fn process_time_series(mapping: &Mapping, time_series: Vec<TimeSeries>) -> Vec<D
         .collect::<Vec<Data>>()
 }
 
+/// Count the consecutive executions in which each time series and the
+/// mapping as a whole breached a warning or critical threshold and reset the
+/// state of those data points to OK (UP for hosts) that have not breached
+/// the thresholds in at least `breach_cycles` consecutive executions yet,
+/// unless the mapping as a whole has.
+fn apply_hysteresis(mapping: &mut Mapping, data: &mut [Data]) {
+    let updates_service = mapping.service.is_some();
+    let mut breaches = HashMap::new();
+
+    // Otherwise a breach that moves to another time series in each execution
+    // would never be reported.
+    if data
+        .iter()
+        .any(|item| item.temp_exit_value == 1 || item.temp_exit_value == 2)
+    {
+        mapping.state.overall_breaches += 1;
+    } else {
+        mapping.state.overall_breaches = 0;
+    }
+
+    for item in data.iter_mut() {
+        // UNKNOWN states are not the result of a threshold breach.
+        if item.temp_exit_value == 0 || item.temp_exit_value == 3 {
+            continue;
+        }
+
+        let count = mapping
+            .state
+            .breaches
+            .get(&item.labels)
+            .copied()
+            .unwrap_or(0)
+            + 1;
+        breaches.insert(item.labels.clone(), count);
+
+        if count < mapping.breach_cycles && mapping.state.overall_breaches < mapping.breach_cycles {
+            debug!(
+                "'{}': suppress threshold breach of time series {:?} ({} of {} cycles)",
+                mapping.name, item.labels, count, mapping.breach_cycles
+            );
+            item.set_state(
+                updates_service,
+                0,
+                0,
+                icinga::exit_value_to_status(updates_service, &0),
+            );
+        }
+    }
+
+    // Time series that no longer breach a threshold or vanished are
+    // forgotten, so the next breach starts counting from scratch.
+    mapping.state.breaches = breaches;
+}
+
+/// Replace the value of each time series by its change since the previous
+/// execution and remember the current values for the next one. Time series
+/// without a previous sample are dropped.
+fn derive_time_series(
+    mapping: &mut Mapping,
+    derivation: Derivation,
+    time_series: Vec<TimeSeries>,
+    now: f64,
+) -> Vec<TimeSeries> {
+    let samples: HashMap<BTreeMap<String, String>, (f64, f64)> = time_series
+        .iter()
+        .map(|ts| (ts.labels.clone(), (ts.value, now)))
+        .collect();
+    let previous = std::mem::replace(&mut mapping.state.samples, samples);
+
+    time_series
+        .into_iter()
+        .filter_map(|mut ts| {
+            let (prev_value, prev_time) = previous.get(&ts.labels).copied()?;
+            ts.value = match derivation {
+                Derivation::Delta => ts.value - prev_value,
+                Derivation::Rate => {
+                    let elapsed = now - prev_time;
+                    if elapsed <= 0.0 {
+                        return None;
+                    }
+                    // A counter that decreased has been reset in between.
+                    let increase = if ts.value < prev_value {
+                        ts.value
+                    } else {
+                        ts.value - prev_value
+                    };
+                    increase / elapsed
+                }
+            };
+            Some(ts)
+        })
+        .collect()
+}
+
+/// Clamp the value to the configured range and round it to the configured
+/// precision. Non-finite values are left untouched.
+fn adjust_value(mapping: &Mapping, value: f64) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    let mut value = value;
+    if let Some(min) = mapping.clamp_min {
+        value = value.max(min);
+    }
+    if let Some(max) = mapping.clamp_max {
+        value = value.min(max);
+    }
+    if let Some(precision) = mapping.precision {
+        let factor = 10f64.powi(precision as i32);
+        value = (value * factor).round() / factor;
+    }
+    value
+}
+
+/// Drop time series whose value is identical to the value in the previous
+/// execution and remember the current values for the next one. Time series
+/// without a previous value are kept.
+fn drop_unchanged_time_series(
+    mapping: &mut Mapping,
+    time_series: Vec<TimeSeries>,
+) -> Vec<TimeSeries> {
+    let values: HashMap<BTreeMap<String, String>, f64> = time_series
+        .iter()
+        .map(|ts| (ts.labels.clone(), ts.value))
+        .collect();
+    let previous = std::mem::replace(&mut mapping.state.previous_values, values);
+
+    time_series
+        .into_iter()
+        .filter(|ts| previous.get(&ts.labels) != Some(&ts.value))
+        .collect()
+}
+
+/// Reduce time series with identical label sets to a single one, keeping
+/// the order in which the label sets first appear in the result.
+fn deduplicate_time_series(
+    deduplication: Deduplication,
+    time_series: Vec<TimeSeries>,
+) -> Vec<TimeSeries> {
+    let mut result: Vec<TimeSeries> = Vec::with_capacity(time_series.len());
+    let mut positions: HashMap<BTreeMap<String, String>, usize> = HashMap::new();
+
+    for ts in time_series {
+        match positions.get(&ts.labels) {
+            Some(&i) => {
+                let replace = match deduplication {
+                    Deduplication::Max => ts.value > result[i].value,
+                    Deduplication::Min => ts.value < result[i].value,
+                    Deduplication::Last => true,
+                };
+                if replace {
+                    result[i] = ts;
+                }
+            }
+            None => {
+                positions.insert(ts.labels.clone(), result.len());
+                result.push(ts);
+            }
+        }
+    }
+
+    result
+}
+
+/// Execute the query of each condition of a mapping and determine its state
+/// from the worst state of the time series in the result. A condition with
+/// an empty result is OK.
+async fn evaluate_conditions(
+    prom_client: &prometheus_http_query::Client,
+    mapping: &Mapping,
+    conditions: &Conditions,
+) -> Result<Vec<ConditionResult>, anyhow::Error> {
+    let updates_service = mapping.service.is_some();
+    let mut results = vec![];
+
+    for condition in conditions.items.iter() {
+        debug!(
+            "'{}': execute PromQL query '{}' of condition '{}'",
+            mapping.name, condition.query, condition.name
+        );
+
+        let query_result = prom_client
+            .query(&condition.query, None, None)
+            .instrument(trace_span!(
+                "query",
+                query = %condition.query,
+                condition = %condition.name
+            ))
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to execute PromQL query of condition '{}'",
+                    condition.name
+                )
+            })?;
+
+        let values: Vec<f64> = query_result
+            .as_instant()
+            .ok_or(anyhow!(
+                "failed to parse PromQL query result of condition '{}' as instant vector",
+                condition.name
+            ))?
+            .iter()
+            .map(|v| TimeSeries::from(v).value)
+            .collect();
+
+        results.push(evaluate_condition(
+            mapping,
+            condition,
+            values,
+            updates_service,
+        ));
+    }
+
+    Ok(results)
+}
+
+fn evaluate_condition(
+    mapping: &Mapping,
+    condition: &Condition,
+    values: Vec<f64>,
+    updates_service: bool,
+) -> ConditionResult {
+    let exit_value = values
+        .iter()
+        .map(|value| {
+            if value.is_finite() {
+                icinga::check_thresholds(mapping, &condition.thresholds, *value).1
+            } else {
+                3
+            }
+        })
+        .max()
+        .unwrap_or(0);
+
+    ConditionResult {
+        name: condition.name.clone(),
+        values,
+        exit_value,
+        exit_status: icinga::exit_value_to_status(updates_service, &exit_value),
+    }
+}
+
+/// Count the consecutive executions with an overall WARNING state and
+/// escalate the state to CRITICAL once it persisted long enough. Host
+/// objects have no WARNING state.
+fn apply_escalation(mapping: &mut Mapping, plugin_output: String, exit_value: u8) -> (String, u8) {
+    let after = match mapping.escalate_after {
+        Some(after) if mapping.service.is_some() => after,
+        _ => return (plugin_output, exit_value),
+    };
+
+    if exit_value != 1 {
+        mapping.state.warning_cycles = 0;
+        return (plugin_output, exit_value);
+    }
+
+    mapping.state.warning_cycles += 1;
+
+    if mapping.state.warning_cycles < after {
+        return (plugin_output, exit_value);
+    }
+
+    debug!(
+        "'{}': escalate WARNING to CRITICAL after {} cycles",
+        mapping.name, mapping.state.warning_cycles
+    );
+
+    // Replace the leading status, if any, and annotate the first line in
+    // case of a multi-line output.
+    let annotation = format!(
+        " (escalated to CRITICAL after {} cycles in WARNING)",
+        mapping.state.warning_cycles
+    );
+    let mut plugin_output = match plugin_output.strip_prefix("[WARNING]") {
+        Some(rest) => format!("[CRITICAL]{}", rest),
+        None => plugin_output,
+    };
+    let pos = plugin_output.find('\n').unwrap_or(plugin_output.len());
+    plugin_output.insert_str(pos, &annotation);
+    (plugin_output, 2)
+}
+
+/// Replace a non-OK result by UNKNOWN during the grace period after startup,
+/// or return None when the result must not be submitted at all.
+fn apply_grace_period(
+    mapping: &Mapping,
+    plugin_output: String,
+    exit_value: u8,
+    now: Instant,
+) -> Option<(String, u8)> {
+    let grace = match mapping.grace_period {
+        Some(grace) if now < grace.until && exit_value != 0 => grace,
+        _ => return Some((plugin_output, exit_value)),
+    };
+
+    let updates_service = mapping.service.is_some();
+
+    // Host objects do not have an UNKNOWN state.
+    if grace.action == GraceAction::Skip || !updates_service {
+        return None;
+    }
+
+    Some((
+        format!(
+            "[UNKNOWN] Startup grace period, actual state {}; {}",
+            icinga::exit_value_to_status(updates_service, &exit_value),
+            plugin_output
+        ),
+        3,
+    ))
+}
+
+/// Remember the overall exit value that is reported for the current
+/// execution and how many executions ago it last changed.
+fn record_exit_value(state: &mut MappingState, exit_value: u8) {
+    if state.last_exit_value == Some(exit_value) {
+        state.unchanged_cycles += 1;
+    } else {
+        state.unchanged_cycles = 0;
+    }
+    state.last_exit_value = Some(exit_value);
+}
+
+/// Return the action of the first maintenance window of the mapping that
+/// covers the given point in time, if any.
+fn active_maintenance_window(mapping: &Mapping, now: NaiveDateTime) -> Option<MaintenanceAction> {
+    mapping
+        .maintenance
+        .iter()
+        .find(|window| window.contains(now))
+        .map(|window| window.action)
+}
+
+/// Keep track of the recent overall exit values of a mapping and hold the last
+/// stable exit value as long as the state changes more often than configured
+/// within the window of recent executions.
+fn apply_flap_detection(
+    mapping: &mut Mapping,
+    plugin_output: String,
+    exit_value: u8,
+) -> (String, u8) {
+    let flapping = match mapping.flapping {
+        Some(f) => f,
+        None => return (plugin_output, exit_value),
+    };
+
+    let state = &mut mapping.state;
+    state.history.push_back(exit_value);
+    while state.history.len() > flapping.window {
+        state.history.pop_front();
+    }
+
+    let changes = state
+        .history
+        .iter()
+        .zip(state.history.iter().skip(1))
+        .filter(|(a, b)| a != b)
+        .count();
+
+    match state.stable_exit_value {
+        Some(stable) if changes > flapping.max_changes && stable != exit_value => {
+            debug!(
+                "'{}': state changed {} times in the last {} executions, hold exit value {}",
+                mapping.name,
+                changes,
+                state.history.len(),
+                stable
+            );
+            let updates_service = mapping.service.is_some();
+            let status = if updates_service {
+                icinga::exit_value_to_status(true, &stable)
+            } else if stable == 0 {
+                "UP".to_string()
+            } else {
+                "DOWN".to_string()
+            };
+            // Annotate the first line in case of a multi-line output.
+            let annotation = format!(
+                " (flapping suppressed, holding last stable state {})",
+                status
+            );
+            let mut plugin_output = plugin_output;
+            let pos = plugin_output.find('\n').unwrap_or(plugin_output.len());
+            plugin_output.insert_str(pos, &annotation);
+            (plugin_output, stable)
+        }
+        _ => {
+            if changes <= flapping.max_changes {
+                state.stable_exit_value = Some(exit_value);
+            }
+            (plugin_output, exit_value)
+        }
+    }
+}
+
+/// Render every template of a mapping (plugin output, performance data and
+/// threshold templates) against a synthetic context, so that errors like
+/// misspelled variables or unknown partials are reported at startup instead
+/// of when the mapping is executed for the first time.
+/// As the label set of the actual time series is unknown, the synthetic time
+/// series has every label that the templates refer to (`labels.<name>`).
+pub(crate) fn validate_templates(mapping: &Mapping) -> Result<(), anyhow::Error> {
+    let perfdata = &mapping.performance_data;
+    let templates: Vec<&str> = [
+        mapping.plugin_output.as_deref(),
+        mapping.no_data.plugin_output.as_deref(),
+        perfdata.label.as_deref(),
+        perfdata.uom.as_deref(),
+        perfdata.format.as_deref(),
+        mapping.threshold_templates.warning.as_deref(),
+        mapping.threshold_templates.critical.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(mapping.partials.values().map(|p| p.as_str()))
+    .collect();
+
+    // Partials that do not exist are silently rendered as empty strings.
+    let partial_regex = Regex::new(r"\{\{~?#?>\s*([^\s(}~]+)").unwrap();
+    for template in templates.iter() {
+        for captures in partial_regex.captures_iter(template) {
+            let name = captures[1].trim_matches(|c| c == '"' || c == '\'');
+            if !mapping.partials.contains_key(name) {
+                bail!("template refers to partial '{}' that does not exist", name);
+            }
+        }
+    }
+
+    let label_regex = Regex::new(r"labels\.([a-zA-Z_][a-zA-Z0-9_]*)").unwrap();
+    let mut labels: BTreeMap<String, String> = templates
+        .iter()
+        .flat_map(|template| label_regex.captures_iter(template))
+        .map(|captures| (captures[1].to_string(), String::from("1")))
+        .collect();
+    for label in mapping.group_by.iter() {
+        labels.insert(label.clone(), String::from("1"));
+    }
+
+    let thresholds = mapping
+        .try_thresholds_for(&labels)
+        .with_context(|| "failed to render threshold templates")?;
+
+    let updates_service = mapping.service.is_some();
+    let time_series = TimeSeries {
+        labels,
+        value: 1.0,
+        baseline: mapping.baseline_query.as_ref().map(|_| 1.0),
+    };
+    let (real_exit_value, temp_exit_value) =
+        icinga::check_thresholds(mapping, &thresholds, time_series.value);
+    let exit_status = icinga::exit_value_to_status(updates_service, &temp_exit_value);
+    let data = vec![Data::from(
+        updates_service,
+        time_series,
+        real_exit_value,
+        temp_exit_value,
+        exit_status.clone(),
+    )];
+
+    if perfdata.enabled {
+        icinga::format_performance_data(mapping, &data)
+            .with_context(|| "failed to render performance data")?;
+    }
+
+    if let Some(ref template) = mapping.plugin_output {
+        icinga::plugin_output::format_from_template(
+            template,
+            mapping,
+            data,
+            real_exit_value,
+            exit_status,
+        )?;
+    }
+
+    if let Some(ref template) = mapping.no_data.plugin_output {
+        let exit_status =
+            icinga::exit_value_to_status(updates_service, &mapping.no_data.exit_value);
+        icinga::plugin_output::format_from_template(
+            template,
+            mapping,
+            vec![],
+            mapping.no_data.exit_value,
+            exit_status,
+        )
+        .with_context(|| "failed to render no_data.plugin_output")?;
+    }
+
+    Ok(())
+}
+
+/// Build the plugin output and exit value from the `no_data` settings of the
+/// mapping when the PromQL query result is empty.
+fn process_empty_result(
+    mapping: &Mapping,
+) -> Result<(String, u8, Option<Vec<String>>), anyhow::Error> {
+    let updates_service = mapping.service.is_some();
+
+    // The state is configured in terms of service states, so collapse it
+    // to UP (0) and DOWN (1) for host objects just like a threshold check.
+    let temp_exit_value = mapping.no_data.exit_value;
+    let real_exit_value = icinga::to_real_exit_value(updates_service, temp_exit_value);
+
+    let exit_status = icinga::exit_value_to_status(updates_service, &temp_exit_value);
+
+    let plugin_output = match mapping.no_data.plugin_output {
+        Some(ref template) => icinga::plugin_output::format_from_template(
+            template,
+            mapping,
+            vec![],
+            real_exit_value,
+            exit_status,
+        )?,
+        None => icinga::plugin_output::format_default_without_data(&mapping.name, &exit_status),
+    };
+
+    // Keep graphs continuous by emitting a placeholder if configured.
+    let performance_data = match mapping.performance_data.absent {
+        Some(value) if mapping.performance_data.enabled => {
+            Some(vec![icinga::format_absent_performance_data(mapping, value)])
+        }
+        _ => None,
+    };
+
+    Ok((plugin_output, real_exit_value, performance_data))
+}
+
+/// Return a description of the violation when the number of time series
+/// returned by the PromQL query is outside of the expected bounds.
+fn check_result_count(mapping: &Mapping, count: usize) -> Option<String> {
+    if let Some(min) = mapping.result_count.min {
+        if count < min {
+            return Some(format!(
+                "expected at least {} result(s), got {}",
+                min, count
+            ));
+        }
+    }
+
+    if let Some(max) = mapping.result_count.max {
+        if count > max {
+            return Some(format!("expected at most {} result(s), got {}", max, count));
+        }
+    }
+
+    None
+}
+
+/// Reduce a set of time series to a single one. Just like a PromQL aggregation
+/// the metric name is dropped, but other labels that all time series have in
+/// common are kept. The baselines are aggregated as well if every time series
+/// has one.
+fn aggregate_time_series(aggregation: Aggregation, time_series: Vec<TimeSeries>) -> TimeSeries {
+    let values: Vec<f64> = time_series.iter().map(|ts| ts.value).collect();
+    let baselines: Option<Vec<f64>> = time_series.iter().map(|ts| ts.baseline).collect();
+
+    let mut series = time_series.into_iter();
+    let mut labels = series.next().map(|ts| ts.labels).unwrap_or_default();
+    labels.remove("__name__");
+    for ts in series {
+        labels.retain(|k, v| ts.labels.get(k) == Some(v));
+    }
+
+    TimeSeries {
+        labels,
+        value: aggregation.apply(&values),
+        baseline: baselines.map(|b| aggregation.apply(&b)),
+    }
+}
+
+/// Split a set of time series into groups with equal values of the given
+/// labels (a missing label counts as an empty value), keeping the order in
+/// which the groups first appear. Without labels all time series form a
+/// single group.
+fn group_time_series(labels: &[String], time_series: Vec<TimeSeries>) -> Vec<Vec<TimeSeries>> {
+    let mut groups: Vec<Vec<TimeSeries>> = vec![];
+    let mut positions: HashMap<Vec<String>, usize> = HashMap::new();
+
+    for ts in time_series {
+        let key: Vec<String> = labels
+            .iter()
+            .map(|l| ts.labels.get(l).cloned().unwrap_or_default())
+            .collect();
+        match positions.get(&key) {
+            Some(&i) => groups[i].push(ts),
+            None => {
+                positions.insert(key, groups.len());
+                groups.push(vec![ts]);
+            }
+        }
+    }
+
+    groups
+}
+
+/// Reduce a set of time series to a single one whose value is the number of
+/// time series with a value within the given range, e.g. to check how many
+/// nodes are overloaded.
+fn count_breaching_time_series(range: &NagiosRange, time_series: Vec<TimeSeries>) -> TimeSeries {
+    let time_series = time_series
+        .into_iter()
+        .map(|ts| TimeSeries {
+            value: if range.check(ts.value) { 1.0 } else { 0.0 },
+            baseline: None,
+            ..ts
+        })
+        .collect();
+    aggregate_time_series(Aggregation::Sum, time_series)
+}
+
+/// Attach the value of the matching baseline time series to each time series.
+/// Time series are matched by their label sets while the metric name is
+/// ignored, as e.g. a baseline query using `offset` returns the same labels.
+fn attach_baseline(time_series: &mut [TimeSeries], baseline: Vec<TimeSeries>) {
+    let mut baseline: HashMap<BTreeMap<String, String>, f64> = baseline
+        .into_iter()
+        .map(|mut ts| {
+            ts.labels.remove("__name__");
+            (ts.labels, ts.value)
+        })
+        .collect();
+
+    for ts in time_series.iter_mut() {
+        let mut labels = ts.labels.clone();
+        labels.remove("__name__");
+        ts.baseline = baseline.remove(&labels);
+    }
+}
+
 /// Convert a PromQL query result (array of instant vectors/time series) to the three major parts
 /// that make up an Icinga check result: the plugin output, exit value and optionally
 /// an array of performance data.
 fn process_query_result(
-    mapping: &Mapping,
+    mapping: &mut Mapping,
     time_series: Vec<TimeSeries>,
 ) -> Result<(String, u8, Option<Vec<String>>), anyhow::Error> {
+    // Drop noisy time series before anything else.
+    let total = time_series.len();
+    let time_series: Vec<TimeSeries> = time_series
+        .into_iter()
+        .filter(|ts| mapping.selects(&ts.labels))
+        .collect();
+
+    if time_series.is_empty() {
+        debug!(
+            "'{}': all {} time series in the PromQL query result were filtered out",
+            mapping.name, total
+        );
+        return process_empty_result(mapping);
+    }
+
+    let time_series = match mapping.deduplicate {
+        Some(deduplication) => deduplicate_time_series(deduplication, time_series),
+        None => time_series,
+    };
+
+    let result_count = time_series.len();
+
+    // Unchanged time series are determined by their raw values. They are
+    // only dropped after the derivation, which must see every time series
+    // to keep its samples up to date.
+    let changed: Option<HashSet<BTreeMap<String, String>>> = if mapping.ignore_unchanged {
+        Some(
+            drop_unchanged_time_series(mapping, time_series.clone())
+                .into_iter()
+                .map(|ts| ts.labels)
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    let time_series = match mapping.derive {
+        Some(derivation) => {
+            derive_time_series(mapping, derivation, time_series, get_unix_timestamp()?)
+        }
+        None => time_series,
+    };
+
+    let time_series = match changed {
+        Some(changed) => {
+            if changed.is_empty() {
+                debug!(
+                    "'{}': no time series changed since the previous execution, keep the previous state",
+                    mapping.name
+                );
+                // A value that is stuck in a non-OK state must not turn OK.
+                let updates_service = mapping.service.is_some();
+                let exit_value = mapping.state.last_exit_value.unwrap_or(0);
+                let exit_status = icinga::real_exit_value_to_status(updates_service, &exit_value);
+                return Ok((
+                    format!(
+                        "[{}] No time series changed since the previous execution",
+                        exit_status
+                    ),
+                    exit_value,
+                    None,
+                ));
+            }
+            time_series
+                .into_iter()
+                .filter(|ts| changed.contains(&ts.labels))
+                .collect()
+        }
+        None => time_series,
+    };
+
+    if let Some(derivation) = mapping.derive {
+        if time_series.is_empty() {
+            debug!(
+                "'{}': no previous samples to derive values from, wait for the next execution",
+                mapping.name
+            );
+            let updates_service = mapping.service.is_some();
+            let exit_status = icinga::exit_value_to_status(updates_service, &0);
+            return Ok((
+                format!(
+                    "[{}] Waiting for a second sample to compute the {}",
+                    exit_status,
+                    match derivation {
+                        Derivation::Delta => "delta",
+                        Derivation::Rate => "rate",
+                    }
+                ),
+                0,
+                None,
+            ));
+        }
+    }
+
+    // Convert the values (and baselines) to the unit the thresholds are
+    // written in.
+    let time_series: Vec<TimeSeries> = match mapping.transform {
+        Some(transform) => time_series
+            .into_iter()
+            .map(|mut ts| {
+                ts.value = transform.apply(ts.value);
+                ts.baseline = ts.baseline.map(|b| transform.apply(b));
+                ts
+            })
+            .collect(),
+        None => time_series,
+    };
+
+    // Drop or replace NaN and ±Inf values according to the configured policy.
+    let non_finite = time_series
+        .iter()
+        .filter(|ts| !ts.value.is_finite())
+        .count();
+
+    let time_series: Vec<TimeSeries> = match mapping.non_finite {
+        NonFinitePolicy::Unknown => time_series,
+        NonFinitePolicy::Drop => time_series
+            .into_iter()
+            .filter(|ts| ts.value.is_finite())
+            .collect(),
+        NonFinitePolicy::Substitute(substitute) => time_series
+            .into_iter()
+            .map(|mut ts| {
+                if !ts.value.is_finite() {
+                    ts.value = substitute;
+                }
+                ts
+            })
+            .collect(),
+    };
+
+    if time_series.is_empty() {
+        debug!(
+            "'{}': all {} time series in the PromQL query result were dropped due to non-finite values",
+            mapping.name, non_finite
+        );
+        return process_empty_result(mapping);
+    }
+
+    // Limit and round the values, so that float noise around a threshold
+    // (e.g. 89.99999 instead of 90) does not change the state.
+    let time_series: Vec<TimeSeries> = if mapping.clamp_min.is_some()
+        || mapping.clamp_max.is_some()
+        || mapping.precision.is_some()
+    {
+        time_series
+            .into_iter()
+            .map(|mut ts| {
+                ts.value = adjust_value(mapping, ts.value);
+                ts
+            })
+            .collect()
+    } else {
+        time_series
+    };
+
+    // Reduce the result set to a single time series if configured.
+    let time_series = match (mapping.aggregate, &mapping.count_breaching) {
+        (Some(aggregation), _) => group_time_series(&mapping.group_by, time_series)
+            .into_iter()
+            .map(|group| aggregate_time_series(aggregation, group))
+            .collect(),
+        (None, Some(range)) => group_time_series(&mapping.group_by, time_series)
+            .into_iter()
+            .map(|group| count_breaching_time_series(range, group))
+            .collect(),
+        (None, None) => time_series,
+    };
+
     // Process real and temporary exit values and exit status for each time series in
     // the query result set and store them together in a structure.
-    let data: Vec<Data> = process_time_series(mapping, time_series);
+    let mut data: Vec<Data> = process_time_series(mapping, time_series);
+
+    // Suppress threshold breaches that did not persist long enough yet.
+    apply_hysteresis(mapping, &mut data);
 
     // Compute the performance data corresponding to each time series.
     let performance_data = if mapping.performance_data.enabled {
-        Some(icinga::format_performance_data(mapping, &data)?)
+        let _render = trace_span!("render", part = "performance_data").entered();
+        let mut performance_data = icinga::format_performance_data(mapping, &data)?;
+        if mapping.performance_data.summary {
+            performance_data.extend(icinga::format_summary_performance_data(
+                mapping,
+                &data,
+                result_count,
+            ));
+        }
+        Some(performance_data)
     } else {
         None
     };
@@ -70,7 +1032,7 @@ fn process_query_result(
     // payload sent to the Icinga API. The exit value is the highest from the
     // set of all individual "real" exit values that were computed for each
     // data point.
-    let overall_real_exit_value = data
+    let mut overall_real_exit_value = data
         .iter()
         .max_by(|x, y| x.real_exit_value.cmp(&y.real_exit_value))
         .unwrap()
@@ -86,13 +1048,48 @@ fn process_query_result(
     // that is aware of possibly breached warning and critical thresholds.
     // As such the "temp" value is dropped after computing the default
     // output.
-    let overall_temp_exit_value = data
+    let data_temp_exit_value = data
         .iter()
         .max_by(|x, y| x.temp_exit_value.cmp(&y.temp_exit_value))
         .unwrap()
         .temp_exit_value;
+    let mut overall_temp_exit_value = data_temp_exit_value;
 
-    // The overall exit status associated with the "temporary exit value".
+    // Combine the state of the main query with the states of the
+    // additional conditions.
+    if let Some(conditions) = &mapping.conditions {
+        overall_temp_exit_value =
+            conditions.combine(overall_temp_exit_value, &mapping.state.conditions);
+        overall_real_exit_value =
+            icinga::to_real_exit_value(mapping.service.is_some(), overall_temp_exit_value);
+    }
+
+    // Raise the overall state when the query returned fewer or more time
+    // series than expected. The default output still describes the values
+    // by the state that results from the threshold checks alone.
+    let mut result_count_violation = check_result_count(mapping, result_count);
+    if result_count_violation.is_some() {
+        mapping.state.result_count_breaches += 1;
+        if mapping.state.result_count_breaches < mapping.breach_cycles {
+            debug!(
+                "'{}': suppress unexpected number of results ({} of {} cycles)",
+                mapping.name, mapping.state.result_count_breaches, mapping.breach_cycles
+            );
+            result_count_violation = None;
+        }
+    } else {
+        mapping.state.result_count_breaches = 0;
+    }
+    if result_count_violation.is_some() {
+        let exit_value = mapping.result_count.exit_value;
+        overall_temp_exit_value = overall_temp_exit_value.max(exit_value);
+        overall_real_exit_value = overall_real_exit_value.max(icinga::to_real_exit_value(
+            mapping.service.is_some(),
+            exit_value,
+        ));
+    }
+
+    // The overall exit status associated with the "temporary exit value".
     // One of "OK", "CRITICAL, "WARNING", "UNKNOWN" for Icinga services.
     // One of "UP", "DOWN" for Icinga hosts.
     let overall_exit_status =
@@ -100,6 +1097,7 @@ fn process_query_result(
 
     // Compute a plugin output either from a handlebars template (if any) or
     // fall back to generic default outputs.
+    let _render = trace_span!("render", part = "plugin_output").entered();
     let plugin_output = match mapping.plugin_output {
         Some(ref template) => {
             debug!(
@@ -115,110 +1113,333 @@ fn process_query_result(
             )?
         }
         None => {
-            if data.len() == 1 {
-                let value = data.first().unwrap().value;
+            let mut output = if let Some(label_states) = &mapping.label_states {
+                icinga::plugin_output::format_default_label_states(
+                    label_states,
+                    &data,
+                    data_temp_exit_value,
+                    overall_exit_status,
+                )
+            } else if let Some(expect) = &mapping.expect {
+                icinga::plugin_output::format_default_expectation(
+                    expect,
+                    &data,
+                    overall_exit_status,
+                )
+            } else if data.len() == 1 {
+                let item = data.first().unwrap();
                 icinga::plugin_output::format_default_single_item(
                     mapping,
-                    value,
-                    overall_temp_exit_value,
+                    &mapping.thresholds_for(&item.labels),
+                    item.value,
+                    data_temp_exit_value,
                     overall_exit_status,
                 )
             } else {
                 let values: Vec<&f64> = data.iter().map(|d| &d.value).collect();
-                icinga::plugin_output::format_default_multiple_items(
+                let mut output = icinga::plugin_output::format_default_multiple_items(
                     mapping,
                     &values,
-                    overall_temp_exit_value,
+                    data_temp_exit_value,
                     overall_exit_status,
-                )
+                );
+                if let Some(offenders) =
+                    icinga::plugin_output::format_top_offenders(&data, &mapping.default_output)
+                {
+                    output.push_str("; ");
+                    output.push_str(&offenders);
+                }
+                output
+            };
+
+            if let Some(violation) = result_count_violation {
+                output.push_str("; ");
+                output.push_str(&violation);
             }
+
+            if let Some(conditions) = &mapping.conditions {
+                output.push_str("; ");
+                output.push_str(&icinga::plugin_output::format_conditions(
+                    conditions,
+                    &mapping.state.conditions,
+                ));
+            }
+
+            // Point out that the values in the output are not the ones
+            // returned by the query.
+            if non_finite > 0 {
+                match mapping.non_finite {
+                    NonFinitePolicy::Unknown => {}
+                    NonFinitePolicy::Drop => output.push_str(&format!(
+                        "; {} time series with NaN/Inf values dropped",
+                        non_finite
+                    )),
+                    NonFinitePolicy::Substitute(substitute) => output.push_str(&format!(
+                        "; {} NaN/Inf value(s) replaced with {}",
+                        non_finite, substitute
+                    )),
+                }
+            }
+
+            if let Some(lines) = icinga::plugin_output::format_long_output(mapping, &data) {
+                output.push('\n');
+                output.push_str(&lines);
+            }
+
+            output
         }
     };
     Ok((plugin_output, overall_real_exit_value, performance_data))
 }
 
+/// Execute the PromQL query (and the queries it depends on) of a mapping
+/// and process the result to a plugin output, an exit value and optional
+/// performance data.
+async fn evaluate_mapping(
+    prom_client: &prometheus_http_query::Client,
+    mapping: &mut Mapping,
+) -> Result<(String, u8, Option<Vec<String>>), anyhow::Error> {
+    let query_start = Instant::now();
+
+    let query_result = prom_client
+        .query(&mapping.query, None, None)
+        .instrument(trace_span!("query", query = %mapping.query))
+        .await
+        .with_context(|| "failed to execute PromQL query")?;
+
+    // Make the query duration available to the plugin output template.
+    mapping.state.timing.query = Some(query_start.elapsed());
+
+    debug!(
+        "'{}': PromQL query finished in {:.3} millisecond(s)",
+        mapping.name,
+        query_start.elapsed().as_secs_f64() * 1000.0
+    );
+
+    let instant_vectors = query_result.as_instant().ok_or(anyhow!(
+        "failed to parse PromQL query result as instant vector"
+    ))?;
+
+    // Return the plugin output configured for an empty query result without
+    // performance data (by default UNKNOWN for service objects and DOWN for
+    // host objects). Else process the non-empty query result.
+    if instant_vectors.is_empty() {
+        trace_span!("process").in_scope(|| process_empty_result(mapping))
+    } else {
+        let mut time_series: Vec<TimeSeries> =
+            instant_vectors.iter().map(TimeSeries::from).collect();
+
+        if let Some(baseline_query) = &mapping.baseline_query {
+            debug!(
+                "'{}': execute PromQL baseline query '{}'",
+                mapping.name, baseline_query
+            );
+
+            let baseline_result = prom_client
+                .query(baseline_query, None, None)
+                .instrument(trace_span!("query", query = %baseline_query, baseline = true))
+                .await
+                .with_context(|| "failed to execute PromQL baseline query")?;
+
+            let baseline = baseline_result
+                .as_instant()
+                .ok_or(anyhow!(
+                    "failed to parse PromQL baseline query result as instant vector"
+                ))?
+                .iter()
+                .map(TimeSeries::from)
+                .collect();
+
+            attach_baseline(&mut time_series, baseline);
+        }
+
+        mapping.state.conditions = match &mapping.conditions {
+            Some(conditions) => evaluate_conditions(prom_client, mapping, conditions).await?,
+            None => vec![],
+        };
+
+        trace_span!("process").in_scope(|| process_query_result(mapping, time_series))
+    }
+}
+
+/// Build the check result that is submitted in place of the regular one
+/// when the evaluation of a mapping failed.
+fn query_error_result(
+    mapping: &Mapping,
+    exit_value: u8,
+    err: &anyhow::Error,
+) -> (String, u8, Option<Vec<String>>) {
+    // The state is configured in terms of service states, so collapse it
+    // to UP (0) and DOWN (1) for host objects just like `no_data.state`.
+    let updates_service = mapping.service.is_some();
+    let exit_status = icinga::exit_value_to_status(updates_service, &exit_value);
+    let real_exit_value = icinga::to_real_exit_value(updates_service, exit_value);
+    (
+        format!("[{}] {:#}", exit_status, err),
+        real_exit_value,
+        None,
+    )
+}
+
 /// This function performs all necessary steps to execute a PromQL query, process
-/// the query result, transform it to a passive check result and send it to Icinga.
+/// the query result, transform it to a passive check result and send it to each
+/// backend (e.g. Icinga).
 /// The result of this operation including any errors that may have occured in the
-/// process are returned to the calling function.
+/// process are returned to the calling function. On success the state of the
+/// mapping that is carried over to the next execution is returned.
 pub(crate) async fn execute_task(
     prom_client: prometheus_http_query::Client,
-    icinga_client: icinga::IcingaClient,
-    mapping: Mapping,
+    backends: Vec<Backend>,
+    mut mapping: Mapping,
 ) -> TaskResult {
-    tokio::spawn(async move {
-        let exec_start = get_unix_timestamp()
-            .with_context(|| "failed to retrieve UNIX timestamp to measure event execution")?;
+    tokio::spawn(
+        async move {
+            let exec_start = get_unix_timestamp()
+                .with_context(|| "failed to retrieve UNIX timestamp to measure event execution")?;
+            mapping.state.timing.execution_start = Some(Local::now());
+            mapping.state.skipped = None;
 
-        debug!(
-            "'{}': start processing mapping at {}",
-            mapping.name, exec_start
-        );
+            debug!(
+                "'{}': start processing mapping at {:.3}",
+                mapping.name, exec_start
+            );
 
-        debug!(
-            "'{}': execute PromQL query '{}'",
-            mapping.name, mapping.query
-        );
+            let maintenance = active_maintenance_window(&mapping, Local::now().naive_local());
 
-        let query_result = prom_client
-            .query(&mapping.query, None, None)
-            .await
-            .with_context(|| "failed to execute PromQL query")?;
+            if maintenance == Some(MaintenanceAction::Skip) {
+                debug!(
+                    "'{}': skip execution during maintenance window",
+                    mapping.name
+                );
+                mapping.state.skipped = Some("maintenance window");
+                return Ok(mapping.state);
+            }
 
-        let instant_vectors = query_result.as_instant().ok_or(anyhow!(
-            "failed to parse PromQL query result as instant vector"
-        ))?;
+            debug!(
+                "'{}': execute PromQL query '{}'",
+                mapping.name, mapping.query
+            );
 
-        // Return a default plugin output without performance data when the query result is empty:
-        //  - UNKNOWN (3) for service objects
-        //  - DOWN (1) for host objects
-        // Else process the non-empty query result.
-        let (plugin_output, overall_exit_value, performance_data) = if instant_vectors.is_empty() {
-            let updates_service = mapping.service.is_some();
-            let plugin_output =
-                icinga::plugin_output::format_default_without_data(&mapping.name, updates_service);
-            let overall_exit_value = if updates_service { 3 } else { 1 };
-            let performance_data = None;
-            (plugin_output, overall_exit_value, performance_data)
-        } else {
-            let time_series: Vec<TimeSeries> =
-                instant_vectors.iter().map(TimeSeries::from).collect();
-            process_query_result(&mapping, time_series)?
-        };
-
-        let exec_end = get_unix_timestamp()
-            .with_context(|| "failed to retrieve UNIX timestamp to measure event execution")?;
-
-        // Build the JSON payload to be sent to the Icinga API.
-        // Note that the exit value is the "real" one as the API
-        // returns HTTP 400 Bad Request when host states (= exit values)
-        // other than 0 or 1 are sent.
-        let payload = icinga::build_payload(
-            &mapping,
-            overall_exit_value,
-            plugin_output,
-            performance_data,
-            exec_start,
-            exec_end,
-        )?;
+            let evaluation = evaluate_mapping(&prom_client, &mut mapping).await;
 
-        debug!(
-            "'{}': stop measuring processing of mapping at {}",
-            mapping.name, exec_end
-        );
+            let (plugin_output, overall_exit_value, performance_data) =
+                match (evaluation, mapping.query_error_state) {
+                    (Ok(result), _) => result,
+                    (Err(err), Some(exit_value)) => {
+                        warn!(
+                            "'{}': submit {} check result due to failed evaluation: {:#}",
+                            mapping.name,
+                            icinga::exit_value_to_status(mapping.service.is_some(), &exit_value),
+                            err
+                        );
+                        query_error_result(&mapping, exit_value, &err)
+                    }
+                    (Err(err), None) => return Err(err),
+                };
 
-        icinga_client
-            .send(&mapping, payload)
-            .await
-            .with_context(|| "failed to send passive check result to Icinga")?;
+            let (plugin_output, overall_exit_value) =
+                apply_flap_detection(&mut mapping, plugin_output, overall_exit_value);
 
-        debug!(
-            "'{}': passive check result was successfully send to Icinga",
-            mapping.name
-        );
+            let (plugin_output, overall_exit_value) =
+                apply_escalation(&mut mapping, plugin_output, overall_exit_value);
 
-        Ok(())
-    })
+            let (plugin_output, overall_exit_value) = if maintenance == Some(MaintenanceAction::Ok)
+            {
+                debug!(
+                    "'{}': force state to OK during maintenance window",
+                    mapping.name
+                );
+                let status = icinga::exit_value_to_status(mapping.service.is_some(), &0);
+                (
+                    format!("[{}] Maintenance window active; {}", status, plugin_output),
+                    0,
+                )
+            } else {
+                (plugin_output, overall_exit_value)
+            };
+
+            let (plugin_output, overall_exit_value) = match apply_grace_period(
+                &mapping,
+                plugin_output,
+                overall_exit_value,
+                Instant::now(),
+            ) {
+                Some(result) => result,
+                None => {
+                    debug!(
+                        "'{}': skip submission of non-OK result during startup grace period",
+                        mapping.name
+                    );
+                    mapping.state.skipped = Some("startup grace period");
+                    return Ok(mapping.state);
+                }
+            };
+
+            let plugin_output = match mapping.max_output_length {
+                Some(max_len) => {
+                    icinga::truncate_plugin_output(&plugin_output, "", max_len, char::len_utf8)
+                }
+                None => plugin_output,
+            };
+
+            record_exit_value(&mut mapping.state, overall_exit_value);
+
+            let exec_end = get_unix_timestamp()
+                .with_context(|| "failed to retrieve UNIX timestamp to measure event execution")?;
+
+            let result = CheckResult {
+                exit_value: overall_exit_value,
+                plugin_output,
+                performance_data,
+                execution_start: exec_start,
+                execution_end: exec_end,
+            };
+
+            debug!(
+                "'{}': stop measuring processing of mapping at {:.3}",
+                mapping.name, exec_end
+            );
+
+            // Submit the check result to every backend, even when the
+            // submission to one of them fails.
+            let mut failed = vec![];
+
+            let submission_start = Instant::now();
+
+            for backend in backends.iter() {
+                match backend
+                    .send(&mapping, &result)
+                    .instrument(trace_span!("submit", backend = backend.name()))
+                    .await
+                {
+                    Ok(()) => debug!(
+                        "'{}': passive check result was successfully sent to {}",
+                        mapping.name,
+                        backend.name()
+                    ),
+                    Err(err) => failed.push(format!("{}: {:#}", backend.name(), err)),
+                }
+            }
+
+            let submission = submission_start.elapsed();
+
+            debug!(
+                "'{}': submission of the passive check result finished in {:.3} millisecond(s)",
+                mapping.name,
+                submission.as_secs_f64() * 1000.0
+            );
+
+            if !failed.is_empty() {
+                return Err(SubmissionError { failed }.into());
+            }
+
+            mapping.state.timing.submission = Some(submission);
+
+            Ok(mapping.state)
+        }
+        // Spans are not inherited by spawned tasks.
+        .in_current_span(),
+    )
     .await
 }
 
@@ -238,13 +1459,53 @@ mod tests {
             thresholds: ThresholdPair {
                 warning: None,
                 critical: None,
+                unknown: None,
+                ok: None,
             },
             host: "foo".to_string(),
             service: None,
             interval: Duration::from_secs(60),
             last_apply: Instant::now(),
             plugin_output: None,
-            performance_data: PerformanceData::default()
+            performance_data: PerformanceData::default(),
+            sync_vars: false,
+            request_timeout: None,
+            basic_auth: None,
+            state: MappingState::default(),
+            threshold_rules: vec![],
+            baseline_query: None,
+            no_data: NoData::default(),
+            non_finite: NonFinitePolicy::default(),
+            result_count: ResultCount::default(),
+            aggregate: None,
+            transform: None,
+            breach_cycles: 1,
+            flapping: None,
+            state_map: [0, 1, 2, 3],
+            maintenance: vec![],
+            label_states: None,
+            state_from_label: None,
+            include: vec![],
+            exclude: vec![],
+            deduplicate: None,
+            derive: None,
+            default_output: DefaultOutput::default(),
+            expect: None,
+            conditions: None,
+            threshold_templates: ThresholdTemplates::default(),
+            grace_period: None,
+            escalate_after: None,
+            count_breaching: None,
+            group_by: vec![],
+            ignore_unchanged: false,
+            clamp_min: None,
+            clamp_max: None,
+            precision: None,
+            query_error_state: None,
+            partials: Default::default(),
+            plugin_output_strict: true,
+            template_engine: TemplateEngine::default(),
+            max_output_length: None,
         }
     }
 
@@ -345,6 +1606,22 @@ mod tests {
         label_set
     }
 
+    #[test]
+    fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("vec2checkd-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        assert!(!watcher.changed());
+
+        std::fs::write(&path, "foo").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+    }
+
     #[test]
     fn test_process_query_result_for_host_objects() {
         let label_set = seed_labels();
@@ -356,6 +1633,7 @@ mod tests {
             .map(|(labels, value)| TimeSeries {
                 labels: labels.clone(),
                 value: *value,
+                baseline: None,
             })
             .collect::<Vec<TimeSeries>>();
 
@@ -366,7 +1644,7 @@ mod tests {
         let expected_output =
             "[UP] PromQL query returned multiple results in the range 0.00..=5.12".to_string();
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
             (expected_output.clone(), 0, None)
         );
 
@@ -378,7 +1656,7 @@ mod tests {
             .map(|(value, checksum)| format!("'{}/{}'={};;;;", mapping.name, checksum, value))
             .collect::<Vec<String>>();
         assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
+            process_query_result(&mut mapping, time_series).unwrap(),
             (expected_output, 0, Some(perfdata))
         );
 
@@ -386,6 +1664,7 @@ mod tests {
         let time_series = vec![TimeSeries {
             labels: label_set[0].clone(),
             value: 12.34534534,
+            baseline: None,
         }];
         let expected_output = "[UP] PromQL query returned one result (12.35)".to_string();
         let perfdata = vec![format!(
@@ -395,7 +1674,7 @@ mod tests {
             12.34534534
         )];
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
             (expected_output, 0, Some(perfdata))
         );
     }
@@ -411,263 +1690,1771 @@ mod tests {
             .map(|(labels, value)| TimeSeries {
                 labels: labels.clone(),
                 value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        let mut mapping = seed_mapping();
+        mapping.service = Some("bar".to_string());
+
+        // Test: Default output, multiple time series, OK, service object, no performance data.
+        mapping.performance_data.enabled = false;
+        let expected_output =
+            "[OK] PromQL query returned multiple results in the range 0.00..=5.12".to_string();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output.clone(), 0, None)
+        );
+
+        // Test: Default output, multiple time series, OK, service object, with performance data.
+        mapping.performance_data.enabled = true;
+        mapping.thresholds.warning = Some(NagiosRange::from("@10:").unwrap());
+        let perfdata = values
+            .iter()
+            .zip(checksums.iter())
+            .map(|(value, checksum)| format!("'{}/{}'={};@10:~;;;", mapping.name, checksum, value))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (expected_output, 0, Some(perfdata))
+        );
+
+        // Test: Default output, single time series, OK, service object, with performance data.
+        let time_series = vec![TimeSeries {
+            labels: label_set[0].clone(),
+            value: 9.21837821321,
+            baseline: None,
+        }];
+        mapping.thresholds.critical = Some(NagiosRange::from("10").unwrap());
+        let expected_output = "[OK] PromQL query returned one result (9.22)".to_string();
+        let perfdata = vec![format!(
+            "'{}/{}'={};@10:~;0:10;;",
+            mapping.name,
+            checksums[0].clone(),
+            9.21837821321
+        )];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output, 0, Some(perfdata))
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_for_host_objects_with_warning() {
+        let label_set = seed_labels();
+        let checksums = seed_checksums();
+        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 0.0, 0.0];
+        let time_series = label_set
+            .iter()
+            .zip(values.iter())
+            .map(|(labels, value)| TimeSeries {
+                labels: labels.clone(),
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        let mut mapping = seed_mapping();
+        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
+        mapping.thresholds.critical = Some(NagiosRange::from("20").unwrap());
+        mapping.performance_data.uom = Some("ms".to_string());
+
+        // Test: Default output, multiple time series, WARNING, host object, with performance data.
+        let expected_output = "[UP] PromQL query returned multiple results within the warning range (values 0.00..=5.12 overlap with @0:10)".to_string();
+        let perfdata = values
+            .iter()
+            .zip(checksums.iter())
+            .map(|(value, checksum)| {
+                format!("'{}/{}'={}ms;@0:10;0:20;;", mapping.name, checksum, value)
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (expected_output, 0, Some(perfdata))
+        );
+
+        // Test: Default output, single time series, WARNING, host object, with performance data.
+        let time_series = vec![TimeSeries {
+            labels: label_set[0].clone(),
+            value: 9.34534534,
+            baseline: None,
+        }];
+        let expected_output =
+            "[UP] PromQL query returned one result within the warning range (9.35 in @0:10)"
+                .to_string();
+        let perfdata = vec![format!(
+            "'{}/{}'={}ms;@0:10;0:20;;",
+            mapping.name,
+            checksums[0].clone(),
+            9.34534534
+        )];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output, 0, Some(perfdata))
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_for_service_objects_with_warning() {
+        let label_set = seed_labels();
+        let checksums = seed_checksums();
+        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 0.0, 0.0];
+        let time_series = label_set
+            .iter()
+            .zip(values.iter())
+            .map(|(labels, value)| TimeSeries {
+                labels: labels.clone(),
+                value: *value,
+                baseline: None,
             })
             .collect::<Vec<TimeSeries>>();
 
         let mut mapping = seed_mapping();
         mapping.service = Some("bar".to_string());
+        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
+        mapping.thresholds.critical = Some(NagiosRange::from("20").unwrap());
+
+        // Test: Default output, multiple time series, WARNING, service object, with performance data.
+        let expected_output = "[WARNING] PromQL query returned multiple results within the warning range (values 0.00..=5.12 overlap with @0:10)".to_string();
+        let perfdata = values
+            .iter()
+            .zip(checksums.iter())
+            .map(|(value, checksum)| {
+                format!("'{}/{}'={};@0:10;0:20;;", mapping.name, checksum, value)
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (expected_output, 1, Some(perfdata))
+        );
+
+        // Test: Default output, single time series, WARNING, service object, with performance data.
+        let time_series = vec![TimeSeries {
+            labels: label_set[0].clone(),
+            value: 9.34534534,
+            baseline: None,
+        }];
+        let expected_output =
+            "[WARNING] PromQL query returned one result within the warning range (9.35 in @0:10)"
+                .to_string();
+        let perfdata = vec![format!(
+            "'{}/{}'={};@0:10;0:20;;",
+            mapping.name,
+            checksums[0].clone(),
+            9.34534534
+        )];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output, 1, Some(perfdata))
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_for_host_objects_with_critical() {
+        let label_set = seed_labels();
+        let checksums = seed_checksums();
+        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 25.4534534, 0.0];
+        let time_series = label_set
+            .iter()
+            .zip(values.iter())
+            .map(|(labels, value)| TimeSeries {
+                labels: labels.clone(),
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        let mut mapping = seed_mapping();
+        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
+        mapping.thresholds.critical = Some(NagiosRange::from("@20:30").unwrap());
+
+        // Test: Default output, multiple time series, CRITICAL, host object, with performance data.
+        let expected_output = "[DOWN] PromQL query returned multiple results within the critical range (values 0.00..=25.45 overlap with @20:30)".to_string();
+        let perfdata = values
+            .iter()
+            .zip(checksums.iter())
+            .map(|(value, checksum)| {
+                format!("'{}/{}'={};@0:10;@20:30;;", mapping.name, checksum, value)
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (expected_output, 1, Some(perfdata))
+        );
+
+        // Test: Default output, single time series, CRITICAL, host object, with performance data.
+        mapping.thresholds.warning = None;
+        let time_series = vec![TimeSeries {
+            labels: label_set[0].clone(),
+            value: 28.34534534,
+            baseline: None,
+        }];
+        let expected_output =
+            "[DOWN] PromQL query returned one result within the critical range (28.35 in @20:30)"
+                .to_string();
+        let perfdata = vec![format!(
+            "'{}/{}'={};;@20:30;;",
+            mapping.name,
+            checksums[0].clone(),
+            28.34534534
+        )];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output, 1, Some(perfdata))
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_for_service_objects_with_critical() {
+        let label_set = seed_labels();
+        let checksums = seed_checksums();
+        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 25.4534534, 0.0];
+        let time_series = label_set
+            .iter()
+            .zip(values.iter())
+            .map(|(labels, value)| TimeSeries {
+                labels: labels.clone(),
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        let mut mapping = seed_mapping();
+        mapping.service = Some("bar".to_string());
+        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
+        mapping.thresholds.critical = Some(NagiosRange::from("@20:30").unwrap());
+
+        // Test: Default output, multiple time series, CRITICAL, service object, with performance data.
+        let expected_output = "[CRITICAL] PromQL query returned multiple results within the critical range (values 0.00..=25.45 overlap with @20:30)".to_string();
+        let perfdata = values
+            .iter()
+            .zip(checksums.iter())
+            .map(|(value, checksum)| {
+                format!("'{}/{}'={};@0:10;@20:30;;", mapping.name, checksum, value)
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (expected_output, 2, Some(perfdata))
+        );
+
+        // Test: Default output, single time series, CRITICAL, service object, with performance data.
+        mapping.thresholds.warning = None;
+        let time_series = vec![TimeSeries {
+            labels: label_set[0].clone(),
+            value: 28.34534534,
+            baseline: None,
+        }];
+        let expected_output =
+            "[CRITICAL] PromQL query returned one result within the critical range (28.35 in @20:30)"
+                .to_string();
+        let perfdata = vec![format!(
+            "'{}/{}'={};;@20:30;;",
+            mapping.name,
+            checksums[0].clone(),
+            28.34534534
+        )];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (expected_output, 2, Some(perfdata))
+        );
+    }
+
+    #[test]
+    fn test_process_time_series_with_threshold_rules() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Disk usage".to_string());
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("80").unwrap()),
+            critical: Some(NagiosRange::from("90").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        mapping.threshold_rules = vec![
+            ThresholdRule {
+                matchers: vec![crate::config::parse_label_matcher(r#"mountpoint="/boot""#).unwrap()],
+                thresholds: ThresholdPair {
+                    warning: Some(NagiosRange::from("95").unwrap()),
+                    critical: Some(NagiosRange::from("98").unwrap()),
+                    unknown: None,
+                    ok: None,
+                },
+            },
+            ThresholdRule {
+                matchers: vec![
+                    crate::config::parse_label_matcher(r#"mountpoint=~"/var(/.*)?""#).unwrap(),
+                    crate::config::parse_label_matcher("device!=tmpfs").unwrap(),
+                ],
+                thresholds: ThresholdPair {
+                    warning: Some(NagiosRange::from("50").unwrap()),
+                    critical: Some(NagiosRange::from("60").unwrap()),
+                    unknown: None,
+                    ok: None,
+                },
+            },
+        ];
+
+        let time_series = [
+            ("/", "sda1"),
+            ("/boot", "sda2"),
+            ("/var/log", "sda3"),
+            ("/var/tmp", "tmpfs"),
+        ]
+        .iter()
+        .map(|(mountpoint, device)| TimeSeries {
+            labels: BTreeMap::from([
+                ("mountpoint".to_string(), mountpoint.to_string()),
+                ("device".to_string(), device.to_string()),
+            ]),
+            value: 85.0,
+            baseline: None,
+        })
+        .collect::<Vec<TimeSeries>>();
+
+        // The first matching rule wins, the mapping thresholds are the fallback.
+        let exit_values = process_time_series(&mapping, time_series)
+            .iter()
+            .map(|d| d.real_exit_value)
+            .collect::<Vec<u8>>();
+        assert_eq!(exit_values, vec![1, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_process_time_series_with_baseline() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Requests".to_string());
+        mapping.baseline_query = Some("http_requests offset 1w".to_string());
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("-20:20").unwrap()),
+            critical: Some(NagiosRange::from("-50:50").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+
+        let series = |name: &str, job: &str, value: f64| TimeSeries {
+            labels: BTreeMap::from([
+                ("__name__".to_string(), name.to_string()),
+                ("job".to_string(), job.to_string()),
+            ]),
+            value,
+            baseline: None,
+        };
+
+        let mut time_series = vec![
+            series("http_requests", "a", 110.0),
+            series("http_requests", "b", 130.0),
+            series("http_requests", "c", 40.0),
+            series("http_requests", "d", 40.0),
+        ];
+        let baseline = vec![
+            series("http_requests", "a", 100.0),
+            series("http_requests", "b", 100.0),
+            series("http_requests", "c", 100.0),
+        ];
+
+        attach_baseline(&mut time_series, baseline);
+        assert_eq!(
+            time_series.iter().map(|ts| ts.baseline).collect::<Vec<_>>(),
+            vec![Some(100.0), Some(100.0), Some(100.0), None]
+        );
+
+        let data = process_time_series(&mapping, time_series);
+        assert_eq!(
+            data.iter().map(|d| d.real_exit_value).collect::<Vec<u8>>(),
+            vec![0, 1, 2, 0]
+        );
+        assert_eq!(data[2].deviation, Some(-60.0));
+    }
+
+    #[test]
+    fn test_process_empty_result() {
+        let mut mapping = seed_mapping();
+
+        // Test: Default state for host objects.
+        assert_eq!(
+            process_empty_result(&mapping).unwrap(),
+            (
+                "[DOWN] PromQL query result set is empty".to_string(),
+                1,
+                None
+            )
+        );
+
+        // Test: Default state for service objects.
+        mapping.service = Some("Absent jobs".to_string());
+        assert_eq!(
+            process_empty_result(&mapping).unwrap(),
+            (
+                "[UNKNOWN] PromQL query result set is empty".to_string(),
+                3,
+                None
+            )
+        );
+
+        // Test: Configured state and output template.
+        mapping.no_data = NoData {
+            exit_value: 0,
+            plugin_output: Some("[{{ exit_status }}] No job is absent".to_string()),
+        };
+        assert_eq!(
+            process_empty_result(&mapping).unwrap(),
+            ("[OK] No job is absent".to_string(), 0, None)
+        );
+
+        // Test: Configured state for host objects.
+        mapping.service = None;
+        mapping.no_data = NoData {
+            exit_value: 1,
+            plugin_output: None,
+        };
+        assert_eq!(
+            process_empty_result(&mapping).unwrap(),
+            ("[UP] PromQL query result set is empty".to_string(), 0, None)
+        );
+
+        // Test: Placeholder performance data.
+        mapping.performance_data.absent = Some(0.0);
+        assert_eq!(
+            process_empty_result(&mapping).unwrap(),
+            (
+                "[UP] PromQL query result set is empty".to_string(),
+                0,
+                Some(vec!["'Node status/absent'=0;;;;".to_string()])
+            )
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_non_finite_values() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Ratio".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.thresholds = ThresholdPair {
+            warning: None,
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let labels = seed_labels();
+        let time_series = vec![
+            TimeSeries {
+                labels: labels[0].clone(),
+                value: 1.0,
+                baseline: None,
+            },
+            TimeSeries {
+                labels: labels[1].clone(),
+                value: f64::NAN,
+                baseline: None,
+            },
+        ];
+
+        // Test: NaN values result in an UNKNOWN state by default.
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            ("[UNKNOWN] PromQL query returned multiple results, 1 of which are not finite numbers (other values in the range 1.00..=1.00)".to_string(), 3, None)
+        );
+
+        // Test: NaN values are dropped.
+        mapping.non_finite = NonFinitePolicy::Drop;
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            ("[OK] PromQL query returned one result (1.00); 1 time series with NaN/Inf values dropped".to_string(), 0, None)
+        );
+
+        // Test: NaN values are replaced.
+        mapping.non_finite = NonFinitePolicy::Substitute(20.0);
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            ("[CRITICAL] PromQL query returned multiple results within the critical range (values 1.00..=20.00 overlap with 0:10); 1 NaN/Inf value(s) replaced with 20".to_string(), 2, None)
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_result_count() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("etcd members".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.result_count = ResultCount {
+            min: Some(3),
+            max: Some(3),
+            exit_value: 1,
+        };
+        let labels = seed_labels();
+        let time_series = labels
+            .iter()
+            .take(2)
+            .map(|labels| TimeSeries {
+                labels: labels.clone(),
+                value: 1.0,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            ("[WARNING] PromQL query returned multiple results in the range 1.00..=1.00; expected at least 3 result(s), got 2".to_string(), 1, None)
+        );
+
+        // Test: Host objects stay UP when the configured state is WARNING.
+        mapping.service = None;
+        let time_series = labels
+            .iter()
+            .take(4)
+            .map(|labels| TimeSeries {
+                labels: labels.clone(),
+                value: 1.0,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            ("[UP] PromQL query returned multiple results in the range 1.00..=1.00; expected at most 3 result(s), got 4".to_string(), 0, None)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_time_series() {
+        let series = |instance: &str, value: f64| TimeSeries {
+            labels: BTreeMap::from([
+                ("__name__".to_string(), "up".to_string()),
+                ("job".to_string(), "etcd".to_string()),
+                ("instance".to_string(), instance.to_string()),
+            ]),
+            value,
+            baseline: None,
+        };
+        let time_series = vec![series("a", 1.0), series("b", 4.0), series("c", 7.0)];
+
+        let aggregated = aggregate_time_series(Aggregation::Sum, time_series.clone());
+        assert_eq!(
+            aggregated.labels,
+            BTreeMap::from([("job".to_string(), "etcd".to_string())])
+        );
+        assert_eq!(aggregated.value, 12.0);
+        assert_eq!(aggregated.baseline, None);
+
+        let values = time_series.iter().map(|ts| ts.value).collect::<Vec<f64>>();
+        assert_eq!(Aggregation::Avg.apply(&values), 4.0);
+        assert_eq!(Aggregation::Max.apply(&values), 7.0);
+        assert_eq!(Aggregation::Min.apply(&values), 1.0);
+        assert_eq!(Aggregation::Count.apply(&values), 3.0);
+        assert_eq!(Aggregation::Percentile(50.0).apply(&values), 4.0);
+        assert_eq!(Aggregation::Percentile(100.0).apply(&values), 7.0);
+
+        let latencies: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(Aggregation::Percentile(95.0).apply(&latencies), 95.05);
+        assert_eq!(Aggregation::Percentile(99.0).apply(&[2.0, 1.0]), 1.99);
+    }
+
+    #[test]
+    fn test_process_query_result_with_transform() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Memory".to_string());
+        mapping.performance_data.enabled = false;
+        // Bytes to GiB.
+        mapping.transform = Some(Transform {
+            factor: 1.0 / 1024.0_f64.powi(3),
+            offset: 0.0,
+        });
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("4").unwrap()),
+            critical: None,
+            unknown: None,
+            ok: None,
+        };
+        let time_series = vec![TimeSeries {
+            labels: seed_labels()[0].clone(),
+            value: 5.0 * 1024.0_f64.powi(3),
+            baseline: None,
+        }];
+
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[WARNING] PromQL query returned one result within the warning range (5.00 in 0:4)"
+                    .to_string(),
+                1,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_query_error_result() {
+        let mut mapping = seed_mapping();
+        let err = anyhow!("connection refused").context("failed to execute PromQL query");
+
+        assert_eq!(
+            query_error_result(&mapping, 2, &err),
+            (
+                "[DOWN] failed to execute PromQL query: connection refused".to_string(),
+                1,
+                None
+            )
+        );
+        assert_eq!(query_error_result(&mapping, 3, &err).1, 1);
+        assert_eq!(query_error_result(&mapping, 1, &err).1, 0);
+
+        mapping.service = Some("Node status".to_string());
+        assert_eq!(
+            query_error_result(&mapping, 3, &err),
+            (
+                "[UNKNOWN] failed to execute PromQL query: connection refused".to_string(),
+                3,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_adjust_value() {
+        let mut mapping = seed_mapping();
+        mapping.clamp_min = Some(0.0);
+        mapping.clamp_max = Some(100.0);
+        mapping.precision = Some(2);
+
+        assert_eq!(adjust_value(&mapping, 89.999999), 90.0);
+        assert_eq!(adjust_value(&mapping, -0.5), 0.0);
+        assert_eq!(adjust_value(&mapping, 100.004), 100.0);
+        assert_eq!(adjust_value(&mapping, 42.125), 42.13);
+        assert!(adjust_value(&mapping, f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_process_query_result_with_precision() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Disk usage".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.precision = Some(2);
+        mapping.thresholds = ThresholdPair {
+            warning: None,
+            critical: Some(NagiosRange::from("90").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let time_series = vec![TimeSeries {
+            labels: seed_labels()[0].clone(),
+            value: 90.0000001,
+            baseline: None,
+        }];
+
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap().1,
+            0
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_hysteresis() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Load".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.breach_cycles = 2;
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: None,
+            unknown: None,
+            ok: None,
+        };
+        let series = |value: f64| {
+            vec![TimeSeries {
+                labels: seed_labels()[0].clone(),
+                value,
+                baseline: None,
+            }]
+        };
+
+        // Test: The first breach is suppressed, the second one is not.
+        assert_eq!(
+            process_query_result(&mut mapping, series(10.0)).unwrap().1,
+            0
+        );
+        assert_eq!(
+            process_query_result(&mut mapping, series(10.0)).unwrap().1,
+            1
+        );
+        assert_eq!(
+            process_query_result(&mut mapping, series(10.0)).unwrap().1,
+            1
+        );
+
+        // Test: A single OK result resets the counter.
+        assert_eq!(
+            process_query_result(&mut mapping, series(1.0)).unwrap().1,
+            0
+        );
+        assert!(mapping.state.breaches.is_empty());
+        assert_eq!(
+            process_query_result(&mut mapping, series(10.0)).unwrap().1,
+            0
+        );
+
+        // Test: A breach that moves to another time series is not suppressed
+        // once the mapping as a whole breached in enough executions.
+        let other = vec![TimeSeries {
+            labels: seed_labels()[1].clone(),
+            value: 10.0,
+            baseline: None,
+        }];
+        assert_eq!(process_query_result(&mut mapping, other).unwrap().1, 1);
+        assert_eq!(mapping.state.breaches.values().max(), Some(&1));
+    }
+
+    #[test]
+    fn test_apply_flap_detection() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Flappy".to_string());
+        mapping.flapping = Some(FlapDetection {
+            window: 4,
+            max_changes: 1,
+        });
+
+        let mut check = |exit_value: u8| {
+            apply_flap_detection(&mut mapping, "[STATE] output".to_string(), exit_value)
+        };
+
+        assert_eq!(check(0), ("[STATE] output".to_string(), 0));
+        assert_eq!(check(2), ("[STATE] output".to_string(), 2));
+        // Test: The second change within the window holds the last stable state.
+        assert_eq!(
+            check(0),
+            (
+                "[STATE] output (flapping suppressed, holding last stable state CRITICAL)"
+                    .to_string(),
+                2
+            )
+        );
+        assert_eq!(check(2).1, 2);
+        assert_eq!(check(0).1, 2);
+        // Test: The state settles once the changes drop out of the window.
+        assert_eq!(check(0).1, 2);
+        assert_eq!(check(0).1, 0);
+    }
+
+    #[test]
+    fn test_process_query_result_with_unknown_range() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Exporter".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.thresholds = ThresholdPair {
+            warning: None,
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: Some(NagiosRange::from("@-1:-1").unwrap()),
+            ok: None,
+        };
+        let time_series = vec![TimeSeries {
+            labels: seed_labels()[0].clone(),
+            value: -1.0,
+            baseline: None,
+        }];
+
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[UNKNOWN] PromQL query returned one result within the unknown range (-1.00 in @-1:-1)"
+                    .to_string(),
+                3,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_process_time_series_with_ok_range() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Replicas".to_string());
+        mapping.thresholds = ThresholdPair {
+            warning: None,
+            critical: Some(NagiosRange::from("0:100").unwrap()),
+            unknown: None,
+            ok: Some(OkRange {
+                range: NagiosRange::from("3:5").unwrap(),
+                exit_value: 1,
+            }),
+        };
+        let time_series = [4.0, 6.0, 2.0, 200.0]
+            .iter()
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        assert_eq!(
+            process_time_series(&mapping, time_series)
+                .iter()
+                .map(|d| d.real_exit_value)
+                .collect::<Vec<u8>>(),
+            vec![0, 1, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_process_time_series_with_state_map() {
+        let mut mapping = seed_mapping();
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        // Treat WARNING as CRITICAL and CRITICAL as WARNING.
+        mapping.state_map = [0, 2, 1, 3];
+        let time_series = [1.0, 7.0, 20.0]
+            .iter()
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        // Test: Host objects.
+        let data = process_time_series(&mapping, time_series.clone());
+        assert_eq!(
+            data.iter()
+                .map(|d| (d.real_exit_value, d.temp_exit_value))
+                .collect::<Vec<(u8, u8)>>(),
+            vec![(0, 0), (1, 2), (0, 1)]
+        );
+
+        // Test: Service objects.
+        mapping.service = Some("Remapped".to_string());
+        let data = process_time_series(&mapping, time_series);
+        assert_eq!(
+            data.iter()
+                .map(|d| d.exit_status.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["OK", "CRITICAL", "WARNING"]
+        );
+    }
+
+    #[test]
+    fn test_active_maintenance_window() {
+        use chrono::{NaiveDate, NaiveTime, Weekday};
+
+        let at = |day: u32, h: u32, m: u32| {
+            // 2022-01-03 is a Monday.
+            NaiveDate::from_ymd_opt(2022, 1, day)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let mut mapping = seed_mapping();
+        assert_eq!(active_maintenance_window(&mapping, at(3, 1, 0)), None);
+
+        mapping.maintenance = vec![
+            MaintenanceWindow {
+                days: vec![Weekday::Mon],
+                start: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(2, 0, 0).unwrap(),
+                action: MaintenanceAction::Skip,
+            },
+            MaintenanceWindow {
+                days: vec![],
+                start: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(12, 30, 0).unwrap(),
+                action: MaintenanceAction::Ok,
+            },
+        ];
+
+        // The first window starts on Monday and spans midnight.
+        assert_eq!(
+            active_maintenance_window(&mapping, at(3, 23, 30)),
+            Some(MaintenanceAction::Skip)
+        );
+        assert_eq!(
+            active_maintenance_window(&mapping, at(4, 1, 59)),
+            Some(MaintenanceAction::Skip)
+        );
+        assert_eq!(active_maintenance_window(&mapping, at(4, 2, 0)), None);
+        assert_eq!(active_maintenance_window(&mapping, at(3, 1, 0)), None);
+        assert_eq!(active_maintenance_window(&mapping, at(4, 23, 30)), None);
+
+        // The second window applies on every day.
+        assert_eq!(
+            active_maintenance_window(&mapping, at(5, 12, 15)),
+            Some(MaintenanceAction::Ok)
+        );
+        assert_eq!(active_maintenance_window(&mapping, at(5, 12, 30)), None);
+    }
+
+    #[test]
+    fn test_process_query_result_with_label_states() {
+        let unit_states = |states: &[(&str, f64)]| {
+            states
+                .iter()
+                .map(|(state, value)| TimeSeries {
+                    labels: BTreeMap::from([
+                        ("name".to_string(), "backup.service".to_string()),
+                        ("state".to_string(), state.to_string()),
+                    ]),
+                    value: *value,
+                    baseline: None,
+                })
+                .collect::<Vec<TimeSeries>>()
+        };
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Backup".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.label_states = Some(LabelStates {
+            label: "state".to_string(),
+            states: HashMap::from([
+                ("active".to_string(), 0),
+                ("inactive".to_string(), 0),
+                ("activating".to_string(), 1),
+                ("failed".to_string(), 2),
+            ]),
+            default: 3,
+        });
+
+        // Test: Only the series with a non-zero sample is evaluated.
+        let time_series = unit_states(&[("active", 0.0), ("failed", 1.0), ("inactive", 0.0)]);
+        let data = process_time_series(&mapping, time_series.clone());
+        assert_eq!(
+            data.iter().map(|d| d.temp_exit_value).collect::<Vec<u8>>(),
+            vec![0, 2, 0]
+        );
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[CRITICAL] PromQL query returned 3 result(s), 1 of which with label 'state' in a non-OK state ('failed')".to_string(),
+                2,
+                None
+            )
+        );
+
+        // Test: Label values that are not in the table fall back to the default.
+        let time_series = unit_states(&[("active", 0.0), ("deactivating", 1.0)]);
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap().1,
+            3
+        );
+
+        let time_series = unit_states(&[("active", 1.0), ("failed", 0.0)]);
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[OK] PromQL query returned 2 result(s), label 'state' is in an OK state"
+                    .to_string(),
+                0,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_process_time_series_with_state_from_label() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Alerts".to_string());
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        mapping.state_from_label = Some(StateFromLabel {
+            label: "severity".to_string(),
+            states: HashMap::from([
+                ("info".to_string(), 0),
+                ("warning".to_string(), 1),
+                ("page".to_string(), 2),
+            ]),
+        });
+        let time_series = [
+            (Some("page"), 1.0),
+            (Some("info"), 20.0),
+            (Some("other"), 7.0),
+            (None, 20.0),
+        ]
+        .iter()
+        .map(|(severity, value)| TimeSeries {
+            labels: severity
+                .map(|s| BTreeMap::from([("severity".to_string(), s.to_string())]))
+                .unwrap_or_default(),
+            value: *value,
+            baseline: None,
+        })
+        .collect::<Vec<TimeSeries>>();
+
+        // Unknown label values and missing labels fall back to the thresholds.
+        let data = process_time_series(&mapping, time_series);
+        assert_eq!(
+            data.iter()
+                .map(|d| d.exit_status.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["CRITICAL", "OK", "WARNING", "CRITICAL"]
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_series_filters() {
+        let time_series = seed_labels()
+            .into_iter()
+            .map(|labels| TimeSeries {
+                labels,
+                value: 1.0,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+        let mut mapping = seed_mapping();
+        mapping.performance_data.enabled = false;
+        mapping.include =
+            vec![crate::config::parse_label_matcher(r#"condition=~"Disk.*""#).unwrap()];
+        mapping.exclude = vec![crate::config::parse_label_matcher(r#"node="worker-02""#).unwrap()];
+
+        // Only one time series is left after filtering.
+        assert_eq!(
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (
+                "[UP] PromQL query returned one result (1.00)".to_string(),
+                0,
+                None
+            )
+        );
+
+        // Filtering out all time series is the same as an empty result.
+        mapping.exclude =
+            vec![crate::config::parse_label_matcher(r#"cluster="production""#).unwrap()];
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[DOWN] PromQL query result set is empty".to_string(),
+                1,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_deduplicate_time_series() {
+        let labels = seed_labels();
+        let time_series = [(0, 1.0), (1, 5.0), (0, 3.0), (0, 2.0)]
+            .iter()
+            .map(|(i, value)| TimeSeries {
+                labels: labels[*i].clone(),
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+
+        for (deduplication, expected) in [
+            (Deduplication::Max, vec![3.0, 5.0]),
+            (Deduplication::Min, vec![1.0, 5.0]),
+            (Deduplication::Last, vec![2.0, 5.0]),
+        ] {
+            let result = deduplicate_time_series(deduplication, time_series.clone());
+            assert_eq!(
+                result.iter().map(|ts| &ts.labels).collect::<Vec<_>>(),
+                vec![&labels[0], &labels[1]]
+            );
+            assert_eq!(
+                result.iter().map(|ts| ts.value).collect::<Vec<f64>>(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_derive_time_series() {
+        let labels = seed_labels();
+        let sample = |i: usize, value: f64| TimeSeries {
+            labels: labels[i].clone(),
+            value,
+            baseline: None,
+        };
+        let mut mapping = seed_mapping();
+
+        // Test: The first execution has nothing to derive from.
+        let result = derive_time_series(
+            &mut mapping,
+            Derivation::Rate,
+            vec![sample(0, 100.0), sample(1, 50.0)],
+            1000.0,
+        );
+        assert!(result.is_empty());
+
+        // Test: Rates are computed per second and counter resets are detected.
+        let result = derive_time_series(
+            &mut mapping,
+            Derivation::Rate,
+            vec![sample(0, 160.0), sample(1, 30.0), sample(2, 10.0)],
+            1060.0,
+        );
+        assert_eq!(
+            result.iter().map(|ts| ts.value).collect::<Vec<f64>>(),
+            vec![1.0, 0.5]
+        );
+
+        // Test: Deltas may be negative, new time series are picked up.
+        let result = derive_time_series(
+            &mut mapping,
+            Derivation::Delta,
+            vec![sample(0, 150.0), sample(2, 15.0)],
+            1120.0,
+        );
+        assert_eq!(
+            result.iter().map(|ts| ts.value).collect::<Vec<f64>>(),
+            vec![-10.0, 5.0]
+        );
+    }
+
+    #[test]
+    fn test_drop_unchanged_time_series() {
+        let labels = seed_labels();
+        let sample = |i: usize, value: f64| TimeSeries {
+            labels: labels[i].clone(),
+            value,
+            baseline: None,
+        };
+        let mut mapping = seed_mapping();
+
+        // Test: All time series are kept in the first execution.
+        let result = drop_unchanged_time_series(&mut mapping, vec![sample(0, 5.0), sample(1, 7.0)]);
+        assert_eq!(result.len(), 2);
+
+        // Test: Unchanged time series are dropped, new ones are kept.
+        let result = drop_unchanged_time_series(
+            &mut mapping,
+            vec![sample(0, 5.0), sample(1, 8.0), sample(2, 1.0)],
+        );
+        assert_eq!(
+            result.iter().map(|ts| ts.value).collect::<Vec<f64>>(),
+            vec![8.0, 1.0]
+        );
+
+        // Test: The query result is OK when nothing changed at all in the
+        // first execution.
+        mapping.ignore_unchanged = true;
+        mapping.service = Some("Event counters".to_string());
+        assert_eq!(
+            process_query_result(
+                &mut mapping,
+                vec![sample(0, 5.0), sample(1, 8.0), sample(2, 1.0)]
+            )
+            .unwrap(),
+            (
+                "[OK] No time series changed since the previous execution".to_string(),
+                0,
+                None
+            )
+        );
+
+        // Test: The previous state is kept when nothing changed.
+        mapping.state.last_exit_value = Some(2);
+        assert_eq!(
+            process_query_result(
+                &mut mapping,
+                vec![sample(0, 5.0), sample(1, 8.0), sample(2, 1.0)]
+            )
+            .unwrap(),
+            (
+                "[CRITICAL] No time series changed since the previous execution".to_string(),
+                2,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_derive_unchanged_time_series() {
+        let labels = seed_labels();
+        let sample = |i: usize, value: f64| TimeSeries {
+            labels: labels[i].clone(),
+            value,
+            baseline: None,
+        };
+        let mut mapping = seed_mapping();
+        mapping.ignore_unchanged = true;
+        mapping.derive = Some(Derivation::Delta);
+
+        process_query_result(&mut mapping, vec![sample(0, 100.0), sample(1, 10.0)]).unwrap();
+
+        // Test: The samples of unchanged time series are recorded as well.
+        process_query_result(&mut mapping, vec![sample(0, 100.0), sample(1, 20.0)]).unwrap();
+        assert_eq!(mapping.state.samples.len(), 2);
+
+        // Test: Nothing changed at all, the samples are still recorded.
+        let (output, _, _) =
+            process_query_result(&mut mapping, vec![sample(0, 100.0), sample(1, 20.0)]).unwrap();
+        assert!(output.contains("No time series changed"));
+        assert_eq!(mapping.state.samples.len(), 2);
+
+        // Test: The delta of a time series that was unchanged before refers
+        // to the previous execution.
+        let (output, _, _) =
+            process_query_result(&mut mapping, vec![sample(0, 130.0), sample(1, 20.0)]).unwrap();
+        assert!(!output.contains("Waiting"));
+        assert_eq!(
+            mapping
+                .state
+                .samples
+                .get(&labels[0])
+                .map(|(value, _)| *value),
+            Some(130.0)
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_top_offenders() {
+        let time_series = [1.0, 12.0, 7.0, 20.0]
+            .iter()
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Node status".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        mapping.default_output.top = 2;
+
+        let (output, exit_value, _) = process_query_result(&mut mapping, time_series).unwrap();
+        assert_eq!(exit_value, 2);
+        assert_eq!(
+            output,
+            "[CRITICAL] PromQL query returned multiple results within the critical range (values 1.00..=20.00 overlap with 0:10); top offenders: {cluster=production, condition=MemoryPressure, namespace=monitoring, node=worker-02, status=true} 20.00 (CRITICAL), {cluster=production, condition=DiskPressure, namespace=monitoring, node=worker-02, status=true} 12.00 (CRITICAL)"
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_long_output() {
+        let time_series = [1.0, 12.0, 7.0]
+            .iter()
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
+                value: *value,
+                baseline: None,
+            })
+            .collect::<Vec<TimeSeries>>();
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Node status".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        mapping.default_output.long = true;
+        mapping.default_output.labels = vec!["node".to_string(), "condition".to_string()];
+
+        let (output, exit_value, _) = process_query_result(&mut mapping, time_series).unwrap();
+        assert_eq!(exit_value, 2);
+        assert_eq!(
+            output,
+            "[CRITICAL] PromQL query returned multiple results within the critical range (values 1.00..=12.00 overlap with 0:10)\n\
+             [CRITICAL] {condition=DiskPressure, node=worker-02} 12.00 in critical range 0:10\n\
+             [WARNING] {condition=MemoryPressure, node=worker-01} 7.00 in warning range 0:5"
+        );
+
+        // Annotations apply to the first line.
+        let (output, _) = apply_flap_detection(
+            &mut Mapping {
+                flapping: Some(FlapDetection {
+                    window: 10,
+                    max_changes: 0,
+                }),
+                state: MappingState {
+                    stable_exit_value: Some(0),
+                    history: std::collections::VecDeque::from([0]),
+                    ..Default::default()
+                },
+                ..mapping
+            },
+            output,
+            2,
+        );
+        assert!(output.starts_with(
+            "[CRITICAL] PromQL query returned multiple results within the critical range (values 1.00..=12.00 overlap with 0:10) (flapping suppressed, holding last stable state OK)\n"
+        ));
+    }
+
+    #[test]
+    fn test_process_query_result_with_expectation() {
+        let replicas = |values: &[f64]| {
+            values
+                .iter()
+                .zip(seed_labels())
+                .map(|(value, labels)| TimeSeries {
+                    labels,
+                    value: *value,
+                    baseline: None,
+                })
+                .collect::<Vec<TimeSeries>>()
+        };
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Replicas".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.expect = Some(Expectation {
+            values: vec![3.0],
+            exit_value: 2,
+        });
+
+        assert_eq!(
+            process_query_result(&mut mapping, replicas(&[3.0])).unwrap(),
+            (
+                "[OK] PromQL query returned one result (3.00) that equals the expected value 3.00"
+                    .to_string(),
+                0,
+                None
+            )
+        );
+        assert_eq!(
+            process_query_result(&mut mapping, replicas(&[2.0])).unwrap(),
+            (
+                "[CRITICAL] PromQL query returned one result (2.00) that is below the expected value 3.00"
+                    .to_string(),
+                2,
+                None
+            )
+        );
+
+        mapping.expect = Some(Expectation {
+            values: vec![3.0, 5.0],
+            exit_value: 1,
+        });
+        assert_eq!(
+            process_query_result(&mut mapping, replicas(&[3.0, 4.0, 6.0, 5.0])).unwrap(),
+            (
+                "[WARNING] PromQL query returned 4 results, 2 of which do not equal one of the expected values 3.00, 5.00 (0 below, 1 above)"
+                    .to_string(),
+                1,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_process_query_result_with_conditions() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Disk".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("80").unwrap()),
+            critical: Some(NagiosRange::from("90").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let prediction = Condition {
+            name: "prediction".to_string(),
+            query: "predict_linear(node_filesystem_avail_bytes[1h], 4 * 3600)".to_string(),
+            thresholds: ThresholdPair {
+                warning: None,
+                critical: Some(NagiosRange::from("@~:0").unwrap()),
+                unknown: None,
+                ok: None,
+            },
+        };
+        mapping.conditions = Some(Conditions {
+            operator: BoolOp::And,
+            items: vec![prediction.clone()],
+        });
+        let time_series = vec![TimeSeries {
+            labels: seed_labels()[0].clone(),
+            value: 95.0,
+            baseline: None,
+        }];
 
-        // Test: Default output, multiple time series, OK, service object, no performance data.
-        mapping.performance_data.enabled = false;
-        let expected_output =
-            "[OK] PromQL query returned multiple results in the range 0.00..=5.12".to_string();
+        // Test: The disk is full, but the prediction is fine.
+        mapping.state.conditions = vec![evaluate_condition(
+            &mapping,
+            &prediction,
+            vec![1024.0],
+            true,
+        )];
+        assert_eq!(mapping.state.conditions[0].exit_status, "OK");
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output.clone(), 0, None)
+            process_query_result(&mut mapping, time_series.clone()).unwrap(),
+            (
+                "[OK] PromQL query returned one result within the critical range (95.00 in 0:90); conditions (and): prediction=OK"
+                    .to_string(),
+                0,
+                None
+            )
         );
 
-        // Test: Default output, multiple time series, OK, service object, with performance data.
-        mapping.performance_data.enabled = true;
-        mapping.thresholds.warning = Some(NagiosRange::from("@10:").unwrap());
-        let perfdata = values
-            .iter()
-            .zip(checksums.iter())
-            .map(|(value, checksum)| format!("'{}/{}'={};@10:~;;;", mapping.name, checksum, value))
-            .collect::<Vec<String>>();
+        // Test: Both conditions are met.
+        mapping.state.conditions =
+            vec![evaluate_condition(&mapping, &prediction, vec![-1.0], true)];
         assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
-            (expected_output, 0, Some(perfdata))
+            process_query_result(&mut mapping, time_series.clone())
+                .unwrap()
+                .1,
+            2
         );
 
-        // Test: Default output, single time series, OK, service object, with performance data.
-        let time_series = vec![TimeSeries {
-            labels: label_set[0].clone(),
-            value: 9.21837821321,
-        }];
-        mapping.thresholds.critical = Some(NagiosRange::from("10").unwrap());
-        let expected_output = "[OK] PromQL query returned one result (9.22)".to_string();
-        let perfdata = vec![format!(
-            "'{}/{}'={};@10:~;0:10;;",
-            mapping.name,
-            checksums[0].clone(),
-            9.21837821321
-        )];
+        // Test: Either condition raises the state.
+        mapping.conditions.as_mut().unwrap().operator = BoolOp::Or;
+        mapping.thresholds.critical = None;
+        mapping.thresholds.warning = None;
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output, 0, Some(perfdata))
+            process_query_result(&mut mapping, time_series).unwrap().1,
+            2
         );
     }
 
     #[test]
-    fn test_process_query_result_for_host_objects_with_warning() {
-        let label_set = seed_labels();
-        let checksums = seed_checksums();
-        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 0.0, 0.0];
-        let time_series = label_set
+    fn test_process_time_series_with_threshold_templates() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Queue length".to_string());
+        mapping.thresholds.warning = Some(NagiosRange::from("5").unwrap());
+        mapping.threshold_templates = ThresholdTemplates {
+            warning: None,
+            critical: Some("{{ labels.capacity }}".to_string()),
+        };
+        let time_series = [(Some("10"), 12.0), (Some("100"), 12.0), (None, 12.0)]
             .iter()
-            .zip(values.iter())
-            .map(|(labels, value)| TimeSeries {
-                labels: labels.clone(),
+            .map(|(capacity, value)| TimeSeries {
+                labels: capacity
+                    .map(|c| BTreeMap::from([("capacity".to_string(), c.to_string())]))
+                    .unwrap_or_default(),
                 value: *value,
+                baseline: None,
             })
             .collect::<Vec<TimeSeries>>();
 
+        // The critical range is rendered per time series, the warning range
+        // applies to all of them.
+        let data = process_time_series(&mapping, time_series);
+        assert_eq!(
+            data.iter()
+                .map(|d| d.exit_status.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["CRITICAL", "WARNING", "UNKNOWN"]
+        );
+        assert_eq!(
+            mapping
+                .thresholds_for(&data[0].labels)
+                .critical
+                .as_ref()
+                .map(|c| c.to_string()),
+            Some("0:10".to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_grace_period() {
+        let now = Instant::now();
         let mut mapping = seed_mapping();
-        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
-        mapping.thresholds.critical = Some(NagiosRange::from("20").unwrap());
-        mapping.performance_data.uom = Some("ms".to_string());
+        mapping.service = Some("Disk".to_string());
+        mapping.grace_period = Some(GracePeriod {
+            until: now + Duration::from_secs(60),
+            action: GraceAction::Unknown,
+        });
 
-        // Test: Default output, multiple time series, WARNING, host object, with performance data.
-        let expected_output = "[UP] PromQL query returned multiple results within the warning range (values 0.00..=5.12 overlap with @0:10)".to_string();
-        let perfdata = values
-            .iter()
-            .zip(checksums.iter())
-            .map(|(value, checksum)| {
-                format!("'{}/{}'={}ms;@0:10;0:20;;", mapping.name, checksum, value)
-            })
-            .collect::<Vec<String>>();
+        // OK results are always submitted.
         assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
-            (expected_output, 0, Some(perfdata))
+            apply_grace_period(&mapping, "[OK] fine".to_string(), 0, now),
+            Some(("[OK] fine".to_string(), 0))
+        );
+        assert_eq!(
+            apply_grace_period(&mapping, "[CRITICAL] full".to_string(), 2, now),
+            Some((
+                "[UNKNOWN] Startup grace period, actual state CRITICAL; [CRITICAL] full"
+                    .to_string(),
+                3
+            ))
+        );
+        // The grace period is over.
+        assert_eq!(
+            apply_grace_period(
+                &mapping,
+                "[CRITICAL] full".to_string(),
+                2,
+                now + Duration::from_secs(61)
+            ),
+            Some(("[CRITICAL] full".to_string(), 2))
         );
 
-        // Test: Default output, single time series, WARNING, host object, with performance data.
-        let time_series = vec![TimeSeries {
-            labels: label_set[0].clone(),
-            value: 9.34534534,
-        }];
-        let expected_output =
-            "[UP] PromQL query returned one result within the warning range (9.35 in @0:10)"
-                .to_string();
-        let perfdata = vec![format!(
-            "'{}/{}'={}ms;@0:10;0:20;;",
-            mapping.name,
-            checksums[0].clone(),
-            9.34534534
-        )];
+        // Host objects have no UNKNOWN state.
+        mapping.service = None;
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output, 0, Some(perfdata))
+            apply_grace_period(&mapping, "[DOWN] full".to_string(), 1, now),
+            None
         );
     }
 
     #[test]
-    fn test_process_query_result_for_service_objects_with_warning() {
-        let label_set = seed_labels();
-        let checksums = seed_checksums();
-        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 0.0, 0.0];
-        let time_series = label_set
-            .iter()
-            .zip(values.iter())
-            .map(|(labels, value)| TimeSeries {
-                labels: labels.clone(),
-                value: *value,
-            })
-            .collect::<Vec<TimeSeries>>();
-
+    fn test_apply_escalation() {
         let mut mapping = seed_mapping();
-        mapping.service = Some("bar".to_string());
-        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
-        mapping.thresholds.critical = Some(NagiosRange::from("20").unwrap());
+        mapping.service = Some("Certificates".to_string());
+        mapping.escalate_after = Some(3);
 
-        // Test: Default output, multiple time series, WARNING, service object, with performance data.
-        let expected_output = "[WARNING] PromQL query returned multiple results within the warning range (values 0.00..=5.12 overlap with @0:10)".to_string();
-        let perfdata = values
-            .iter()
-            .zip(checksums.iter())
-            .map(|(value, checksum)| {
-                format!("'{}/{}'={};@0:10;0:20;;", mapping.name, checksum, value)
-            })
-            .collect::<Vec<String>>();
+        let mut escalate = |exit_value: u8| {
+            apply_escalation(
+                &mut mapping,
+                "[WARNING] expires soon".to_string(),
+                exit_value,
+            )
+        };
+
+        assert_eq!(escalate(1).1, 1);
+        assert_eq!(escalate(1).1, 1);
         assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
-            (expected_output, 1, Some(perfdata))
+            escalate(1),
+            (
+                "[CRITICAL] expires soon (escalated to CRITICAL after 3 cycles in WARNING)"
+                    .to_string(),
+                2
+            )
         );
+        assert_eq!(escalate(1).1, 2);
 
-        // Test: Default output, single time series, WARNING, service object, with performance data.
-        let time_series = vec![TimeSeries {
-            labels: label_set[0].clone(),
-            value: 9.34534534,
-        }];
-        let expected_output =
-            "[WARNING] PromQL query returned one result within the warning range (9.35 in @0:10)"
-                .to_string();
-        let perfdata = vec![format!(
-            "'{}/{}'={};@0:10;0:20;;",
-            mapping.name,
-            checksums[0].clone(),
-            9.34534534
-        )];
+        // Any other state resets the counter.
+        assert_eq!(escalate(0).1, 0);
+        assert_eq!(escalate(1).1, 1);
+
+        // Test: An output without a leading status is only annotated.
+        mapping.state.warning_cycles = 2;
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output, 1, Some(perfdata))
+            apply_escalation(&mut mapping, "expires soon\ncert.pem".to_string(), 1),
+            (
+                "expires soon (escalated to CRITICAL after 3 cycles in WARNING)\ncert.pem"
+                    .to_string(),
+                2
+            )
         );
     }
 
     #[test]
-    fn test_process_query_result_for_host_objects_with_critical() {
-        let label_set = seed_labels();
-        let checksums = seed_checksums();
-        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 25.4534534, 0.0];
-        let time_series = label_set
+    fn test_process_query_result_with_count_breaching() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Load".to_string());
+        mapping.performance_data.enabled = false;
+        // Count the nodes with a load above 10.
+        mapping.count_breaching = Some(NagiosRange::from("10").unwrap());
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("1").unwrap()),
+            critical: Some(NagiosRange::from("2").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let time_series = [12.0, 3.0, 15.0, 10.0]
             .iter()
-            .zip(values.iter())
-            .map(|(labels, value)| TimeSeries {
-                labels: labels.clone(),
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
                 value: *value,
+                baseline: None,
             })
             .collect::<Vec<TimeSeries>>();
 
-        let mut mapping = seed_mapping();
-        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
-        mapping.thresholds.critical = Some(NagiosRange::from("@20:30").unwrap());
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[WARNING] PromQL query returned one result within the warning range (2.00 in 0:1)"
+                    .to_string(),
+                1,
+                None
+            )
+        );
+    }
 
-        // Test: Default output, multiple time series, CRITICAL, host object, with performance data.
-        let expected_output = "[DOWN] PromQL query returned multiple results within the critical range (values 0.00..=25.45 overlap with @20:30)".to_string();
-        let perfdata = values
+    #[test]
+    fn test_process_query_result_with_summary_performance_data() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Node load".to_string());
+        mapping.performance_data.summary = true;
+        mapping.performance_data.max_items = Some(1);
+        mapping.state.timing.query = Some(Duration::from_millis(231));
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let time_series = [1.0, 12.0, 7.0, 3.0]
             .iter()
-            .zip(checksums.iter())
-            .map(|(value, checksum)| {
-                format!("'{}/{}'={};@0:10;@20:30;;", mapping.name, checksum, value)
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
+                value: *value,
+                baseline: None,
             })
-            .collect::<Vec<String>>();
-        assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
-            (expected_output, 1, Some(perfdata))
-        );
+            .collect::<Vec<TimeSeries>>();
 
-        // Test: Default output, single time series, CRITICAL, host object, with performance data.
-        mapping.thresholds.warning = None;
-        let time_series = vec![TimeSeries {
-            labels: label_set[0].clone(),
-            value: 28.34534534,
-        }];
-        let expected_output =
-            "[DOWN] PromQL query returned one result within the critical range (28.35 in @20:30)"
-                .to_string();
-        let perfdata = vec![format!(
-            "'{}/{}'={};;@20:30;;",
-            mapping.name,
-            checksums[0].clone(),
-            28.34534534
-        )];
+        let (_, exit_value, performance_data) =
+            process_query_result(&mut mapping, time_series).unwrap();
+        assert_eq!(exit_value, 2);
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output, 1, Some(perfdata))
+            performance_data.unwrap()[1..],
+            [
+                "'truncated'=3;;;;",
+                "'num_results'=4;;;;",
+                "'num_ok'=2;;;;",
+                "'num_warning'=1;;;;",
+                "'num_critical'=1;;;;",
+                "'num_unknown'=0;;;;",
+                "'query_time'=0.231s;;;;",
+            ]
         );
     }
 
     #[test]
-    fn test_process_query_result_for_service_objects_with_critical() {
-        let label_set = seed_labels();
-        let checksums = seed_checksums();
-        let values = [0.0, 0.0, 0.0, 5.1238712, 0.0, 0.0, 25.4534534, 0.0];
-        let time_series = label_set
+    fn test_process_query_result_with_group_by() {
+        let mut mapping = seed_mapping();
+        mapping.service = Some("Node conditions".to_string());
+        mapping.performance_data.enabled = false;
+        mapping.aggregate = Some(Aggregation::Max);
+        mapping.group_by = vec!["node".to_string()];
+        mapping.thresholds = ThresholdPair {
+            warning: Some(NagiosRange::from("5").unwrap()),
+            critical: Some(NagiosRange::from("10").unwrap()),
+            unknown: None,
+            ok: None,
+        };
+        let time_series = [1.0, 12.0, 7.0, 3.0]
             .iter()
-            .zip(values.iter())
-            .map(|(labels, value)| TimeSeries {
-                labels: labels.clone(),
+            .zip(seed_labels())
+            .map(|(value, labels)| TimeSeries {
+                labels,
                 value: *value,
+                baseline: None,
             })
             .collect::<Vec<TimeSeries>>();
 
+        // Every group is aggregated, checked and listed separately.
+        assert_eq!(
+            process_query_result(&mut mapping, time_series).unwrap(),
+            (
+                "[CRITICAL] PromQL query returned multiple results within the critical range (values 7.00..=12.00 overlap with 0:10)\n\
+                 [WARNING] {node=worker-01} 7.00 in warning range 0:5\n\
+                 [CRITICAL] {node=worker-02} 12.00 in critical range 0:10"
+                    .to_string(),
+                2,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_validate_templates() {
         let mut mapping = seed_mapping();
-        mapping.service = Some("bar".to_string());
-        mapping.thresholds.warning = Some(NagiosRange::from("@10").unwrap());
-        mapping.thresholds.critical = Some(NagiosRange::from("@20:30").unwrap());
+        assert!(validate_templates(&mapping).is_ok());
 
-        // Test: Default output, multiple time series, CRITICAL, service object, with performance data.
-        let expected_output = "[CRITICAL] PromQL query returned multiple results within the critical range (values 0.00..=25.45 overlap with @20:30)".to_string();
-        let perfdata = values
-            .iter()
-            .zip(checksums.iter())
-            .map(|(value, checksum)| {
-                format!("'{}/{}'={};@0:10;@20:30;;", mapping.name, checksum, value)
+        mapping.plugin_output = Some(
+            "[{{ exit_status }}] {{#each data}}{{ upper this.labels.node }}: {{ sub this.labels.capacity this.value }}{{/each}}"
+                .to_string(),
+        );
+        mapping.performance_data.label = Some("{{ labels.node }}".to_string());
+        assert!(validate_templates(&mapping).is_ok());
+
+        // Misspelled variable.
+        mapping.plugin_output = Some("[{{ exit_stauts }}]".to_string());
+        assert!(validate_templates(&mapping).is_err());
+
+        // Wrong helper parameters.
+        mapping.plugin_output = Some("{{ humanize_bytes name }}".to_string());
+        assert!(validate_templates(&mapping).is_err());
+
+        // Unknown partial.
+        mapping.plugin_output = Some("{{> header }}".to_string());
+        assert!(validate_templates(&mapping).is_err());
+        mapping.partials = std::sync::Arc::new(BTreeMap::from([(
+            "header".to_string(),
+            "[{{ exit_status }}]".to_string(),
+        )]));
+        assert!(validate_templates(&mapping).is_ok());
+
+        // Templates that are only rendered for empty query results.
+        mapping.no_data.plugin_output = Some("{{ data.0.value }}".to_string());
+        assert!(validate_templates(&mapping).is_err());
+    }
+
+    #[test]
+    fn test_plugin_output_strict_mode() {
+        let mut mapping = seed_mapping();
+        let template = "{{#each data}}[{{ this.labels.node }}]{{/each}}";
+        let data = vec![Data::from(
+            false,
+            TimeSeries {
+                labels: BTreeMap::new(),
+                value: 1.0,
+                baseline: None,
+            },
+            0,
+            0,
+            "UP".to_string(),
+        )];
+
+        let render = |mapping: &Mapping| {
+            icinga::plugin_output::format_from_template(
+                template,
+                mapping,
+                data.clone(),
+                0,
+                "UP".to_string(),
+            )
+        };
+        assert!(render(&mapping).is_err());
+
+        mapping.plugin_output_strict = false;
+        assert_eq!(render(&mapping).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_plugin_output_from_jinja_template() {
+        let mut mapping = seed_mapping();
+        mapping.template_engine = TemplateEngine::Jinja;
+        let data: Vec<Data> = [("a", 5.0), ("b", 2.5)]
+            .into_iter()
+            .map(|(node, value)| {
+                Data::from(
+                    false,
+                    TimeSeries {
+                        labels: BTreeMap::from([("node".to_string(), node.to_string())]),
+                        value,
+                        baseline: None,
+                    },
+                    0,
+                    0,
+                    "UP".to_string(),
+                )
             })
-            .collect::<Vec<String>>();
+            .collect();
+
+        let render = |mapping: &Mapping, template: &str| {
+            icinga::plugin_output::format_from_template(
+                template,
+                mapping,
+                data.clone(),
+                0,
+                "UP".to_string(),
+            )
+        };
         assert_eq!(
-            process_query_result(&mapping, time_series).unwrap(),
-            (expected_output, 2, Some(perfdata))
+            render(
+                &mapping,
+                "[{{ exit_status }}] {{ num_results }} nodes{% for d in data %}, {{ d.labels.node | upper }}={{ d.value }}{% endfor %}"
+            )
+            .unwrap(),
+            "[UP] 2 nodes, A=5.0, B=2.5"
+        );
+        assert_eq!(
+            render(&mapping, "{% if is_ok %}ok{% else %}host{% endif %}").unwrap(),
+            "host"
         );
+        assert!(render(&mapping, "{{ data[0].labels.instance }}").is_err());
+        assert!(render(&mapping, "{% if %}").is_err());
 
-        // Test: Default output, single time series, CRITICAL, service object, with performance data.
-        mapping.thresholds.warning = None;
-        let time_series = vec![TimeSeries {
-            labels: label_set[0].clone(),
-            value: 28.34534534,
-        }];
-        let expected_output =
-            "[CRITICAL] PromQL query returned one result within the critical range (28.35 in @20:30)"
-                .to_string();
-        let perfdata = vec![format!(
-            "'{}/{}'={};;@20:30;;",
-            mapping.name,
-            checksums[0].clone(),
-            28.34534534
-        )];
+        mapping.plugin_output_strict = false;
         assert_eq!(
-            process_query_result(&mapping, time_series.clone()).unwrap(),
-            (expected_output, 2, Some(perfdata))
+            render(&mapping, "[{{ data[0].labels.instance }}]").unwrap(),
+            "[]"
         );
     }
 }